@@ -0,0 +1,126 @@
+//! Operator endpoints under `/admin`, mounted only when `ADMIN_API_TOKEN` is
+//! set. Every handler takes `AdminAuth`, which checks the bearer token.
+
+use std::sync::{Arc, OnceLock};
+
+use axum::{
+    Json,
+    extract::{FromRequestParts, Path, State},
+    http::{StatusCode, request::Parts},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::client_request::bearer_token;
+use crate::db::DeadLetterReplay;
+use crate::ingest::RouterState;
+use crate::url_utils::{extract_domain_and_path_from_url, extract_url_parts};
+use crate::url_utils::path_rules::PathRewriter;
+use crate::utils::constant_time_eq;
+
+static ADMIN_TOKEN: OnceLock<String> = OnceLock::new();
+
+pub fn initialize(token: String) {
+    if ADMIN_TOKEN.set(token).is_err() {
+        tracing::warn!("Admin token already initialized, ignoring");
+    }
+}
+
+/// Proof that the request carried the admin bearer token
+pub struct AdminAuth;
+
+impl<S: Send + Sync> FromRequestParts<S> for AdminAuth {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let expected = ADMIN_TOKEN.get().map(String::as_bytes);
+        match (expected, bearer_token(&parts.headers)) {
+            (Some(expected), Some(token)) if constant_time_eq(expected, token.as_bytes()) => Ok(AdminAuth),
+            _ => Err((StatusCode::UNAUTHORIZED, "Invalid admin token")),
+        }
+    }
+}
+
+/// Re-inserts the dead-letter spool into ClickHouse, e.g. after a schema fix
+pub async fn replay_dead_letters(
+    _auth: AdminAuth,
+    State((db, _processor, _metrics, _validator, _s3, _site_cfg_cache)): State<RouterState>,
+) -> Result<Json<DeadLetterReplay>, (StatusCode, String)> {
+    let spool = db.dead_letter().ok_or((
+        StatusCode::NOT_FOUND,
+        "Dead-letter spool is not configured (set DEAD_LETTER_DIR)".to_string(),
+    ))?;
+    db.replay_dead_letters(spool)
+        .await
+        .map(Json)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PathRulesPreviewRequest {
+    /// Full URLs or bare paths
+    pub urls: Vec<String>,
+    /// Draft rules to try instead of the saved ones; same shape as `SiteConfig.pathRules`
+    #[serde(default)]
+    pub path_rules: Option<Value>,
+    #[serde(default)]
+    pub path_id_detectors: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PathRulesPreview {
+    pub results: Vec<PathRewritePreview>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PathRewritePreview {
+    pub url: String,
+    pub path: String,
+    pub normalized: String,
+}
+
+/// Dry run of a site's path rewrite rules: shows the path each URL would be
+/// stored under, with the saved rules or with draft rules from the request.
+/// The site's saved hash routing and query parameter options always apply.
+pub async fn preview_path_rules(
+    _auth: AdminAuth,
+    State((_db, _processor, _metrics, _validator, _s3, site_cfg_cache)): State<RouterState>,
+    Path(site_id): Path<String>,
+    Json(request): Json<PathRulesPreviewRequest>,
+) -> Result<Json<PathRulesPreview>, (StatusCode, String)> {
+    let cfg = site_cfg_cache
+        .get(&site_id)
+        .ok_or((StatusCode::NOT_FOUND, format!("Unknown site '{site_id}'")))?;
+
+    let rewriter = if request.path_rules.is_some() || request.path_id_detectors.is_some() {
+        let rules = request.path_rules.unwrap_or(Value::Null);
+        let detectors = request.path_id_detectors.unwrap_or_default();
+        // Draft rules are refused outright, unlike saved ones, so the caller sees every problem
+        let (rewriter, errors) = PathRewriter::parse(&rules, &detectors, false);
+        if !errors.is_empty() {
+            return Err((StatusCode::BAD_REQUEST, errors.join("; ")));
+        }
+        Arc::new(rewriter)
+    } else {
+        cfg.path_rewriter.clone().unwrap_or_default()
+    };
+
+    let results = request
+        .urls
+        .into_iter()
+        .map(|url| {
+            let (path, query) = match &cfg.path_options {
+                Some(options) => {
+                    let (_, path, query) = extract_url_parts(&url, options);
+                    (path, query)
+                }
+                None => (extract_domain_and_path_from_url(&url).1, String::new()),
+            };
+            let normalized = rewriter.rewrite(&path) + &query;
+            let path = path + &query;
+            PathRewritePreview { url, path, normalized }
+        })
+        .collect();
+    Ok(Json(PathRulesPreview { results }))
+}
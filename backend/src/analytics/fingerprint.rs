@@ -11,6 +11,8 @@ pub struct VisitorAttrs<'a> {
     pub browser_version: Option<&'a str>,
     pub os: Option<&'a str>,
     pub root_domain: Option<&'a str>,
+    /// Visitor named by an authenticated sender; replaces the other attributes
+    pub visitor_key: Option<&'a str>,
 }
 
 /// Keyed hash of stable visitor attributes into a u64 fingerprint.
@@ -19,6 +21,14 @@ pub struct VisitorAttrs<'a> {
 /// rotation window passes the salt is discarded, so a stored fingerprint can no longer be
 /// traced back to an IP.
 pub fn generate_fingerprint(salt: &Salt, attrs: &VisitorAttrs) -> u64 {
+    if let Some(key) = attrs.visitor_key {
+        let mut hasher = Sha256::new();
+        hasher.update(salt.as_slice());
+        hasher.update(format!("key:{}", key));
+        let result = hasher.finalize();
+        return u64::from_be_bytes(result[..8].try_into().expect("always produces 32 bytes"));
+    }
+
     let anonymized_ip = anonymize_ip(attrs.ip).unwrap_or_else(|| "unknown".to_string());
     let device_category = attrs.device_type.unwrap_or("unknown").to_lowercase();
     let browser_family = attrs.browser.unwrap_or("unknown").to_lowercase();
@@ -54,6 +64,7 @@ mod tests {
             browser_version: Some("120"),
             os: Some("Windows"),
             root_domain: Some("example.com"),
+            visitor_key: None,
         }
     }
 
@@ -84,4 +95,26 @@ mod tests {
         };
         assert_ne!(base, generate_fingerprint(&salt, &changed));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn visitor_key_replaces_request_attributes() {
+        let salt = [7u8; 16];
+        let keyed = |key| VisitorAttrs {
+            visitor_key: Some(key),
+            ..attrs()
+        };
+        let other_request = |key| VisitorAttrs {
+            ip: "198.51.100.9",
+            browser: Some("python-requests"),
+            ..keyed(key)
+        };
+        assert_eq!(
+            generate_fingerprint(&salt, &keyed("123.456")),
+            generate_fingerprint(&salt, &other_request("123.456"))
+        );
+        assert_ne!(
+            generate_fingerprint(&salt, &keyed("123.456")),
+            generate_fingerprint(&salt, &keyed("789.012"))
+        );
+    }
+}
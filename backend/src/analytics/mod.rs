@@ -1,5 +1,10 @@
 use serde::{Deserialize, Serialize};
 use nanoid::nanoid;
+use std::sync::Arc;
+use crate::campaign::CampaignAliases;
+use crate::url_utils::PathOptions;
+use crate::url_utils::path_rules::PathRewriter;
+use crate::validation::network_policy::NetworkPolicy;
 
 mod fingerprint;
 mod device;
@@ -9,7 +14,8 @@ pub use device::*;
 /// Raw tracking data received from the client
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct RawTrackingEvent {
-    /// Site identifier
+    /// Site identifier; may be left out when sites are resolved by hostname
+    #[serde(default)]
     pub site_id: String,
     /// Name of event
     pub event_name: String,
@@ -65,6 +71,26 @@ pub struct AnalyticsEvent {
     pub sec_ch_ua: String,
     /// Request carried a browser speculative-loading header
     pub prefetch: bool,
+    /// Event time vouched for by an authenticated sender; used instead of the
+    /// receive time. Always None for browser events.
+    pub trusted_timestamp: Option<chrono::DateTime<chrono::Utc>>,
+    /// Custom property schema violations kept by a site in tag mode, as "key:kind"
+    pub property_schema_violations: Vec<String>,
+    /// Site domain, alias or allowed-domain pattern the URL's host matched; empty if none
+    pub matched_domain: String,
+    /// From one of the site's internal networks or carrying its internal traffic token
+    pub is_internal: bool,
+    /// The site's country and ASN rules, checked once the processor has looked them up
+    pub network_policy: Option<Arc<NetworkPolicy>>,
+    /// The site's query parameter aliases for campaign fields
+    pub campaign_aliases: Option<Arc<CampaignAliases>>,
+    /// The site's path rewrite rules
+    pub path_rewriter: Option<Arc<PathRewriter>>,
+    /// The site's hash routing and retained query parameter options
+    pub path_options: Option<Arc<PathOptions>>,
+    /// Visitor named by an authenticated server sender (GA4 `client_id`); used
+    /// as the visitor identity instead of IP and user agent
+    pub visitor_key: Option<String>,
 }
 
 impl AnalyticsEvent {
@@ -81,6 +107,15 @@ impl AnalyticsEvent {
             header_user_agent,
             sec_ch_ua,
             prefetch,
+            trusted_timestamp: None,
+            property_schema_violations: Vec::new(),
+            matched_domain: String::new(),
+            is_internal: false,
+            network_policy: None,
+            campaign_aliases: None,
+            path_rewriter: None,
+            path_options: None,
+            visitor_key: None,
         }
     }
 }
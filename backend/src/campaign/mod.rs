@@ -1,3 +1,6 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
 use url::Url;
 
 /// Campaign tracking information
@@ -8,27 +11,163 @@ pub struct CampaignInfo {
     pub utm_campaign: Option<String>,
     pub utm_term: Option<String>,
     pub utm_content: Option<String>,
+    pub utm_id: Option<String>,
+    pub utm_source_platform: Option<String>,
+    /// Ad network whose click ID the URL carried (e.g. "google_ads"); the ID itself is not kept
+    pub click_id_network: Option<String>,
+}
+
+/// A campaign field a query parameter can fill
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CampaignParam {
+    Source,
+    Medium,
+    Campaign,
+    Term,
+    Content,
+    Id,
+    SourcePlatform,
+}
+
+impl FromStr for CampaignParam {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "utm_source" => Ok(Self::Source),
+            "utm_medium" => Ok(Self::Medium),
+            "utm_campaign" => Ok(Self::Campaign),
+            "utm_term" => Ok(Self::Term),
+            "utm_content" => Ok(Self::Content),
+            "utm_id" => Ok(Self::Id),
+            "utm_source_platform" => Ok(Self::SourcePlatform),
+            other => Err(format!("unknown campaign parameter '{other}'")),
+        }
+    }
+}
+
+/// Per-site query parameter names read as campaign fields, e.g. `cmp` -> `utm_campaign`
+pub type CampaignAliases = HashMap<String, CampaignParam>;
+
+/// Click-ID query parameters and the ad network that sets them
+const CLICK_ID_NETWORKS: [(&str, &str); 8] = [
+    ("gclid", "google_ads"),
+    ("gbraid", "google_ads"),
+    ("wbraid", "google_ads"),
+    ("fbclid", "meta"),
+    ("msclkid", "microsoft_ads"),
+    ("ttclid", "tiktok"),
+    ("li_fat_id", "linkedin"),
+    ("twclid", "x"),
+];
+
+/// Parses `{"<query param>": "<utm field>"}` as stored in the site config.
+/// Shorthands like `ref` or `source` count as `utm_source` only on sites that
+/// alias them, since many apps use those names for their own parameters.
+pub fn parse_campaign_aliases(value: &serde_json::Value) -> Result<CampaignAliases, String> {
+    let Some(map) = value.as_object() else {
+        return if value.is_null() { Ok(CampaignAliases::new()) } else { Err("expected an object".to_string()) };
+    };
+    map.iter()
+        .map(|(alias, target)| {
+            let target = target.as_str().ok_or_else(|| format!("alias '{alias}' must map to a string"))?;
+            Ok((alias.clone(), target.parse()?))
+        })
+        .collect()
 }
 
 /// Parse campaign parameters from a URL
 pub fn parse_campaign_params(url_str: &str) -> CampaignInfo {
+    parse_campaign_params_with_aliases(url_str, &CampaignAliases::new())
+}
+
+/// Like `parse_campaign_params`, also reading the site's alias parameters.
+/// Explicit `utm_*` parameters win over aliases.
+pub fn parse_campaign_params_with_aliases(url_str: &str, aliases: &CampaignAliases) -> CampaignInfo {
     let url = match Url::parse(url_str) {
         Ok(url) => url,
         Err(_) => return CampaignInfo::default(),
     };
-    
+
     let mut campaign_info = CampaignInfo::default();
-    
+    let mut aliased = CampaignInfo::default();
+
     for (key, value) in url.query_pairs() {
-        match key.as_ref() {
-            "utm_source" => campaign_info.utm_source = Some(value.to_string()),
-            "utm_medium" => campaign_info.utm_medium = Some(value.to_string()),
-            "utm_campaign" => campaign_info.utm_campaign = Some(value.to_string()),
-            "utm_term" => campaign_info.utm_term = Some(value.to_string()),
-            "utm_content" => campaign_info.utm_content = Some(value.to_string()),
-            _ => {}
+        let value = value.to_string();
+        if let Ok(param) = key.parse::<CampaignParam>() {
+            set_field(&mut campaign_info, param, value);
+        } else if let Some(param) = aliases.get(key.as_ref()) {
+            set_field(&mut aliased, *param, value);
+        } else if let Some((_, network)) = CLICK_ID_NETWORKS.iter().find(|(param, _)| *param == key)
+            && campaign_info.click_id_network.is_none()
+            && !value.is_empty()
+        {
+            campaign_info.click_id_network = Some(network.to_string());
         }
     }
-    
+
+    campaign_info.utm_source = campaign_info.utm_source.or(aliased.utm_source);
+    campaign_info.utm_medium = campaign_info.utm_medium.or(aliased.utm_medium);
+    campaign_info.utm_campaign = campaign_info.utm_campaign.or(aliased.utm_campaign);
+    campaign_info.utm_term = campaign_info.utm_term.or(aliased.utm_term);
+    campaign_info.utm_content = campaign_info.utm_content.or(aliased.utm_content);
+    campaign_info.utm_id = campaign_info.utm_id.or(aliased.utm_id);
+    campaign_info.utm_source_platform = campaign_info.utm_source_platform.or(aliased.utm_source_platform);
+
     campaign_info
-}
\ No newline at end of file
+}
+
+fn set_field(info: &mut CampaignInfo, param: CampaignParam, value: String) {
+    let field = match param {
+        CampaignParam::Source => &mut info.utm_source,
+        CampaignParam::Medium => &mut info.utm_medium,
+        CampaignParam::Campaign => &mut info.utm_campaign,
+        CampaignParam::Term => &mut info.utm_term,
+        CampaignParam::Content => &mut info.utm_content,
+        CampaignParam::Id => &mut info.utm_id,
+        CampaignParam::SourcePlatform => &mut info.utm_source_platform,
+    };
+    *field = Some(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn click_ids_record_the_network_only() {
+        let info = parse_campaign_params("https://example.com/?gclid=abc123&utm_id=42&utm_source_platform=Google%20Ads");
+        assert_eq!(info.click_id_network.as_deref(), Some("google_ads"));
+        assert_eq!(info.utm_id.as_deref(), Some("42"));
+        assert_eq!(info.utm_source_platform.as_deref(), Some("Google Ads"));
+
+        let info = parse_campaign_params("https://example.com/?fbclid=xyz");
+        assert_eq!(info.click_id_network.as_deref(), Some("meta"));
+        assert_eq!(info.utm_source, None);
+    }
+
+    #[test]
+    fn ref_and_source_count_only_when_aliased() {
+        assert_eq!(parse_campaign_params("https://example.com/?ref=producthunt&source=header_cta").utm_source, None);
+
+        let aliases = parse_campaign_aliases(&json!({ "ref": "utm_source" })).unwrap();
+        let source = |url: &str| parse_campaign_params_with_aliases(url, &aliases).utm_source;
+        assert_eq!(source("https://example.com/?ref=producthunt").as_deref(), Some("producthunt"));
+        assert_eq!(source("https://example.com/?ref=producthunt&utm_source=newsletter").as_deref(), Some("newsletter"));
+    }
+
+    #[test]
+    fn site_aliases_map_onto_campaign_fields() {
+        let aliases = parse_campaign_aliases(&json!({ "cmp": "utm_campaign", "ref": "utm_medium" })).unwrap();
+        let info = parse_campaign_params_with_aliases("https://example.com/?cmp=spring&ref=partner", &aliases);
+        assert_eq!(info.utm_campaign.as_deref(), Some("spring"));
+        assert_eq!(info.utm_medium.as_deref(), Some("partner"));
+        assert_eq!(info.utm_source, None);
+
+        let info = parse_campaign_params_with_aliases("https://example.com/?cmp=spring&utm_campaign=summer", &aliases);
+        assert_eq!(info.utm_campaign.as_deref(), Some("summer"));
+
+        assert!(parse_campaign_aliases(&json!({ "cmp": "campaign" })).is_err());
+    }
+}
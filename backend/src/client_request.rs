@@ -4,28 +4,32 @@ use axum::http::request::Parts;
 use std::convert::Infallible;
 use std::net::SocketAddr;
 
-/// Client identity of a tracking request, extracted once per handler: proxy-aware
-/// IP, User-Agent header, and browser speculative-loading (prefetch) flag.
+const INTERNAL_HEADER: &str = "x-betterlytics-internal";
+const INTERNAL_COOKIE: &str = "betterlytics_internal";
+
+/// Client identity of a tracking request, extracted once per handler: IP (from
+/// forwarding headers only when the peer is a trusted proxy), User-Agent header,
+/// and browser speculative-loading (prefetch) flag.
 pub struct ClientRequest {
     pub ip: String,
     pub user_agent: String,
     /// sec-ch-ua header; sent by every Chromium >= 89, absent elsewhere
     pub sec_ch_ua: String,
     pub prefetch: bool,
+    /// Internal traffic token from the `betterlytics_internal` cookie or the
+    /// `X-Betterlytics-Internal` header; compared against the site's token
+    pub internal_token: Option<String>,
 }
 
 impl<S: Send + Sync> FromRequestParts<S> for ClientRequest {
     type Rejection = Infallible;
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-        let ip = crate::ip_parser::parse_ip(&parts.headers)
-            .ok()
-            .or_else(|| {
-                parts
-                    .extensions
-                    .get::<ConnectInfo<SocketAddr>>()
-                    .map(|connect_info| connect_info.0.ip())
-            })
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|connect_info| connect_info.0.ip());
+        let ip = crate::ip_parser::client_ip(&parts.headers, peer)
             .map(|ip| ip.to_string())
             .unwrap_or_default();
 
@@ -34,6 +38,7 @@ impl<S: Send + Sync> FromRequestParts<S> for ClientRequest {
             user_agent: user_agent(&parts.headers).to_string(),
             sec_ch_ua: header_str(&parts.headers, "sec-ch-ua").to_string(),
             prefetch: is_prefetch(&parts.headers),
+            internal_token: internal_token(&parts.headers).map(str::to_string),
         })
     }
 }
@@ -45,6 +50,33 @@ pub fn user_agent(headers: &HeaderMap) -> &str {
         .unwrap_or_default()
 }
 
+/// Token from an `Authorization: Bearer <token>` header, if present and non-empty
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+        .map(|(_, token)| token.trim())
+        .filter(|token| !token.is_empty())
+}
+
+/// The header wins over the cookie, which only reaches us on same-site setups
+pub fn internal_token(headers: &HeaderMap) -> Option<&str> {
+    let header = Some(header_str(headers, INTERNAL_HEADER).trim()).filter(|v| !v.is_empty());
+    header.or_else(|| {
+        headers
+            .get_all(axum::http::header::COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(';'))
+            .filter_map(|pair| pair.trim().split_once('='))
+            .find(|(name, _)| *name == INTERNAL_COOKIE)
+            .map(|(_, value)| value.trim())
+            .filter(|value| !value.is_empty())
+    })
+}
+
 fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> &'a str {
     headers.get(name).and_then(|v| v.to_str().ok()).unwrap_or_default()
 }
@@ -101,4 +133,16 @@ mod tests {
         assert!(!is_prefetch(&normal));
         assert!(!is_prefetch(&HeaderMap::new()));
     }
+
+    #[test]
+    fn reads_internal_token_from_header_or_cookie() {
+        let mut headers = HeaderMap::new();
+        headers.insert("cookie", "theme=dark; betterlytics_internal=qa-token".parse().unwrap());
+        assert_eq!(internal_token(&headers), Some("qa-token"));
+
+        headers.insert("x-betterlytics-internal", "office-token".parse().unwrap());
+        assert_eq!(internal_token(&headers), Some("office-token"));
+
+        assert_eq!(internal_token(&HeaderMap::new()), None);
+    }
 }
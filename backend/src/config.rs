@@ -23,6 +23,9 @@ impl GeolocationMode {
 pub struct Config {
     pub server_port: u16,
     pub server_host: String,
+    // Client IP extraction: which forwarding header to read, and which peers may set it
+    pub client_ip_header_mode: String,
+    pub trusted_proxies: String,
     pub log_level: String,
     pub clickhouse_url: String,
     pub clickhouse_user: String,
@@ -43,6 +46,27 @@ pub struct Config {
     pub ga4_source_categories_path: PathBuf,
     pub ua_regexes_path: PathBuf,
     pub data_retention_days: i32,
+    // Directory for batches ClickHouse permanently rejects (None = drop them)
+    pub dead_letter_dir: Option<PathBuf>,
+    // Bearer token for the /admin endpoints (None = admin endpoints disabled)
+    pub admin_api_token: Option<String>,
+    // On-disk overflow spool for the events channel (None = drop overflow)
+    pub ingest_spool_dir: Option<PathBuf>,
+    pub ingest_spool_max_bytes: u64,
+    // HTTP NDJSON export of every event row (None = export disabled)
+    pub export_url: Option<String>,
+    pub export_hmac_secret: Option<String>,
+    pub export_batch_rows: usize,
+    pub export_flush_interval: Duration,
+    pub export_max_attempts: u32,
+    pub export_queue_capacity: usize,
+    // Per-site quota enforcement
+    pub quota_respond_429: bool,
+    pub quota_reconcile_interval: Duration,
+    // Resolve events without a site_id from their URL's host
+    pub resolve_site_from_host: bool,
+    // Ordered enrichment stage names run by the event processor
+    pub enrichment_stages: Vec<String>,
     // Monitoring configuration
     pub enable_monitoring: bool,
     pub enable_uptime_monitoring: bool,
@@ -64,6 +88,8 @@ pub struct Config {
     pub s3_sse_enabled: bool,        // enable SSE (AES256) on uploaded objects
     // Site-config cache database (read-only)
     pub site_config_database_url: String,
+    // Apply config changes announced through Postgres LISTEN/NOTIFY instead of waiting for the next poll
+    pub config_change_listen: bool,
     // Salt database (read-write) - stores the secret rotating fingerprint salts
     pub salts_database_url: String,
     // Development mode - allows localhost monitoring targets
@@ -105,6 +131,9 @@ impl Config {
                 .unwrap_or(3000),
             server_host: env::var("SERVER_HOST")
                 .unwrap_or_else(|_| "127.0.0.1".to_string()),
+            client_ip_header_mode: env::var("CLIENT_IP_HEADER_MODE")
+                .unwrap_or_else(|_| "legacy".to_string()),
+            trusted_proxies: env::var("TRUSTED_PROXIES").unwrap_or_default(),
             log_level: env::var("LOG_LEVEL")
                 .unwrap_or_else(|_| "info".to_string()),
             clickhouse_url: env::var("CLICKHOUSE_URL")
@@ -156,6 +185,63 @@ impl Config {
                 .unwrap_or_else(|_| "365".to_string())
                 .parse()
                 .unwrap_or(365),
+            dead_letter_dir: env::var("DEAD_LETTER_DIR")
+                .ok()
+                .filter(|dir| !dir.trim().is_empty())
+                .map(PathBuf::from),
+            admin_api_token: env::var("ADMIN_API_TOKEN")
+                .ok()
+                .filter(|token| !token.trim().is_empty()),
+            ingest_spool_dir: env::var("INGEST_SPOOL_DIR")
+                .ok()
+                .filter(|dir| !dir.trim().is_empty())
+                .map(PathBuf::from),
+            ingest_spool_max_bytes: env::var("INGEST_SPOOL_MAX_MB")
+                .ok()
+                .and_then(|val| val.parse::<u64>().ok())
+                .unwrap_or(1024)
+                * 1024 * 1024,
+            export_url: env::var("EXPORT_HTTP_URL")
+                .ok()
+                .filter(|url| !url.trim().is_empty()),
+            export_hmac_secret: env::var("EXPORT_HMAC_SECRET")
+                .ok()
+                .filter(|secret| !secret.trim().is_empty()),
+            export_batch_rows: env::var("EXPORT_BATCH_ROWS")
+                .ok()
+                .and_then(|val| val.parse().ok())
+                .unwrap_or(500),
+            export_flush_interval: Duration::from_millis(
+                env::var("EXPORT_FLUSH_INTERVAL_MS")
+                    .ok()
+                    .and_then(|val| val.parse().ok())
+                    .unwrap_or(1000)
+            ),
+            export_max_attempts: env::var("EXPORT_MAX_ATTEMPTS")
+                .ok()
+                .and_then(|val| val.parse().ok())
+                .unwrap_or(5),
+            export_queue_capacity: env::var("EXPORT_QUEUE_CAPACITY")
+                .ok()
+                .and_then(|val| val.parse().ok())
+                .unwrap_or(50_000),
+            resolve_site_from_host: env::var("RESOLVE_SITE_FROM_HOST")
+                .map(|val| val.to_lowercase() == "true")
+                .unwrap_or(false),
+            enrichment_stages: env::var("ENRICHMENT_STAGES")
+                .ok()
+                .filter(|val| !val.trim().is_empty())
+                .map(|val| val.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect())
+                .unwrap_or_else(|| crate::processing::enrichment::DEFAULT_STAGES.iter().map(|s| s.to_string()).collect()),
+            quota_respond_429: env::var("QUOTA_EXCEEDED_STATUS_429")
+                .map(|val| val.to_lowercase() == "true")
+                .unwrap_or(false),
+            quota_reconcile_interval: Duration::from_secs(
+                env::var("QUOTA_RECONCILE_INTERVAL_SECS")
+                    .ok()
+                    .and_then(|val| val.parse().ok())
+                    .unwrap_or(60)
+            ),
             // Monitoring configuration
             enable_monitoring: env::var("ENABLE_MONITORING")
                 .map(|val| val.to_lowercase() == "true")
@@ -189,6 +275,9 @@ impl Config {
             s3_sse_enabled: env::var("S3_SSE_ENABLED").map(|v| v.to_lowercase() == "true").unwrap_or(false),
             site_config_database_url: env::var("SITE_CONFIG_DATABASE_URL")
                 .expect("SITE_CONFIG_DATABASE_URL must be set to a valid Postgres URL for the site-config cache database"),
+            config_change_listen: env::var("CONFIG_CHANGE_LISTEN")
+                .map(|val| val.to_lowercase() == "true")
+                .unwrap_or(false),
             salts_database_url: env::var("SALTS_DATABASE_URL")
                 .expect("SALTS_DATABASE_URL must be set to a valid read-write Postgres URL for the fingerprint salts table"),
             is_development: env::var("IS_DEVELOPMENT")
@@ -0,0 +1,158 @@
+//! Dead-letter spool for batches ClickHouse permanently rejects. Each batch
+//! becomes one NDJSON file: a header line naming the table and the ClickHouse
+//! error, then one line per row. Files are replayed after the cause (usually
+//! a schema mismatch) is fixed, and deleted once ClickHouse accepts them.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+const FILE_EXTENSION: &str = "ndjson";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeadLetterHeader {
+    pub table: String,
+    pub error: String,
+    pub dead_lettered_at: DateTime<Utc>,
+    /// Rows that could not be encoded even as JSON; these are lost
+    pub unserializable_rows: usize,
+}
+
+/// Outcome of a replay pass, returned by the admin endpoint
+#[derive(Debug, Default, Serialize)]
+pub struct DeadLetterReplay {
+    pub replayed_files: usize,
+    pub replayed_rows: usize,
+    pub failed: Vec<DeadLetterFailure>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeadLetterFailure {
+    pub file: String,
+    pub error: String,
+}
+
+pub struct DeadLetterSpool {
+    dir: PathBuf,
+}
+
+impl DeadLetterSpool {
+    pub fn new(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    /// Writes the batch to a new spool file and returns how many rows it holds.
+    /// The file is renamed into place only once complete, so a replay never
+    /// picks up a half-written batch.
+    pub async fn write<R: Serialize>(
+        &self,
+        table: &str,
+        error: &str,
+        rows: &[R],
+    ) -> io::Result<usize> {
+        let encoded: Vec<String> = rows
+            .iter()
+            .filter_map(|row| serde_json::to_string(row).ok())
+            .collect();
+        let header = DeadLetterHeader {
+            table: table.to_string(),
+            error: error.to_string(),
+            dead_lettered_at: Utc::now(),
+            unserializable_rows: rows.len() - encoded.len(),
+        };
+
+        let mut contents = serde_json::to_string(&header)?;
+        contents.push('\n');
+        for line in &encoded {
+            contents.push_str(line);
+            contents.push('\n');
+        }
+
+        let name = format!(
+            "{}-{}-{}",
+            header.dead_lettered_at.format("%Y%m%dT%H%M%S"),
+            table,
+            uuid::Uuid::new_v4()
+        );
+        let tmp_path = self.dir.join(format!("{name}.tmp"));
+        tokio::fs::write(&tmp_path, contents).await?;
+        tokio::fs::rename(&tmp_path, self.dir.join(format!("{name}.{FILE_EXTENSION}"))).await?;
+
+        Ok(encoded.len())
+    }
+
+    /// Spooled files, oldest first
+    pub fn files(&self) -> io::Result<Vec<PathBuf>> {
+        let mut files: Vec<PathBuf> = std::fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == FILE_EXTENSION))
+            .collect();
+        files.sort();
+        Ok(files)
+    }
+}
+
+/// Splits a spool file into its header and the raw row lines
+pub fn parse_file(contents: &str) -> anyhow::Result<(DeadLetterHeader, Vec<&str>)> {
+    let mut lines = contents.lines().filter(|line| !line.trim().is_empty());
+    let header = lines
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("Dead-letter file is empty"))?;
+    let header: DeadLetterHeader = serde_json::from_str(header)?;
+    Ok((header, lines.collect()))
+}
+
+pub fn parse_rows<R: DeserializeOwned>(lines: &[&str]) -> anyhow::Result<Vec<R>> {
+    lines
+        .iter()
+        .map(|line| serde_json::from_str(line).map_err(Into::into))
+        .collect()
+}
+
+/// Stable per-file token, so replaying a file twice (e.g. the delete after a
+/// successful replay failed) is deduplicated by ClickHouse
+pub fn dedup_token(path: &Path) -> String {
+    format!(
+        "dead-letter-{}",
+        path.file_stem().and_then(|s| s.to_str()).unwrap_or_default()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct TestRow {
+        id: u32,
+    }
+
+    #[tokio::test]
+    async fn spooled_batch_round_trips() {
+        let dir = std::env::temp_dir().join(format!(
+            "betterlytics-dead-letter-{}",
+            SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos()
+        ));
+        let spool = DeadLetterSpool::new(&dir).unwrap();
+
+        let rows = vec![TestRow { id: 1 }, TestRow { id: 2 }];
+        let written = spool.write("analytics.events", "Code: 53. Type mismatch", &rows).await.unwrap();
+        assert_eq!(written, 2);
+
+        let files = spool.files().unwrap();
+        assert_eq!(files.len(), 1);
+        let contents = std::fs::read_to_string(&files[0]).unwrap();
+        let (header, lines) = parse_file(&contents).unwrap();
+        assert_eq!(header.table, "analytics.events");
+        assert_eq!(header.error, "Code: 53. Type mismatch");
+        assert_eq!(header.unserializable_rows, 0);
+        assert_eq!(parse_rows::<TestRow>(&lines).unwrap(), rows);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,452 @@
+//! Optional HTTP export of every event row ClickHouse accepts, whether it came
+//! through the inserter, the ingest overflow spool or a dead-letter replay.
+//! Each accepted row is offered to a bounded queue without waiting: when the
+//! export endpoint falls behind, rows are dropped from the export (and
+//! counted) rather than slowing down the primary insert path.
+//!
+//! Rows are POSTed as NDJSON batches of `ExportRow`, a stable external schema
+//! with ISO 8601 timestamps and string enums rather than ClickHouse encodings.
+//! Each request carries a batch id that stays the same across retries, and,
+//! when a secret is configured, an HMAC-SHA256 signature over
+//! `"{timestamp}.{body}"`.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use tokio::sync::Notify;
+use tokio::sync::mpsc::{self, Receiver, Sender, error::TrySendError};
+use tokio::task::{JoinError, JoinHandle};
+use tokio::time::{Instant, timeout_at};
+use tracing::{debug, error, info, warn};
+
+use super::EventRow;
+use crate::metrics::MetricsCollector;
+
+pub const TIMESTAMP_HEADER: &str = "x-betterlytics-timestamp";
+pub const SIGNATURE_HEADER: &str = "x-betterlytics-signature";
+pub const BATCH_ID_HEADER: &str = "x-betterlytics-batch-id";
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const RETRY_BASE_BACKOFF: Duration = Duration::from_millis(500);
+const RETRY_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub struct ExportConfig {
+    pub url: String,
+    /// Signs each request when set
+    pub hmac_secret: Option<String>,
+    pub batch_rows: usize,
+    pub flush_interval: Duration,
+    /// Delivery attempts per batch before it is dropped
+    pub max_attempts: u32,
+    /// Rows buffered ahead of the sender before new rows are dropped
+    pub queue_capacity: usize,
+}
+
+/// One exported event, as written to the export endpoint
+#[derive(Debug, Serialize)]
+pub struct ExportRow<'a> {
+    pub site_id: &'a str,
+    pub visitor_id: u64,
+    pub session_id: u64,
+    pub timestamp: DateTime<Utc>,
+    pub date: NaiveDate,
+    pub session_created_at: DateTime<Utc>,
+    /// `pageview`, `custom`, `outbound_link`, `cwv`, `scroll_depth`, `client_error` or `engagement`
+    pub event_type: &'a str,
+    pub domain: &'a str,
+    pub url: &'a str,
+    pub url_raw: &'a str,
+    pub matched_domain: &'a str,
+    pub device_type: &'a str,
+    pub country_code: &'a str,
+    pub subdivision_code: &'a str,
+    pub city: &'a str,
+    pub browser: &'a str,
+    pub browser_version: &'a str,
+    pub os: &'a str,
+    pub os_version: &'a str,
+    pub asn: u32,
+    pub asn_org: &'a str,
+    pub referrer_source: &'a str,
+    pub referrer_source_canonical: &'a str,
+    pub referrer_source_name: &'a str,
+    pub referrer_search_term: &'a str,
+    pub referrer_url: &'a str,
+    pub channel: &'a str,
+    pub utm_source: &'a str,
+    pub utm_medium: &'a str,
+    pub utm_campaign: &'a str,
+    pub utm_term: &'a str,
+    pub utm_content: &'a str,
+    pub utm_id: &'a str,
+    pub utm_source_platform: &'a str,
+    pub click_id_network: &'a str,
+    pub custom_event_name: &'a str,
+    pub custom_event_json: &'a str,
+    pub outbound_link_url: &'a str,
+    pub cwv_cls: Option<f32>,
+    pub cwv_lcp: Option<f32>,
+    pub cwv_inp: Option<f32>,
+    pub cwv_fcp: Option<f32>,
+    pub cwv_ttfb: Option<f32>,
+    pub scroll_depth_percentage: Option<f32>,
+    pub scroll_depth_pixels: Option<f32>,
+    pub page_duration_seconds: u32,
+    pub error_exceptions: &'a str,
+    pub error_type: &'a str,
+    pub error_message: &'a str,
+    pub error_fingerprint: &'a str,
+    pub global_properties_keys: &'a [String],
+    pub global_properties_values: &'a [String],
+    pub property_schema_violations: &'a [String],
+    pub is_internal: bool,
+}
+
+impl<'a> From<&'a EventRow> for ExportRow<'a> {
+    fn from(row: &'a EventRow) -> Self {
+        Self {
+            site_id: &row.site_id,
+            visitor_id: row.visitor_id,
+            session_id: row.session_id,
+            timestamp: row.timestamp,
+            date: row.date,
+            session_created_at: row.session_created_at,
+            event_type: row.event_type.as_ref(),
+            domain: &row.domain,
+            url: &row.url,
+            url_raw: &row.url_raw,
+            matched_domain: &row.matched_domain,
+            device_type: &row.device_type,
+            country_code: &row.country_code,
+            subdivision_code: &row.subdivision_code,
+            city: &row.city,
+            browser: &row.browser,
+            browser_version: &row.browser_version,
+            os: &row.os,
+            os_version: &row.os_version,
+            asn: row.asn,
+            asn_org: &row.asn_org,
+            referrer_source: &row.referrer_source,
+            referrer_source_canonical: &row.referrer_source_canonical,
+            referrer_source_name: &row.referrer_source_name,
+            referrer_search_term: &row.referrer_search_term,
+            referrer_url: &row.referrer_url,
+            channel: &row.channel,
+            utm_source: &row.utm_source,
+            utm_medium: &row.utm_medium,
+            utm_campaign: &row.utm_campaign,
+            utm_term: &row.utm_term,
+            utm_content: &row.utm_content,
+            utm_id: &row.utm_id,
+            utm_source_platform: &row.utm_source_platform,
+            click_id_network: &row.click_id_network,
+            custom_event_name: &row.custom_event_name,
+            custom_event_json: &row.custom_event_json,
+            outbound_link_url: &row.outbound_link_url,
+            cwv_cls: row.cwv_cls,
+            cwv_lcp: row.cwv_lcp,
+            cwv_inp: row.cwv_inp,
+            cwv_fcp: row.cwv_fcp,
+            cwv_ttfb: row.cwv_ttfb,
+            scroll_depth_percentage: row.scroll_depth_percentage,
+            scroll_depth_pixels: row.scroll_depth_pixels,
+            page_duration_seconds: row.page_duration_seconds,
+            error_exceptions: &row.error_exceptions,
+            error_type: &row.error_type,
+            error_message: &row.error_message,
+            error_fingerprint: &row.error_fingerprint,
+            global_properties_keys: &row.global_properties_keys,
+            global_properties_values: &row.global_properties_values,
+            property_schema_violations: &row.property_schema_violations,
+            is_internal: row.is_internal,
+        }
+    }
+}
+
+/// Inserter-side handle; cheap to clone
+#[derive(Clone)]
+pub struct EventExport {
+    tx: Sender<String>,
+    metrics: Option<Arc<MetricsCollector>>,
+}
+
+/// The sender task, kept so shutdown can wait for the last batch
+pub struct ExportTask {
+    close: Arc<Notify>,
+    handle: JoinHandle<()>,
+}
+
+impl ExportTask {
+    /// Stops taking rows, sends what is already queued and waits for the
+    /// sender to exit. Rows offered afterwards are dropped.
+    pub async fn finish(self) -> Result<(), JoinError> {
+        self.close.notify_one();
+        self.handle.await
+    }
+}
+
+impl EventExport {
+    /// Starts the sender task. It flushes its last batch and exits once
+    /// `ExportTask::finish` is called or every handle is dropped.
+    pub fn spawn(config: ExportConfig, metrics: Option<Arc<MetricsCollector>>) -> anyhow::Result<(Self, ExportTask)> {
+        let client = reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build()?;
+        let (tx, rx) = mpsc::channel(config.queue_capacity.max(1));
+        let close = Arc::new(Notify::new());
+        info!(url = %config.url, signed = config.hmac_secret.is_some(), "Event export enabled");
+        let handle = tokio::spawn(run_sender(client, config, rx, Arc::clone(&close), metrics.clone()));
+        Ok((Self { tx, metrics }, ExportTask { close, handle }))
+    }
+
+    /// Queues rows ClickHouse accepted for export without waiting; a full
+    /// queue drops them
+    pub fn offer(&self, rows: &[EventRow]) {
+        for row in rows {
+            self.offer_line(&ExportRow::from(row));
+        }
+    }
+
+    fn offer_line<R: Serialize>(&self, row: &R) {
+        let line = match serde_json::to_string(row) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!(error = %e, "Failed to encode row for export");
+                self.count_dropped("encode_error", 1);
+                return;
+            }
+        };
+        match self.tx.try_send(line) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => self.count_dropped("queue_full", 1),
+            Err(TrySendError::Closed(_)) => self.count_dropped("sender_stopped", 1),
+        }
+    }
+
+    fn count_dropped(&self, reason: &str, count: u64) {
+        if let Some(metrics) = &self.metrics {
+            metrics.increment_events_export_dropped(reason, count);
+        }
+    }
+}
+
+async fn run_sender(
+    client: reqwest::Client,
+    config: ExportConfig,
+    mut rx: Receiver<String>,
+    close: Arc<Notify>,
+    metrics: Option<Arc<MetricsCollector>>,
+) {
+    let mut batch: Vec<String> = Vec::with_capacity(config.batch_rows);
+    let mut flush_deadline = Instant::now() + config.flush_interval;
+
+    loop {
+        let next = tokio::select! {
+            next = timeout_at(flush_deadline, rx.recv()) => next,
+            _ = close.notified() => {
+                // Closing keeps the queued rows readable, then recv yields None
+                rx.close();
+                continue;
+            }
+        };
+        match next {
+            Ok(Some(line)) => {
+                batch.push(line);
+                if batch.len() >= config.batch_rows {
+                    send_batch(&client, &config, &mut batch, metrics.as_deref()).await;
+                    flush_deadline = Instant::now() + config.flush_interval;
+                }
+            }
+            Ok(None) => {
+                send_batch(&client, &config, &mut batch, metrics.as_deref()).await;
+                info!("Event export stopped");
+                return;
+            }
+            Err(_) => {
+                send_batch(&client, &config, &mut batch, metrics.as_deref()).await;
+                flush_deadline = Instant::now() + config.flush_interval;
+            }
+        }
+    }
+}
+
+/// Retries network errors, 429 and 5xx with backoff; any other status gives
+/// up on the batch straight away. The batch is cleared either way.
+async fn send_batch(
+    client: &reqwest::Client,
+    config: &ExportConfig,
+    batch: &mut Vec<String>,
+    metrics: Option<&MetricsCollector>,
+) {
+    if batch.is_empty() {
+        return;
+    }
+
+    let mut body = batch.join("\n");
+    body.push('\n');
+    let batch_id = uuid::Uuid::new_v4().to_string();
+    let rows = batch.len() as u64;
+    batch.clear();
+
+    let max_attempts = config.max_attempts.max(1);
+    let mut backoff = RETRY_BASE_BACKOFF;
+    for attempt in 1..=max_attempts {
+        let timestamp = chrono::Utc::now().timestamp().to_string();
+        let mut request = client
+            .post(&config.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/x-ndjson")
+            .header(BATCH_ID_HEADER, &batch_id)
+            .header(TIMESTAMP_HEADER, &timestamp);
+        if let Some(secret) = &config.hmac_secret {
+            request = request.header(SIGNATURE_HEADER, sign(secret, &timestamp, &body));
+        }
+
+        let retryable = match request.body(body.clone()).send().await {
+            Ok(response) if response.status().is_success() => {
+                debug!(rows, batch_id, "Exported event batch");
+                if let Some(metrics) = metrics {
+                    metrics.increment_events_exported(rows);
+                }
+                return;
+            }
+            Ok(response) => {
+                let status = response.status();
+                warn!(%status, attempt, batch_id, "Event export endpoint refused batch");
+                status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            }
+            Err(e) => {
+                warn!(error = %e, attempt, batch_id, "Event export request failed");
+                true
+            }
+        };
+        if !retryable || attempt == max_attempts {
+            break;
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(RETRY_MAX_BACKOFF);
+    }
+
+    error!(rows, batch_id, "Giving up on event export batch");
+    if let Some(metrics) = metrics {
+        metrics.increment_events_export_dropped("delivery_failed", rows);
+    }
+}
+
+/// `sha256=<hex>` HMAC of `"{timestamp}.{body}"`
+pub fn sign(secret: &str, timestamp: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderMap;
+    use tokio::time::timeout;
+
+    #[derive(Serialize)]
+    struct TestRow {
+        id: u32,
+    }
+
+    #[test]
+    fn signature_covers_timestamp_and_body() {
+        assert_eq!(
+            sign("s3cret", "1700000000", "{\"id\":1}\n"),
+            "sha256=9757151ef15eaf801b8014163fa16a7e8afe7ca9e8855a365084b5fd25d9a930"
+        );
+    }
+
+    #[test]
+    fn export_rows_use_iso_timestamps_and_string_enums() {
+        let row = EventRow::from_processed(crate::db::tests::test_event(7)).unwrap();
+        let json = serde_json::to_value(ExportRow::from(&row)).unwrap();
+        assert_eq!(json["event_type"], "pageview");
+        assert_eq!(json["timestamp"], row.timestamp.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true));
+        assert_eq!(json["date"], row.date.format("%Y-%m-%d").to_string());
+        assert_eq!(json["session_id"], 7);
+    }
+
+    #[tokio::test]
+    async fn batches_are_signed_and_retried_after_server_errors() {
+        let (req_tx, mut req_rx) = mpsc::unbounded_channel::<(HeaderMap, String)>();
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let app = axum::Router::new().fallback(move |headers: HeaderMap, body: String| {
+            let req_tx = req_tx.clone();
+            let calls = Arc::clone(&calls);
+            async move {
+                let _ = req_tx.send((headers, body));
+                // First delivery fails, so the batch must be retried
+                if calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                    http::StatusCode::SERVICE_UNAVAILABLE
+                } else {
+                    http::StatusCode::NO_CONTENT
+                }
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let (export, _task) = EventExport::spawn(
+            ExportConfig {
+                url: format!("http://{addr}/ingest"),
+                hmac_secret: Some("s3cret".to_string()),
+                batch_rows: 2,
+                flush_interval: Duration::from_secs(60),
+                max_attempts: 3,
+                queue_capacity: 10,
+            },
+            None,
+        )
+        .unwrap();
+        export.offer_line(&TestRow { id: 1 });
+        export.offer_line(&TestRow { id: 2 });
+
+        let (first, _) = timeout(Duration::from_secs(10), req_rx.recv()).await.unwrap().unwrap();
+        let (headers, body) = timeout(Duration::from_secs(10), req_rx.recv()).await.unwrap().unwrap();
+        assert_eq!(body, "{\"id\":1}\n{\"id\":2}\n");
+        assert_eq!(first[BATCH_ID_HEADER], headers[BATCH_ID_HEADER]);
+
+        let timestamp = headers[TIMESTAMP_HEADER].to_str().unwrap();
+        assert_eq!(headers[SIGNATURE_HEADER].to_str().unwrap(), sign("s3cret", timestamp, &body));
+    }
+
+    #[tokio::test]
+    async fn finish_sends_the_partial_batch_while_handles_are_alive() {
+        let (req_tx, mut req_rx) = mpsc::unbounded_channel::<String>();
+        let app = axum::Router::new().fallback(move |body: String| {
+            let req_tx = req_tx.clone();
+            async move {
+                let _ = req_tx.send(body);
+                http::StatusCode::NO_CONTENT
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let (export, task) = EventExport::spawn(
+            ExportConfig {
+                url: format!("http://{addr}/ingest"),
+                hmac_secret: None,
+                batch_rows: 10,
+                flush_interval: Duration::from_secs(60),
+                max_attempts: 1,
+                queue_capacity: 10,
+            },
+            None,
+        )
+        .unwrap();
+        export.offer_line(&TestRow { id: 1 });
+
+        timeout(Duration::from_secs(10), task.finish()).await.unwrap().unwrap();
+        assert_eq!(req_rx.try_recv().unwrap(), "{\"id\":1}\n");
+    }
+}
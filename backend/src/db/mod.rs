@@ -13,8 +13,14 @@ use crate::config::Config;
 use crate::metrics::MetricsCollector;
 use crate::processing::{BotEvent, ProcessedEvent};
 
+pub mod dead_letter;
+pub mod export;
 mod models;
+pub mod spool;
+pub use dead_letter::{DeadLetterFailure, DeadLetterReplay, DeadLetterSpool};
+pub use export::{EventExport, ExportConfig, ExportTask};
 pub use models::{ActiveSessionRow, BotEventRow, EventRow, ReferrerSourceCategoryRow, SessionReplayRow};
+pub use spool::{IngestSpool, SpoolWriter};
 
 const EVENT_CHANNEL_CAPACITY: usize = 100_000;
 const BOT_CHANNEL_CAPACITY: usize = 10_000;
@@ -32,9 +38,20 @@ const RETRY_BASE_BACKOFF_SECS: u64 = 1;
 const RETRY_MAX_BACKOFF_SECS: u64 = 30;
 const REJECTED_BATCH_ATTEMPTS: u32 = 3;
 
+/// Called with each batch ClickHouse accepts
+type ExportHook<R> = Arc<dyn Fn(&[R]) + Send + Sync>;
+
 pub struct Database {
     clickhouse: Arc<ClickHouseClient>,
     config: Arc<Config>,
+    /// Where rejected batches are kept for replay; None drops them as before
+    dead_letter: Option<Arc<DeadLetterSpool>>,
+    /// Overflow spool for the events channel; None drops overflow as before
+    ingest_spool: Option<Arc<IngestSpool>>,
+    /// HTTP export of accepted event rows; None when export is disabled
+    export: Option<EventExport>,
+    /// Taken by `main` to flush the export at shutdown
+    export_task: Option<ExportTask>,
 }
 
 pub type SharedDatabase = Arc<Database>;
@@ -49,13 +66,65 @@ impl Database {
         let (event_tx, event_rx) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
         let (bot_event_tx, bot_event_rx) = mpsc::channel(BOT_CHANNEL_CAPACITY);
 
+        let dead_letter = match &config.dead_letter_dir {
+            Some(dir) => {
+                let spool = DeadLetterSpool::new(dir).map_err(|e| {
+                    anyhow::anyhow!("Failed to create dead-letter directory {}: {}", dir.display(), e)
+                })?;
+                info!(dir = %dir.display(), "Dead-letter spool enabled");
+                Some(Arc::new(spool))
+            }
+            None => None,
+        };
+
         let client = clickhouse.inner().clone();
+
+        let (export, export_task) = match &config.export_url {
+            Some(url) => {
+                let (export, task) = EventExport::spawn(
+                    ExportConfig {
+                        url: url.clone(),
+                        hmac_secret: config.export_hmac_secret.clone(),
+                        batch_rows: config.export_batch_rows,
+                        flush_interval: config.export_flush_interval,
+                        max_attempts: config.export_max_attempts,
+                        queue_capacity: config.export_queue_capacity,
+                    },
+                    metrics.clone(),
+                )?;
+                (Some(export), Some(task))
+            }
+            None => (None, None),
+        };
+
+        let ingest_spool = match &config.ingest_spool_dir {
+            Some(dir) => {
+                let spool = IngestSpool::open(dir, config.ingest_spool_max_bytes).map_err(|e| {
+                    anyhow::anyhow!("Failed to open ingest spool {}: {}", dir.display(), e)
+                })?;
+                info!(dir = %dir.display(), max_bytes = config.ingest_spool_max_bytes, "Ingest overflow spool enabled");
+                let spool = Arc::new(spool);
+                tokio::spawn(spool::run_drain(
+                    client.clone(),
+                    Arc::clone(&spool),
+                    dead_letter.clone(),
+                    export.clone(),
+                    event_tx.downgrade(),
+                    metrics.clone(),
+                ));
+                Some(spool)
+            }
+            None => None,
+        };
+
         let inserter_handle = tokio::spawn(run_inserter(
             client.clone(),
             "analytics.events",
             event_rx,
             EventRow::from_processed,
             metrics.clone(),
+            dead_letter.clone(),
+            export.clone().map(|export| -> ExportHook<EventRow> { Arc::new(move |rows| export.offer(rows)) }),
         ));
         let bot_inserter_handle = tokio::spawn(run_inserter(
             client,
@@ -63,9 +132,11 @@ impl Database {
             bot_event_rx,
             |event: BotEvent| Some(BotEventRow::from_bot(event)),
             metrics,
+            dead_letter.clone(),
+            None,
         ));
 
-        Ok((Self { clickhouse, config }, event_tx, bot_event_tx, inserter_handle, bot_inserter_handle))
+        Ok((Self { clickhouse, config, dead_letter, ingest_spool, export, export_task }, event_tx, bot_event_tx, inserter_handle, bot_inserter_handle))
     }
 
     /// Fetch the current session of every visitor active within `window`, from `analytics.sessions`
@@ -203,6 +274,44 @@ impl Database {
         Ok(())
     }
 
+    pub fn ingest_spool(&self) -> Option<Arc<IngestSpool>> {
+        self.ingest_spool.clone()
+    }
+
+    pub fn take_export_task(&mut self) -> Option<ExportTask> {
+        self.export_task.take()
+    }
+
+    pub fn dead_letter(&self) -> Option<&DeadLetterSpool> {
+        self.dead_letter.as_deref()
+    }
+
+    /// Re-inserts every spooled batch, oldest first. A file is deleted once
+    /// ClickHouse accepts it; failures are reported and left for the next run.
+    pub async fn replay_dead_letters(&self, spool: &DeadLetterSpool) -> Result<DeadLetterReplay> {
+        let mut report = DeadLetterReplay::default();
+        for path in spool.files()? {
+            match replay_dead_letter_file(self.clickhouse.inner(), &path, self.export.as_ref()).await {
+                Ok(rows) => {
+                    info!(file = %path.display(), rows, "Replayed dead-letter batch");
+                    if let Err(e) = tokio::fs::remove_file(&path).await {
+                        warn!(file = %path.display(), "Replayed dead-letter file could not be removed: {}", e);
+                    }
+                    report.replayed_files += 1;
+                    report.replayed_rows += rows;
+                }
+                Err(e) => {
+                    warn!(file = %path.display(), "Dead-letter replay failed: {}", e);
+                    report.failed.push(DeadLetterFailure {
+                        file: path.display().to_string(),
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+        Ok(report)
+    }
+
     pub async fn upsert_session_replay(&self, row: SessionReplayRow) -> Result<()> {
         let mut inserter = self.clickhouse.inner().inserter("analytics.session_replays")?;
         inserter.write(&row)?;
@@ -211,12 +320,40 @@ impl Database {
     }
 }
 
+async fn replay_dead_letter_file(
+    client: &clickhouse::Client,
+    path: &std::path::Path,
+    export: Option<&EventExport>,
+) -> Result<usize> {
+    let contents = tokio::fs::read_to_string(path).await?;
+    let (header, lines) = dead_letter::parse_file(&contents)?;
+    let token = dead_letter::dedup_token(path);
+    match header.table.as_str() {
+        "analytics.events" => {
+            let rows: Vec<EventRow> = dead_letter::parse_rows(&lines)?;
+            try_insert(client, "analytics.events", &rows, &token).await?;
+            if let Some(export) = export {
+                export.offer(&rows);
+            }
+            Ok(rows.len())
+        }
+        "analytics.bot_events" => {
+            let rows: Vec<BotEventRow> = dead_letter::parse_rows(&lines)?;
+            try_insert(client, "analytics.bot_events", &rows, &token).await?;
+            Ok(rows.len())
+        }
+        other => Err(anyhow::anyhow!("Unknown dead-letter table: {}", other)),
+    }
+}
+
 async fn run_inserter<T, R>(
     client: clickhouse::Client,
     table: &'static str,
     mut rx: Receiver<T>,
     convert: fn(T) -> Option<R>,
     metrics: Option<Arc<MetricsCollector>>,
+    dead_letter: Option<Arc<DeadLetterSpool>>,
+    export: Option<ExportHook<R>>,
 ) where
     T: Send,
     R: clickhouse::Row + serde::Serialize,
@@ -240,18 +377,18 @@ async fn run_inserter<T, R>(
                 }
 
                 if batch.len() >= INSERTER_MAX_ROWS {
-                    flush(&client, table, &mut batch, &metrics).await;
+                    flush(&client, table, &mut batch, &metrics, &dead_letter, &export).await;
                     flush_deadline = Instant::now() + period;
                 }
             }
             Ok(None) => {
                 info!(table, rows = batch.len(), "Ingest channel closed, committing final batch");
-                flush(&client, table, &mut batch, &metrics).await;
+                flush(&client, table, &mut batch, &metrics, &dead_letter, &export).await;
                 info!(table, "Inserter shutdown complete, final batch committed");
                 return;
             }
             Err(_) => {
-                flush(&client, table, &mut batch, &metrics).await;
+                flush(&client, table, &mut batch, &metrics, &dead_letter, &export).await;
                 flush_deadline = Instant::now() + period;
             }
         }
@@ -259,14 +396,18 @@ async fn run_inserter<T, R>(
 }
 
 /// Clears the batch only once ClickHouse confirms it. Transient failures retry
-/// forever (the channel buffers upstream); recognized rejections drop the batch
-/// after a few attempts so a poison batch cannot block the pipeline. All
-/// attempts share one dedup token, so a re-sent batch is ignored server-side.
+/// forever (the channel buffers upstream); recognized rejections give up on the
+/// batch after a few attempts so a poison batch cannot block the pipeline, and
+/// move it to the dead-letter spool when one is configured. All attempts share
+/// one dedup token, so a re-sent batch is ignored server-side. Only accepted
+/// batches are exported; dead-lettered rows are exported once replayed.
 async fn flush<R>(
     client: &clickhouse::Client,
     table: &'static str,
     batch: &mut Vec<R>,
     metrics: &Option<Arc<MetricsCollector>>,
+    dead_letter: &Option<Arc<DeadLetterSpool>>,
+    export: &Option<ExportHook<R>>,
 ) where
     R: clickhouse::Row + serde::Serialize,
 {
@@ -288,6 +429,9 @@ async fn flush<R>(
                     metrics.set_inserter_retry_attempts(table, 0);
                     metrics.set_inserter_batch_rows(table, 0);
                 }
+                if let Some(export) = export {
+                    export(batch);
+                }
                 batch.clear();
                 return;
             }
@@ -314,14 +458,32 @@ async fn flush<R>(
                 ErrorClass::Deterministic => {
                     rejected_attempts += 1;
                     if rejected_attempts >= REJECTED_BATCH_ATTEMPTS {
+                        let spooled = match dead_letter {
+                            Some(spool) => match spool.write(table, &e.to_string(), batch).await {
+                                Ok(spooled) => spooled,
+                                Err(spool_err) => {
+                                    error!(error = %spool_err, table, "Failed to write dead-letter batch");
+                                    0
+                                }
+                            },
+                            None => 0,
+                        };
+                        let dropped = batch.len() - spooled;
                         error!(
                             error = %e,
                             table,
                             rows = batch.len(),
-                            "ClickHouse rejected batch deterministically, dropping it"
+                            spooled,
+                            dropped,
+                            "ClickHouse rejected batch deterministically, giving up on it"
                         );
                         if let Some(metrics) = metrics {
-                            metrics.increment_events_dropped("insert_gave_up", table, batch.len() as u64);
+                            if spooled > 0 {
+                                metrics.increment_events_dead_lettered(table, spooled as u64);
+                            }
+                            if dropped > 0 {
+                                metrics.increment_events_dropped("insert_gave_up", table, dropped as u64);
+                            }
                             metrics.set_inserter_retry_attempts(table, 0);
                             metrics.set_inserter_batch_rows(table, 0);
                         }
@@ -418,7 +580,7 @@ mod tests {
     use clickhouse::test::{handlers, Mock};
     use tokio::time::timeout;
 
-    fn test_event(n: u64) -> ProcessedEvent {
+    pub(crate) fn test_event(n: u64) -> ProcessedEvent {
         let raw = RawTrackingEvent {
             site_id: "test-site".to_string(),
             event_name: "pageview".to_string(),
@@ -461,9 +623,11 @@ mod tests {
             timestamp: chrono::Utc::now(),
             domain: Some("example.com".to_string()),
             url: format!("/page-{n}"),
+            url_raw: String::new(),
             referrer_info: ReferrerInfo::default(),
             user_agent: "test-agent".to_string(),
             campaign_info: CampaignInfo::default(),
+            channel: None,
             custom_event_name: String::new(),
             custom_event_json: String::new(),
             outbound_link_url: String::new(),
@@ -496,6 +660,8 @@ mod tests {
             rx,
             EventRow::from_processed,
             None,
+            None,
+            None,
         ))
     }
 
@@ -593,4 +759,72 @@ mod tests {
         assert_eq!(rows.len(), 1, "only the healthy row should be inserted");
         assert_eq!(rows[0].session_id, 1);
     }
+
+    fn temp_spool() -> (std::path::PathBuf, Arc<DeadLetterSpool>) {
+        let dir = std::env::temp_dir().join(format!("betterlytics-dead-letter-{}", uuid::Uuid::new_v4()));
+        let spool = Arc::new(DeadLetterSpool::new(&dir).unwrap());
+        (dir, spool)
+    }
+
+    /// Answers every request like a ClickHouse server rejecting the insert.
+    pub(crate) async fn rejecting_server() -> String {
+        let app = axum::Router::new().fallback(|| async {
+            (
+                http::StatusCode::INTERNAL_SERVER_ERROR,
+                "Code: 53. DB::Exception: Type mismatch in column. (TYPE_MISMATCH)",
+            )
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn rejected_batch_is_dead_lettered_and_replayable() {
+        let (dir, spool) = temp_spool();
+        let client = clickhouse::Client::default().with_url(rejecting_server().await);
+
+        let (tx, rx) = mpsc::channel(100);
+        let handle = tokio::spawn(run_inserter(
+            client,
+            "analytics.events",
+            rx,
+            EventRow::from_processed,
+            None,
+            Some(Arc::clone(&spool)),
+            None,
+        ));
+        for n in 0..3 {
+            tx.send(test_event(n)).await.unwrap();
+        }
+        drop(tx);
+
+        timeout(Duration::from_secs(30), handle)
+            .await
+            .expect("inserter blocked on rejected batch")
+            .expect("inserter task panicked");
+
+        let files = spool.files().unwrap();
+        assert_eq!(files.len(), 1);
+        let contents = std::fs::read_to_string(&files[0]).unwrap();
+        let (header, lines) = dead_letter::parse_file(&contents).unwrap();
+        assert_eq!(header.table, "analytics.events");
+        assert!(header.error.contains("TYPE_MISMATCH"));
+        assert_eq!(lines.len(), 3);
+
+        // After the "schema fix", the same file replays into ClickHouse
+        let mock = Mock::new();
+        let recording = mock.add(handlers::record());
+        let client = clickhouse::Client::default().with_url(mock.url());
+        let replayed = replay_dead_letter_file(&client, &files[0], None).await.unwrap();
+        assert_eq!(replayed, 3);
+
+        let rows: Vec<EventRow> = recording.collect().await;
+        let mut session_ids: Vec<u64> = rows.iter().map(|r| r.session_id).collect();
+        session_ids.sort();
+        assert_eq!(session_ids, vec![0, 1, 2]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
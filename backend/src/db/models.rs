@@ -1,7 +1,7 @@
 use chrono::{DateTime, Utc, NaiveDate};
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
-use strum_macros::EnumString;
+use strum_macros::{AsRefStr, EnumString};
 use crate::processing::{BotEvent, ProcessedEvent};
 
 // Ensure field order exactly matches ClickHouse table schema
@@ -56,10 +56,27 @@ pub struct EventRow {
     pub page_duration_seconds: u32,
     pub asn: u32,
     pub asn_org: String,
+    // Default keeps spool and dead-letter files written before the column existed readable
+    #[serde(default)]
+    pub property_schema_violations: Vec<String>,
+    #[serde(default)]
+    pub matched_domain: String,
+    #[serde(default)]
+    pub is_internal: bool,
+    #[serde(default)]
+    pub utm_id: String,
+    #[serde(default)]
+    pub utm_source_platform: String,
+    #[serde(default)]
+    pub click_id_network: String,
+    #[serde(default)]
+    pub channel: String,
+    #[serde(default)]
+    pub url_raw: String,
 }
 
 // Ensure field order exactly matches ClickHouse table schema
-#[derive(clickhouse::Row, Serialize, Debug)]
+#[derive(clickhouse::Row, Serialize, Debug, Deserialize)]
 pub struct BotEventRow {
     pub site_id: String,
     #[serde(with = "clickhouse::serde::chrono::datetime")]
@@ -126,7 +143,7 @@ pub struct SessionReplayRow {
     pub error_fingerprints: Vec<String>,
 }
 
-#[derive(Debug, EnumString, Serialize_repr, Deserialize_repr)]
+#[derive(Debug, EnumString, AsRefStr, Serialize_repr, Deserialize_repr)]
 #[strum(serialize_all = "snake_case")]
 #[repr(u8)]
 pub enum EventType {
@@ -210,6 +227,14 @@ impl EventRow {
             page_duration_seconds: event.page_duration_seconds,
             asn: event.asn,
             asn_org: event.asn_org,
+            property_schema_violations: event.event.property_schema_violations,
+            matched_domain: event.event.matched_domain,
+            is_internal: event.event.is_internal,
+            utm_id: event.campaign_info.utm_id.unwrap_or_default(),
+            utm_source_platform: event.campaign_info.utm_source_platform.unwrap_or_default(),
+            click_id_network: event.campaign_info.click_id_network.unwrap_or_default(),
+            channel: event.channel.map(|channel| channel.as_str().to_string()).unwrap_or_default(),
+            url_raw: event.url_raw,
         })
     }
 }
@@ -264,9 +289,11 @@ mod tests {
             timestamp: chrono::Utc::now(),
             domain: Some("example.com".to_string()),
             url: "/".to_string(),
+            url_raw: String::new(),
             referrer_info: ReferrerInfo::default(),
             user_agent: "test-agent".to_string(),
             campaign_info: CampaignInfo::default(),
+            channel: None,
             custom_event_name: String::new(),
             custom_event_json: String::new(),
             outbound_link_url: String::new(),
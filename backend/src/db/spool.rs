@@ -0,0 +1,570 @@
+//! On-disk overflow spool for `analytics.events`. When the ingest channel is
+//! full (a long ClickHouse outage), the processor appends rows here instead of
+//! dropping them. Rows go to append-only NDJSON segment files; a drain task
+//! inserts sealed segments oldest-first once the inserter is keeping up again
+//! and deletes each segment after ClickHouse confirms it. Segments left on
+//! disk by a previous run are picked up on start.
+//!
+//! Nothing here runs file I/O on the async workers: the processor hands rows
+//! to a `SpoolWriter`, whose blocking task does the appends, and the drain task
+//! reads and deletes segments through `spawn_blocking`.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use tokio::sync::mpsc::{self, WeakSender, error::TrySendError};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use super::{DeadLetterSpool, ErrorClass, EventExport, EventRow, REJECTED_BATCH_ATTEMPTS, classify};
+use crate::metrics::MetricsCollector;
+use crate::processing::ProcessedEvent;
+
+const SEGMENT_EXTENSION: &str = "seg";
+/// A segment is sealed (and becomes drainable) once it reaches this size
+const MAX_SEGMENT_BYTES: u64 = 16 * 1024 * 1024;
+const DRAIN_INTERVAL: Duration = Duration::from_secs(5);
+/// Drain only while the ingest channel is at most this full, so replayed
+/// segments never compete with live traffic for a struggling inserter
+const DRAIN_MAX_CHANNEL_FILL: f64 = 0.5;
+/// Encoded rows queued ahead of the writer task before new rows are refused
+const WRITER_QUEUE_ROWS: usize = 10_000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SpoolError {
+    #[error("spool is at its size limit")]
+    Full,
+    #[error("spool writer is not keeping up")]
+    Backlogged,
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Encode(#[from] serde_json::Error),
+}
+
+#[derive(Debug, Clone)]
+pub struct Segment {
+    seq: u64,
+    path: PathBuf,
+    bytes: u64,
+    created_at: SystemTime,
+}
+
+impl Segment {
+    /// Stable across restarts, so a segment inserted twice (the delete after a
+    /// successful insert failed) is deduplicated by ClickHouse
+    fn dedup_token(&self) -> String {
+        format!("ingest-spool-{:020}", self.seq)
+    }
+}
+
+struct ActiveSegment {
+    segment: Segment,
+    file: File,
+}
+
+struct SpoolState {
+    active: Option<ActiveSegment>,
+    sealed: VecDeque<Segment>,
+    next_seq: u64,
+    /// Deterministic insert rejections per segment seq
+    rejections: HashMap<u64, u32>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct SpoolStats {
+    pub bytes: u64,
+    pub segments: usize,
+    pub oldest_age_secs: f64,
+}
+
+pub struct IngestSpool {
+    dir: PathBuf,
+    max_bytes: u64,
+    /// Bytes on disk plus bytes reserved by rows still queued for the writer.
+    /// Kept outside `state` so the ingest path never waits on a disk write.
+    total_bytes: AtomicU64,
+    /// Held across segment writes; take it only off the async workers
+    state: Mutex<SpoolState>,
+}
+
+impl IngestSpool {
+    /// Opens (or creates) the spool directory; segments from a previous run
+    /// are queued for draining ahead of anything new.
+    pub fn open(dir: impl Into<PathBuf>, max_bytes: u64) -> io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+
+        let mut sealed = Vec::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let Some(seq) = segment_seq(&path) else { continue };
+            let metadata = std::fs::metadata(&path)?;
+            sealed.push(Segment {
+                seq,
+                path,
+                bytes: metadata.len(),
+                created_at: metadata.modified().unwrap_or_else(|_| SystemTime::now()),
+            });
+        }
+        sealed.sort_by_key(|s| s.seq);
+
+        // Time-based floor keeps sequence numbers (and so dedup tokens) unique
+        // even after the directory has been fully drained
+        let now_micros = chrono::Utc::now().timestamp_micros().max(0) as u64;
+        let next_seq = sealed.last().map_or(0, |s| s.seq + 1).max(now_micros);
+        let total_bytes = sealed.iter().map(|s| s.bytes).sum();
+        if !sealed.is_empty() {
+            info!(segments = sealed.len(), bytes = total_bytes, "Ingest spool has segments from a previous run");
+        }
+
+        Ok(Self {
+            dir,
+            max_bytes,
+            total_bytes: AtomicU64::new(total_bytes),
+            state: Mutex::new(SpoolState {
+                active: None,
+                sealed: sealed.into(),
+                next_seq,
+                rejections: HashMap::new(),
+            }),
+        })
+    }
+
+    /// Appends one row to the active segment, blocking on the write. The
+    /// ingest path goes through `SpoolWriter` instead.
+    #[cfg(test)]
+    pub fn append(&self, row: &EventRow) -> Result<(), SpoolError> {
+        let line = encode(row)?;
+        self.reserve(line.len() as u64)?;
+        self.write_reserved(&line)?;
+        Ok(())
+    }
+
+    /// Claims room for `len` bytes against the size limit without touching disk
+    fn reserve(&self, len: u64) -> Result<(), SpoolError> {
+        self.total_bytes
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |total| {
+                (total + len <= self.max_bytes).then_some(total + len)
+            })
+            .map(|_| ())
+            .map_err(|_| SpoolError::Full)
+    }
+
+    /// Writes a line whose bytes were reserved; gives the reservation back if
+    /// the write fails. Each row is one `write` call, so it survives a process
+    /// crash even though it is not fsynced.
+    fn write_reserved(&self, line: &[u8]) -> io::Result<()> {
+        let result = self.write_line(line);
+        if result.is_err() {
+            self.total_bytes.fetch_sub(line.len() as u64, Ordering::AcqRel);
+        }
+        result
+    }
+
+    fn write_line(&self, line: &[u8]) -> io::Result<()> {
+        let len = line.len() as u64;
+        let mut state = self.state.lock().unwrap();
+        if state.active.is_none() {
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            let path = self.dir.join(format!("{:020}.{}", seq, SEGMENT_EXTENSION));
+            let file = OpenOptions::new().create_new(true).append(true).open(&path)?;
+            state.active = Some(ActiveSegment {
+                segment: Segment { seq, path, bytes: 0, created_at: SystemTime::now() },
+                file,
+            });
+        }
+
+        let active = state.active.as_mut().expect("active segment opened above");
+        active.file.write_all(line)?;
+        active.segment.bytes += len;
+        let full = active.segment.bytes >= MAX_SEGMENT_BYTES;
+
+        if full {
+            seal(&mut state);
+        }
+        Ok(())
+    }
+
+    /// Oldest drainable segment. Seals the active segment when nothing else
+    /// is waiting, so the tail of an overflow drains too.
+    pub fn oldest(&self) -> Option<Segment> {
+        let mut state = self.state.lock().unwrap();
+        if state.sealed.is_empty() {
+            seal(&mut state);
+        }
+        state.sealed.front().cloned()
+    }
+
+    /// Reads a segment's rows. A torn final line from a crash mid-write is
+    /// skipped rather than failing the whole segment.
+    pub fn read(&self, segment: &Segment) -> io::Result<Vec<EventRow>> {
+        let contents = std::fs::read_to_string(&segment.path)?;
+        let mut rows = Vec::new();
+        for line in contents.lines().filter(|l| !l.trim().is_empty()) {
+            match serde_json::from_str(line) {
+                Ok(row) => rows.push(row),
+                Err(e) => warn!(segment = %segment.path.display(), "Skipping unreadable spooled row: {}", e),
+            }
+        }
+        Ok(rows)
+    }
+
+    /// Deletes a segment once its rows are safely in ClickHouse
+    pub fn remove(&self, segment: &Segment) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(pos) = state.sealed.iter().position(|s| s.seq == segment.seq) {
+            let removed = state.sealed.remove(pos).expect("position is in range");
+            self.total_bytes.fetch_sub(removed.bytes, Ordering::AcqRel);
+        }
+        state.rejections.remove(&segment.seq);
+        drop(state);
+        std::fs::remove_file(&segment.path)
+    }
+
+    /// Counts a deterministic rejection of the segment and returns its total
+    fn record_rejection(&self, segment: &Segment) -> u32 {
+        let mut state = self.state.lock().unwrap();
+        let attempts = state.rejections.entry(segment.seq).or_default();
+        *attempts += 1;
+        *attempts
+    }
+
+    pub fn stats(&self) -> SpoolStats {
+        let state = self.state.lock().unwrap();
+        let oldest = state
+            .sealed
+            .front()
+            .or(state.active.as_ref().map(|a| &a.segment));
+        SpoolStats {
+            bytes: self.total_bytes.load(Ordering::Acquire),
+            segments: state.sealed.len() + usize::from(state.active.is_some()),
+            oldest_age_secs: oldest
+                .and_then(|s| s.created_at.elapsed().ok())
+                .map_or(0.0, |age| age.as_secs_f64()),
+        }
+    }
+}
+
+/// Ingest-path handle to the spool. Rows are encoded and counted against the
+/// size limit up front, then queued for a blocking writer task, so a slow disk
+/// never stalls a tokio worker. The writer finishes the queue and exits once
+/// every handle is dropped.
+#[derive(Clone)]
+pub struct SpoolWriter {
+    spool: Arc<IngestSpool>,
+    tx: mpsc::Sender<Vec<u8>>,
+}
+
+impl SpoolWriter {
+    pub fn spawn(spool: Arc<IngestSpool>, metrics: Option<Arc<MetricsCollector>>) -> (Self, JoinHandle<()>) {
+        let (tx, mut rx) = mpsc::channel::<Vec<u8>>(WRITER_QUEUE_ROWS);
+        let writer_spool = Arc::clone(&spool);
+        let handle = tokio::task::spawn_blocking(move || {
+            while let Some(line) = rx.blocking_recv() {
+                match writer_spool.write_reserved(&line) {
+                    Ok(()) => {
+                        if let Some(metrics) = &metrics {
+                            metrics.increment_events_spooled();
+                        }
+                    }
+                    Err(e) => {
+                        error!("Failed to write event to ingest spool: {}", e);
+                        if let Some(metrics) = &metrics {
+                            metrics.increment_events_dropped("spool_error", "analytics.events", 1);
+                        }
+                    }
+                }
+            }
+            info!("Ingest spool writer stopped");
+        });
+        (Self { spool, tx }, handle)
+    }
+
+    /// Queues a row for the writer without waiting
+    pub fn append(&self, row: &EventRow) -> Result<(), SpoolError> {
+        let line = encode(row)?;
+        let len = line.len() as u64;
+        self.spool.reserve(len)?;
+        self.tx.try_send(line).map_err(|e| {
+            self.spool.total_bytes.fetch_sub(len, Ordering::AcqRel);
+            match e {
+                TrySendError::Full(_) => SpoolError::Backlogged,
+                TrySendError::Closed(_) => SpoolError::Io(io::Error::other("spool writer stopped")),
+            }
+        })
+    }
+}
+
+fn encode(row: &EventRow) -> Result<Vec<u8>, serde_json::Error> {
+    let mut line = serde_json::to_vec(row)?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// Runs a spool operation that takes the state lock or touches the disk on
+/// the blocking pool
+async fn blocking<T, F>(spool: &Arc<IngestSpool>, op: F) -> anyhow::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&IngestSpool) -> T + Send + 'static,
+{
+    let spool = Arc::clone(spool);
+    Ok(tokio::task::spawn_blocking(move || op(&spool)).await?)
+}
+
+fn seal(state: &mut SpoolState) {
+    if let Some(active) = state.active.take()
+        && active.segment.bytes > 0
+    {
+        state.sealed.push_back(active.segment);
+    }
+}
+
+fn segment_seq(path: &Path) -> Option<u64> {
+    if path.extension()? != SEGMENT_EXTENSION {
+        return None;
+    }
+    path.file_stem()?.to_str()?.parse().ok()
+}
+
+/// Inserts the oldest segment and deletes it on success. Returns whether a
+/// segment was drained. A segment ClickHouse rejects `REJECTED_BATCH_ATTEMPTS`
+/// times, like a poison batch in the inserter, moves to the dead-letter spool
+/// (or is dropped without one) so later segments are not stuck behind it.
+pub async fn drain_oldest(
+    client: &clickhouse::Client,
+    spool: &Arc<IngestSpool>,
+    dead_letter: Option<&DeadLetterSpool>,
+    export: Option<&EventExport>,
+    metrics: Option<&MetricsCollector>,
+) -> anyhow::Result<bool> {
+    const TABLE: &str = "analytics.events";
+    let next = blocking(spool, |spool| spool.oldest().map(|segment| (spool.read(&segment), segment))).await?;
+    let Some((rows, segment)) = next else {
+        return Ok(false);
+    };
+    let rows = rows?;
+    if !rows.is_empty()
+        && let Err(e) = super::try_insert(client, TABLE, &rows, &segment.dedup_token()).await
+    {
+        if matches!(classify(&e), ErrorClass::Transient) {
+            return Err(e.into());
+        }
+        let rejected = segment.clone();
+        if blocking(spool, move |spool| spool.record_rejection(&rejected)).await? < REJECTED_BATCH_ATTEMPTS {
+            return Err(e.into());
+        }
+        let spooled = match dead_letter {
+            Some(dead_letter) => match dead_letter.write(TABLE, &e.to_string(), &rows).await {
+                Ok(spooled) => spooled,
+                // Keep the segment; it is retried on the next tick
+                Err(spool_err) => return Err(spool_err.into()),
+            },
+            None => 0,
+        };
+        remove(spool, segment).await?;
+        let dropped = rows.len() - spooled;
+        error!(
+            error = %e,
+            rows = rows.len(),
+            spooled,
+            dropped,
+            "ClickHouse rejected ingest spool segment deterministically, giving up on it"
+        );
+        if let Some(metrics) = metrics {
+            if spooled > 0 {
+                metrics.increment_events_dead_lettered(TABLE, spooled as u64);
+            }
+            if dropped > 0 {
+                metrics.increment_events_dropped("insert_gave_up", TABLE, dropped as u64);
+            }
+        }
+        return Ok(true);
+    }
+    remove(spool, segment).await?;
+    info!(rows = rows.len(), "Drained ingest spool segment into ClickHouse");
+    if let Some(export) = export {
+        export.offer(&rows);
+    }
+    if let Some(metrics) = metrics {
+        metrics.increment_events_inserted(TABLE, rows.len() as u64);
+    }
+    Ok(true)
+}
+
+async fn remove(spool: &Arc<IngestSpool>, segment: Segment) -> anyhow::Result<()> {
+    blocking(spool, move |spool| spool.remove(&segment)).await??;
+    Ok(())
+}
+
+/// Publishes spool metrics and drains segments whenever the ingest channel has
+/// room. Holds only a weak channel handle, so the channel still closes at
+/// shutdown.
+pub async fn run_drain(
+    client: clickhouse::Client,
+    spool: Arc<IngestSpool>,
+    dead_letter: Option<Arc<DeadLetterSpool>>,
+    export: Option<EventExport>,
+    ingest_tx: WeakSender<ProcessedEvent>,
+    metrics: Option<Arc<MetricsCollector>>,
+) {
+    let mut tick = tokio::time::interval(DRAIN_INTERVAL);
+    loop {
+        tick.tick().await;
+        if let Some(metrics) = &metrics
+            && let Ok(stats) = blocking(&spool, IngestSpool::stats).await
+        {
+            metrics.set_ingest_spool_stats(stats);
+        }
+
+        let Some(tx) = ingest_tx.upgrade() else { return };
+        let fill = 1.0 - tx.capacity() as f64 / tx.max_capacity() as f64;
+        drop(tx);
+        if fill > DRAIN_MAX_CHANNEL_FILL {
+            continue;
+        }
+
+        loop {
+            match drain_oldest(&client, &spool, dead_letter.as_deref(), export.as_ref(), metrics.as_deref()).await {
+                Ok(true) => continue,
+                Ok(false) => break,
+                Err(e) => {
+                    warn!("Ingest spool drain failed, retrying later: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clickhouse::test::{handlers, Mock};
+
+    fn row(session_id: u64) -> EventRow {
+        let mut row: EventRow = serde_json::from_str(ROW_JSON).unwrap();
+        row.session_id = session_id;
+        row
+    }
+
+    const ROW_JSON: &str = r#"{"site_id":"test-site","visitor_id":1,"session_id":0,"domain":"example.com","url":"/","device_type":"","country_code":"","subdivision_code":"","city":"","timestamp":1700000000,"date":19675,"browser":"","browser_version":"","os":"","os_version":"","referrer_source":"direct","referrer_source_canonical":"","referrer_source_name":"","referrer_search_term":"","referrer_url":"","utm_source":"","utm_medium":"","utm_campaign":"","utm_term":"","utm_content":"","event_type":1,"custom_event_name":"","custom_event_json":"","outbound_link_url":"","cwv_cls":null,"cwv_lcp":null,"cwv_inp":null,"cwv_fcp":null,"cwv_ttfb":null,"scroll_depth_percentage":null,"scroll_depth_pixels":null,"error_exceptions":"","error_type":"","error_message":"","error_fingerprint":"","session_created_at":1700000000,"global_properties_keys":[],"global_properties_values":[],"page_duration_seconds":0,"asn":0,"asn_org":""}"#;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("betterlytics-ingest-spool-{}", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn segments_survive_reopen_in_order() {
+        let dir = temp_dir();
+        {
+            let spool = IngestSpool::open(&dir, u64::MAX).unwrap();
+            spool.append(&row(1)).unwrap();
+            spool.append(&row(2)).unwrap();
+            assert_eq!(spool.stats().segments, 1);
+        }
+
+        let spool = IngestSpool::open(&dir, u64::MAX).unwrap();
+        spool.append(&row(3)).unwrap();
+
+        let first = spool.oldest().unwrap();
+        let ids: Vec<u64> = spool.read(&first).unwrap().iter().map(|r| r.session_id).collect();
+        assert_eq!(ids, vec![1, 2]);
+        spool.remove(&first).unwrap();
+
+        let second = spool.oldest().unwrap();
+        let ids: Vec<u64> = spool.read(&second).unwrap().iter().map(|r| r.session_id).collect();
+        assert_eq!(ids, vec![3]);
+        spool.remove(&second).unwrap();
+
+        assert!(spool.oldest().is_none());
+        assert_eq!(spool.stats(), SpoolStats::default());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn append_fails_once_size_limit_is_reached() {
+        let dir = temp_dir();
+        let line_len = serde_json::to_vec(&row(0)).unwrap().len() as u64 + 1;
+        let spool = IngestSpool::open(&dir, line_len * 2).unwrap();
+
+        spool.append(&row(0)).unwrap();
+        spool.append(&row(1)).unwrap();
+        assert!(matches!(spool.append(&row(2)), Err(SpoolError::Full)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn drained_segment_is_inserted_and_deleted() {
+        let dir = temp_dir();
+        let spool = Arc::new(IngestSpool::open(&dir, u64::MAX).unwrap());
+        for n in 0..3 {
+            spool.append(&row(n)).unwrap();
+        }
+
+        let mock = Mock::new();
+        let recording = mock.add(handlers::record());
+        let client = clickhouse::Client::default().with_url(mock.url());
+
+        assert!(drain_oldest(&client, &spool, None, None, None).await.unwrap());
+        let rows: Vec<EventRow> = recording.collect().await;
+        assert_eq!(rows.iter().map(|r| r.session_id).collect::<Vec<_>>(), vec![0, 1, 2]);
+
+        assert!(!drain_oldest(&client, &spool, None, None, None).await.unwrap());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejected_segment_is_dead_lettered_and_does_not_block_later_segments() {
+        let dir = temp_dir();
+        let spool = Arc::new(IngestSpool::open(&dir, u64::MAX).unwrap());
+        spool.append(&row(0)).unwrap();
+        let poison = spool.oldest().unwrap();
+        spool.append(&row(1)).unwrap();
+
+        let dead_letter_dir = temp_dir();
+        let dead_letter = DeadLetterSpool::new(&dead_letter_dir).unwrap();
+        let client = clickhouse::Client::default().with_url(crate::db::tests::rejecting_server().await);
+
+        for _ in 1..REJECTED_BATCH_ATTEMPTS {
+            assert!(drain_oldest(&client, &spool, Some(&dead_letter), None, None).await.is_err());
+            assert_eq!(spool.oldest().unwrap().seq, poison.seq);
+        }
+        assert!(drain_oldest(&client, &spool, Some(&dead_letter), None, None).await.unwrap());
+        assert_eq!(dead_letter.files().unwrap().len(), 1);
+
+        let next = spool.oldest().unwrap();
+        assert_ne!(next.seq, poison.seq);
+        assert_eq!(spool.read(&next).unwrap()[0].session_id, 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_dir_all(&dead_letter_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn writer_appends_queued_rows_before_exiting() {
+        let dir = temp_dir();
+        let line_len = serde_json::to_vec(&row(0)).unwrap().len() as u64 + 1;
+        let spool = Arc::new(IngestSpool::open(&dir, line_len * 2).unwrap());
+        let (writer, handle) = SpoolWriter::spawn(Arc::clone(&spool), None);
+
+        writer.append(&row(0)).unwrap();
+        writer.append(&row(1)).unwrap();
+        assert!(matches!(writer.append(&row(2)), Err(SpoolError::Full)));
+        drop(writer);
+        handle.await.unwrap();
+
+        let segment = spool.oldest().unwrap();
+        let ids: Vec<u64> = spool.read(&segment).unwrap().iter().map(|r| r.session_id).collect();
+        assert_eq!(ids, vec![0, 1]);
+        assert_eq!(spool.stats().bytes, line_len * 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
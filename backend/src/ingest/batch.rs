@@ -0,0 +1,208 @@
+//! `/events` batch endpoint: a JSON array or NDJSON stream of tracking events,
+//! each run through the regular ingest path and answered with its own status.
+
+use axum::{
+    Json,
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode, header::CONTENT_TYPE},
+};
+use serde::Serialize;
+
+use crate::analytics::RawTrackingEvent;
+use crate::client_request::ClientRequest;
+
+use super::{Ingest, RouterState};
+use super::body::{BodyTransport, decode_body};
+
+/// Largest number of events accepted in one batch request
+pub const MAX_BATCH_EVENTS: usize = 100;
+/// Request body cap for `/events`, sized for a full batch of typical events
+pub const BATCH_BODY_LIMIT_BYTES: usize = 512 * 1024;
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchEventStatus {
+    Accepted,
+    Rejected,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchEventResult {
+    pub status: BatchEventStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchResponse {
+    pub accepted: usize,
+    pub rejected: usize,
+    /// One entry per submitted event, in submission order
+    pub results: Vec<BatchEventResult>,
+}
+
+pub async fn track_events(
+    State((_db, processor, metrics, validator, _s3, site_cfg_cache)): State<RouterState>,
+    client: ClientRequest,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<BatchResponse>, (StatusCode, String)> {
+    let is_ndjson = headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(is_ndjson_content_type)
+        .unwrap_or(false);
+
+    let body = decode_body(&headers, body, BATCH_BODY_LIMIT_BYTES, metrics.as_deref())?;
+    let items = parse_batch(&body, is_ndjson).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    if items.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Batch contains no events".to_string()));
+    }
+    if let Some(metrics) = &metrics {
+        metrics.increment_events_received(BodyTransport::from_headers(&headers).as_str(), items.len() as u64);
+    }
+    if items.len() > MAX_BATCH_EVENTS {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Batch exceeds {} events", MAX_BATCH_EVENTS),
+        ));
+    }
+
+    let ingest = Ingest {
+        processor: &processor,
+        metrics: metrics.as_deref(),
+        validator: &validator,
+        site_cfg_cache: &site_cfg_cache,
+    };
+
+    let mut results = Vec::with_capacity(items.len());
+    for item in items {
+        let result = match item {
+            Ok(raw_event) => match ingest.event(&client, raw_event).await {
+                Ok(()) => BatchEventResult {
+                    status: BatchEventStatus::Accepted,
+                    reason: None,
+                    error: None,
+                },
+                Err(rejection) => BatchEventResult {
+                    status: BatchEventStatus::Rejected,
+                    reason: Some(rejection.reason),
+                    error: Some(rejection.message),
+                },
+            },
+            Err(e) => {
+                if let Some(metrics) = &metrics {
+                    metrics.increment_events_rejected("invalid_json");
+                }
+                BatchEventResult {
+                    status: BatchEventStatus::Rejected,
+                    reason: Some("invalid_json"),
+                    error: Some(e),
+                }
+            }
+        };
+        results.push(result);
+    }
+
+    let accepted = results
+        .iter()
+        .filter(|r| r.status == BatchEventStatus::Accepted)
+        .count();
+    Ok(Json(BatchResponse {
+        accepted,
+        rejected: results.len() - accepted,
+        results,
+    }))
+}
+
+fn is_ndjson_content_type(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or_default().trim();
+    mime.eq_ignore_ascii_case("application/x-ndjson")
+        || mime.eq_ignore_ascii_case("application/ndjson")
+        || mime.eq_ignore_ascii_case("application/jsonl")
+}
+
+/// Splits a batch body into per-event parse results. Only a body that cannot
+/// be split into events at all is an error; a malformed event fails alone.
+/// A body starting with `[` is read as a JSON array unless the content type
+/// says NDJSON, anything else as one JSON object per line.
+pub fn parse_batch(
+    body: &[u8],
+    is_ndjson: bool,
+) -> Result<Vec<Result<RawTrackingEvent, String>>, String> {
+    let text = std::str::from_utf8(body).map_err(|_| "Body is not valid UTF-8".to_string())?;
+    let trimmed = text.trim_start();
+
+    if !is_ndjson && trimmed.starts_with('[') {
+        let values: Vec<serde_json::Value> =
+            serde_json::from_str(trimmed).map_err(|e| format!("Invalid JSON array: {}", e))?;
+        return Ok(values
+            .into_iter()
+            .map(|value| serde_json::from_value(value).map_err(|e| e.to_string()))
+            .collect());
+    }
+
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| serde_json::from_str(line).map_err(|e| e.to_string()))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event_json(site_id: &str) -> String {
+        format!(
+            r#"{{"site_id":"{site_id}","event_name":"pageview","is_custom_event":false,"properties":"","url":"https://example.com/","referrer":null,"user_agent":"test-agent","screen_resolution":"1920x1080","outbound_link_url":null,"cwv_cls":null,"cwv_lcp":null,"cwv_inp":null,"cwv_fcp":null,"cwv_ttfb":null,"scroll_depth_percentage":null,"scroll_depth_pixels":null,"error_exceptions":null,"page_duration_seconds":null}}"#
+        )
+    }
+
+    #[test]
+    fn parses_json_array() {
+        let body = format!("[{}, {}]", event_json("a"), event_json("b"));
+        let items = parse_batch(body.as_bytes(), false).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[1].as_ref().unwrap().site_id, "b");
+    }
+
+    #[test]
+    fn parses_ndjson_and_skips_blank_lines() {
+        let body = format!("{}\n\n{}\n", event_json("a"), event_json("b"));
+        let items = parse_batch(body.as_bytes(), true).unwrap();
+        assert_eq!(items.len(), 2);
+        assert!(items.iter().all(Result::is_ok));
+    }
+
+    #[test]
+    fn malformed_event_fails_alone() {
+        let body = format!("[{}, {{\"site_id\": 5}}, {}]", event_json("a"), event_json("c"));
+        let items = parse_batch(body.as_bytes(), false).unwrap();
+        assert_eq!(items.len(), 3);
+        assert!(items[0].is_ok());
+        assert!(items[1].is_err());
+        assert!(items[2].is_ok());
+
+        let body = format!("{}\nnot json\n", event_json("a"));
+        let items = parse_batch(body.as_bytes(), false).unwrap();
+        assert!(items[0].is_ok());
+        assert!(items[1].is_err());
+    }
+
+    #[test]
+    fn unsplittable_array_is_an_error() {
+        assert!(parse_batch(b"[{\"site_id\": ", false).is_err());
+    }
+
+    #[test]
+    fn recognizes_ndjson_content_types() {
+        assert!(is_ndjson_content_type("application/x-ndjson"));
+        assert!(is_ndjson_content_type("application/jsonl; charset=utf-8"));
+        assert!(!is_ndjson_content_type("application/json"));
+    }
+}
@@ -0,0 +1,212 @@
+//! `Content-Encoding` support for ingest request bodies. `DefaultBodyLimit`
+//! caps the bytes on the wire; the same limit is applied again to the decoded
+//! body, so a small compressed payload cannot expand past it. Bodies over the
+//! limit once decoded count as `decompressed_body_too_large` rejections.
+//!
+//! JSON bodies are also accepted as `text/plain` or without a content type,
+//! which is what `navigator.sendBeacon` sends for strings and untyped Blobs.
+
+use std::io::Read;
+
+use axum::{
+    Json,
+    body::Bytes,
+    extract::{FromRequest, FromRequestParts, Request},
+    http::{HeaderMap, StatusCode, header, request::Parts},
+};
+use serde::de::DeserializeOwned;
+
+use super::RouterState;
+use crate::metrics::MetricsCollector;
+use crate::validation::ValidationError;
+
+/// Body limit for single-event and replay routes, before and after decoding
+pub const INGEST_BODY_LIMIT_BYTES: usize = 64 * 1024;
+
+/// Decodes the body per its `Content-Encoding` header, outermost encoding
+/// last as listed. Supports gzip, deflate (zlib), br and identity.
+pub fn decode_body(
+    headers: &HeaderMap,
+    body: Bytes,
+    limit: usize,
+    metrics: Option<&MetricsCollector>,
+) -> Result<Bytes, (StatusCode, String)> {
+    let Some(encoding) = headers.get(header::CONTENT_ENCODING) else {
+        return Ok(body);
+    };
+    let encoding = encoding
+        .to_str()
+        .map_err(|_| (StatusCode::UNSUPPORTED_MEDIA_TYPE, "Invalid Content-Encoding header".to_string()))?;
+
+    let mut decoded = body;
+    for coding in encoding.rsplit(',').map(|c| c.trim().to_ascii_lowercase()) {
+        decoded = match coding.as_str() {
+            "" | "identity" => decoded,
+            "gzip" | "x-gzip" => read_limited(flate2::read::GzDecoder::new(&decoded[..]), limit, metrics)?,
+            "deflate" => read_limited(flate2::read::ZlibDecoder::new(&decoded[..]), limit, metrics)?,
+            "br" => read_limited(brotli_decompressor::Decompressor::new(&decoded[..], 4096), limit, metrics)?,
+            other => {
+                return Err((
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    format!("Unsupported Content-Encoding: {other}"),
+                ));
+            }
+        };
+    }
+    Ok(decoded)
+}
+
+fn read_limited(reader: impl Read, limit: usize, metrics: Option<&MetricsCollector>) -> Result<Bytes, (StatusCode, String)> {
+    let mut out = Vec::new();
+    reader
+        .take(limit as u64 + 1)
+        .read_to_end(&mut out)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to decode request body: {e}")))?;
+    if out.len() > limit {
+        let error = ValidationError::DecompressedBodyTooLarge(format!("Decoded body exceeds {limit} bytes"));
+        if let Some(metrics) = metrics {
+            metrics.increment_events_rejected(error.reason());
+        }
+        return Err((StatusCode::PAYLOAD_TOO_LARGE, error.to_string()));
+    }
+    Ok(Bytes::from(out))
+}
+
+/// How the client sent the body, told apart by its content type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyTransport {
+    /// `application/json` (or `+json`), as sent by `fetch`
+    Json,
+    /// `text/plain`, as sent by `sendBeacon` with a string
+    TextPlain,
+    /// No content type, as sent by `sendBeacon` with an untyped Blob
+    Untyped,
+    Other,
+}
+
+impl BodyTransport {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let Some(content_type) = headers.get(header::CONTENT_TYPE) else {
+            return Self::Untyped;
+        };
+        let essence = content_type
+            .to_str()
+            .ok()
+            .and_then(|v| v.split(';').next())
+            .map(|v| v.trim().to_ascii_lowercase())
+            .unwrap_or_default();
+        if essence == "application/json" || (essence.starts_with("application/") && essence.ends_with("+json")) {
+            Self::Json
+        } else if essence == "text/plain" {
+            Self::TextPlain
+        } else {
+            Self::Other
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::TextPlain => "text_plain",
+            Self::Untyped => "untyped",
+            Self::Other => "other",
+        }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for BodyTransport {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self::from_headers(&parts.headers))
+    }
+}
+
+/// `Json` that accepts compressed and beacon-style bodies, limited to
+/// `INGEST_BODY_LIMIT_BYTES` once decoded
+pub struct DecodedJson<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest<RouterState> for DecodedJson<T> {
+    type Rejection = (StatusCode, String);
+
+    async fn from_request(req: Request, state: &RouterState) -> Result<Self, Self::Rejection> {
+        if BodyTransport::from_headers(req.headers()) == BodyTransport::Other {
+            return Err((
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Expected a JSON body sent as `application/json` or `text/plain`".to_string(),
+            ));
+        }
+        let headers = req.headers().clone();
+        let body = Bytes::from_request(req, state)
+            .await
+            .map_err(|e| (e.status(), e.body_text()))?;
+        let (_db, _processor, metrics, _validator, _s3, _site_cfg_cache) = state;
+        let body = decode_body(&headers, body, INGEST_BODY_LIMIT_BYTES, metrics.as_deref())?;
+        let Json(value) = Json::<T>::from_bytes(&body).map_err(|e| (e.status(), e.body_text()))?;
+        Ok(DecodedJson(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn headers(encoding: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_ENCODING, encoding.parse().unwrap());
+        headers
+    }
+
+    fn gzip(data: &[u8]) -> Bytes {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        Bytes::from(encoder.finish().unwrap())
+    }
+
+    #[test]
+    fn gzip_and_deflate_bodies_are_decoded() {
+        let body = br#"{"site_id":"abc"}"#;
+        assert_eq!(decode_body(&headers("gzip"), gzip(body), 1024, None).unwrap(), &body[..]);
+
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(body).unwrap();
+        let deflated = Bytes::from(encoder.finish().unwrap());
+        assert_eq!(decode_body(&headers("deflate"), deflated, 1024, None).unwrap(), &body[..]);
+    }
+
+    #[test]
+    fn limit_applies_after_decompression() {
+        // ~100 bytes on the wire, 1 MB once inflated
+        let bomb = gzip(&vec![b'a'; 1024 * 1024]);
+        assert!(bomb.len() < 4096);
+
+        let metrics = MetricsCollector::new().unwrap();
+        let (status, message) = decode_body(&headers("gzip"), bomb, 64 * 1024, Some(&metrics)).unwrap_err();
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(message.starts_with("Decompressed body too large"));
+        assert!(metrics.export_metrics().unwrap().contains(r#"reason="decompressed_body_too_large""#));
+    }
+
+    #[test]
+    fn beacon_content_types_are_recognized() {
+        let transport = |content_type: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(ct) = content_type {
+                headers.insert(header::CONTENT_TYPE, ct.parse().unwrap());
+            }
+            BodyTransport::from_headers(&headers)
+        };
+        assert_eq!(transport(Some("application/json; charset=utf-8")), BodyTransport::Json);
+        assert_eq!(transport(Some("text/plain;charset=UTF-8")), BodyTransport::TextPlain);
+        assert_eq!(transport(None), BodyTransport::Untyped);
+        assert_eq!(transport(Some("application/x-www-form-urlencoded")), BodyTransport::Other);
+    }
+
+    #[test]
+    fn unknown_encoding_is_unsupported() {
+        let (status, _) = decode_body(&headers("compress"), Bytes::from_static(b"x"), 1024, None).unwrap_err();
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(decode_body(&HeaderMap::new(), Bytes::from_static(b"x"), 1024, None).unwrap(), "x");
+    }
+}
@@ -0,0 +1,270 @@
+//! Drop-in routes for sites moving over from Plausible (`/api/event`) and
+//! Umami (`/api/send`): their trackers keep working unchanged. Payloads are
+//! mapped onto `RawTrackingEvent`, the site is found by the domain the tracker
+//! reports, and the event then takes the normal ingest path.
+
+use axum::{Json, extract::State, http::StatusCode};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::analytics::RawTrackingEvent;
+use crate::client_request::ClientRequest;
+use crate::validation::ValidationError;
+
+use super::{Ingest, RouterState};
+use super::body::{BodyTransport, DecodedJson};
+
+/// Plausible tracker payload; the long field names are accepted as well
+#[derive(Debug, Deserialize)]
+pub struct PlausibleEvent {
+    #[serde(alias = "name")]
+    pub n: String,
+    #[serde(alias = "url")]
+    pub u: String,
+    /// Site domain, or several separated by commas
+    #[serde(alias = "domain")]
+    pub d: String,
+    #[serde(default, alias = "referrer")]
+    pub r: Option<String>,
+    /// Custom properties, as an object or a JSON-encoded string
+    #[serde(default, alias = "props")]
+    pub p: Option<Value>,
+    /// Screen width
+    #[serde(default)]
+    pub w: Option<u32>,
+    /// Engagement time in milliseconds
+    #[serde(default)]
+    pub e: Option<u64>,
+    /// Scroll depth percentage
+    #[serde(default)]
+    pub sd: Option<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UmamiRequest {
+    #[serde(rename = "type", default = "umami_event_type")]
+    pub kind: String,
+    pub payload: UmamiPayload,
+}
+
+fn umami_event_type() -> String {
+    "event".to_string()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UmamiPayload {
+    pub hostname: String,
+    /// Path and query of the page
+    pub url: String,
+    #[serde(default)]
+    pub referrer: Option<String>,
+    #[serde(default)]
+    pub screen: Option<String>,
+    /// Custom event name; absent for pageviews
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub data: Option<Value>,
+}
+
+pub async fn track_plausible_event(
+    State((_db, processor, metrics, validator, _s3, site_cfg_cache)): State<RouterState>,
+    client: ClientRequest,
+    transport: BodyTransport,
+    DecodedJson(event): DecodedJson<PlausibleEvent>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    if let Some(metrics) = &metrics {
+        metrics.increment_events_received(transport.as_str(), 1);
+    }
+    let ingest = Ingest {
+        processor: &processor,
+        metrics: metrics.as_deref(),
+        validator: &validator,
+        site_cfg_cache: &site_cfg_cache,
+    };
+
+    let site_id = event
+        .d
+        .split(',')
+        .find_map(|domain| site_cfg_cache.site_id_for_domain(domain))
+        .ok_or_else(|| unknown_domain(&ingest, &event.d))?;
+
+    ingest
+        .event(&client, plausible_to_raw(event, site_id, &client))
+        .await
+        .map(|()| StatusCode::ACCEPTED)
+        .map_err(|rejection| (rejection.status, rejection.message))
+}
+
+pub async fn track_umami_event(
+    State((_db, processor, metrics, validator, _s3, site_cfg_cache)): State<RouterState>,
+    client: ClientRequest,
+    transport: BodyTransport,
+    DecodedJson(request): DecodedJson<UmamiRequest>,
+) -> Result<Json<Value>, (StatusCode, String)> {
+    // `identify` and other session-level calls carry no page event
+    if request.kind != "event" {
+        return Ok(Json(Value::Object(Default::default())));
+    }
+    if let Some(metrics) = &metrics {
+        metrics.increment_events_received(transport.as_str(), 1);
+    }
+    let ingest = Ingest {
+        processor: &processor,
+        metrics: metrics.as_deref(),
+        validator: &validator,
+        site_cfg_cache: &site_cfg_cache,
+    };
+
+    let payload = request.payload;
+    let site_id = site_cfg_cache
+        .site_id_for_domain(&payload.hostname)
+        .ok_or_else(|| unknown_domain(&ingest, &payload.hostname))?;
+
+    ingest
+        .event(&client, umami_to_raw(payload, site_id, &client))
+        .await
+        .map(|()| Json(Value::Object(Default::default())))
+        .map_err(|rejection| (rejection.status, rejection.message))
+}
+
+fn unknown_domain(ingest: &Ingest<'_>, domain: &str) -> (StatusCode, String) {
+    let e = ValidationError::UnknownSiteHost(format!("No site is configured for domain '{domain}'"));
+    let rejection = ingest.reject(StatusCode::BAD_REQUEST, &e);
+    (rejection.status, rejection.message)
+}
+
+fn plausible_to_raw(event: PlausibleEvent, site_id: String, client: &ClientRequest) -> RawTrackingEvent {
+    let mut raw = base_event(site_id, event.u, event.r, client);
+    raw.screen_resolution = event.w.map(|w| format!("{w}x0")).unwrap_or_default();
+    match event.n.as_str() {
+        "pageview" => {}
+        "engagement" => {
+            raw.event_name = "engagement".to_string();
+            raw.page_duration_seconds = event.e.map(|ms| u32::try_from(ms / 1000).unwrap_or(u32::MAX));
+            raw.scroll_depth_percentage = event.sd;
+        }
+        name => {
+            raw.event_name = name.to_string();
+            raw.is_custom_event = true;
+            raw.properties = properties_json(event.p);
+        }
+    }
+    raw
+}
+
+fn umami_to_raw(payload: UmamiPayload, site_id: String, client: &ClientRequest) -> RawTrackingEvent {
+    let url = format!("https://{}{}", payload.hostname, payload.url);
+    let mut raw = base_event(site_id, url, payload.referrer, client);
+    raw.screen_resolution = payload.screen.unwrap_or_default();
+    if let Some(name) = payload.name.filter(|name| !name.is_empty()) {
+        raw.event_name = name;
+        raw.is_custom_event = true;
+        raw.properties = properties_json(payload.data);
+    }
+    raw
+}
+
+/// A pageview; callers overwrite what their payload says otherwise
+pub(super) fn base_event(site_id: String, url: String, referrer: Option<String>, client: &ClientRequest) -> RawTrackingEvent {
+    RawTrackingEvent {
+        site_id,
+        event_name: "pageview".to_string(),
+        is_custom_event: false,
+        properties: "{}".to_string(),
+        url,
+        referrer: referrer.filter(|r| !r.is_empty()),
+        user_agent: client.user_agent.clone(),
+        screen_resolution: String::new(),
+        timestamp: None,
+        outbound_link_url: None,
+        cwv_cls: None,
+        cwv_lcp: None,
+        cwv_inp: None,
+        cwv_fcp: None,
+        cwv_ttfb: None,
+        scroll_depth_percentage: None,
+        scroll_depth_pixels: None,
+        error_exceptions: None,
+        global_properties: None,
+        automation: false,
+        page_duration_seconds: None,
+    }
+}
+
+/// Plausible sends props either as an object or already JSON-encoded
+fn properties_json(props: Option<Value>) -> String {
+    match props {
+        Some(Value::Object(map)) => Value::Object(map).to_string(),
+        Some(Value::String(s)) if serde_json::from_str::<Value>(&s).is_ok_and(|v| v.is_object()) => s,
+        _ => "{}".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn client() -> ClientRequest {
+        ClientRequest {
+            ip: "203.0.113.7".to_string(),
+            user_agent: "Mozilla/5.0".to_string(),
+            sec_ch_ua: String::new(),
+            prefetch: false,
+            internal_token: None,
+        }
+    }
+
+    fn plausible(payload: Value) -> RawTrackingEvent {
+        let event: PlausibleEvent = serde_json::from_value(payload).unwrap();
+        plausible_to_raw(event, "site".to_string(), &client())
+    }
+
+    #[test]
+    fn plausible_pageview_and_custom_events_map_onto_raw_events() {
+        let raw = plausible(json!({ "n": "pageview", "u": "https://example.com/a", "d": "example.com", "r": "", "w": 1280 }));
+        assert_eq!(raw.event_name, "pageview");
+        assert!(!raw.is_custom_event);
+        assert_eq!(raw.referrer, None);
+        assert_eq!(raw.screen_resolution, "1280x0");
+        assert_eq!(raw.user_agent, "Mozilla/5.0");
+
+        let raw = plausible(json!({
+            "name": "Signup", "url": "https://example.com/join", "domain": "example.com",
+            "props": "{\"plan\":\"pro\"}"
+        }));
+        assert!(raw.is_custom_event);
+        assert_eq!(raw.event_name, "Signup");
+        assert_eq!(raw.properties, r#"{"plan":"pro"}"#);
+    }
+
+    #[test]
+    fn plausible_engagement_reports_duration_in_seconds() {
+        let raw = plausible(json!({ "n": "engagement", "u": "https://example.com/", "d": "example.com", "e": 12_500, "sd": 80 }));
+        assert_eq!(raw.event_name, "engagement");
+        assert_eq!(raw.page_duration_seconds, Some(12));
+        assert_eq!(raw.scroll_depth_percentage, Some(80.0));
+    }
+
+    #[test]
+    fn umami_path_is_joined_with_hostname() {
+        let request: UmamiRequest = serde_json::from_value(json!({
+            "type": "event",
+            "payload": {
+                "website": "4fb7fa4c-5b46-438d-94b3-3a8fb9bc2e8b",
+                "hostname": "example.com",
+                "url": "/pricing?ref=nav",
+                "screen": "1920x1080",
+                "name": "cta_click",
+                "data": { "position": "hero" }
+            }
+        }))
+        .unwrap();
+        let raw = umami_to_raw(request.payload, "site".to_string(), &client());
+        assert_eq!(raw.url, "https://example.com/pricing?ref=nav");
+        assert_eq!(raw.screen_resolution, "1920x1080");
+        assert!(raw.is_custom_event);
+        assert_eq!(raw.properties, r#"{"position":"hero"}"#);
+    }
+}
@@ -0,0 +1,309 @@
+//! `/mp/collect`: GA4 Measurement Protocol hits from back-office systems. The
+//! `measurement_id` query parameter picks the site (`SiteConfig.ga4MeasurementId`)
+//! and `api_secret` must be one of that site's ingest API keys. Each entry in
+//! `events[]` becomes a pageview, engagement or custom event on the normal
+//! ingest path.
+//!
+//! The sender is an authenticated backend, so its own user agent and address
+//! say nothing about the visitor: events skip the user-agent bot checks and
+//! the visitor is identified by `client_id` (and `user_id` when sent) rather
+//! than by IP and user agent. `ip_override` still supplies the visitor's
+//! location.
+//!
+//! Like GA4, a hit is answered with 204 once it is authorized, whether or not
+//! every event in it was accepted; rejected events are counted in
+//! `events_rejected_total`.
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+};
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use tracing::debug;
+
+use crate::analytics::RawTrackingEvent;
+use crate::client_request::ClientRequest;
+use crate::validation::ValidationError;
+
+use super::{Ingest, RouterState};
+use super::body::{BodyTransport, DecodedJson};
+use super::compat::base_event;
+use super::server::trusted_timestamp;
+
+/// Events per hit; GA4 drops hits with more
+pub const MAX_GA4_EVENTS: usize = 25;
+
+/// Params consumed by the mapping and not copied into custom properties
+const MAPPED_PARAMS: [&str; 5] = [
+    "page_location",
+    "page_referrer",
+    "engagement_time_msec",
+    "session_id",
+    "debug_mode",
+];
+
+#[derive(Debug, Deserialize)]
+pub struct CollectQuery {
+    pub measurement_id: String,
+    pub api_secret: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Ga4Hit {
+    pub client_id: String,
+    /// Signed-in user ID; tells apart users sharing one client
+    #[serde(default)]
+    pub user_id: Option<String>,
+    /// Hit time in unix microseconds; events may override it
+    #[serde(default)]
+    pub timestamp_micros: Option<u64>,
+    /// Visitor IP, used instead of the sender's address
+    #[serde(default)]
+    pub ip_override: Option<String>,
+    #[serde(default)]
+    pub device: Option<Ga4Device>,
+    pub events: Vec<Ga4Event>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Ga4Device {
+    #[serde(default)]
+    pub screen_resolution: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Ga4Event {
+    pub name: String,
+    #[serde(default)]
+    pub params: Map<String, Value>,
+    #[serde(default)]
+    pub timestamp_micros: Option<u64>,
+}
+
+pub async fn collect_ga4_hit(
+    State((_db, processor, metrics, validator, _s3, site_cfg_cache)): State<RouterState>,
+    Query(query): Query<CollectQuery>,
+    client: ClientRequest,
+    transport: BodyTransport,
+    DecodedJson(hit): DecodedJson<Ga4Hit>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let ingest = Ingest {
+        processor: &processor,
+        metrics: metrics.as_deref(),
+        validator: &validator,
+        site_cfg_cache: &site_cfg_cache,
+    };
+
+    // Unknown measurement ID and wrong secret look the same to the caller
+    let site = site_cfg_cache
+        .site_id_for_measurement_id(&query.measurement_id)
+        .and_then(|site_id| site_cfg_cache.get(&site_id).map(|cfg| (site_id, cfg)))
+        .filter(|(_, cfg)| cfg.accepts_api_key(&query.api_secret));
+    let Some((site_id, cfg)) = site else {
+        let e = ValidationError::InvalidApiKey("Unknown measurement_id or invalid api_secret".to_string());
+        let rejection = ingest.reject(StatusCode::UNAUTHORIZED, &e);
+        return Err((rejection.status, rejection.message));
+    };
+
+    if hit.events.len() > MAX_GA4_EVENTS {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Hit exceeds {} events", MAX_GA4_EVENTS),
+        ));
+    }
+    if let Some(metrics) = &metrics {
+        metrics.increment_events_received(transport.as_str(), hit.events.len() as u64);
+    }
+
+    let client = ClientRequest {
+        ip: hit.ip_override.clone().unwrap_or(client.ip),
+        ..client
+    };
+    let visitor_key = visitor_key(&hit);
+    let default_url = format!("https://{}/", cfg.domain);
+    let screen_resolution = hit
+        .device
+        .as_ref()
+        .and_then(|device| device.screen_resolution.clone())
+        .unwrap_or_default();
+
+    for event in hit.events {
+        let timestamp = match event.timestamp_micros.or(hit.timestamp_micros) {
+            Some(micros) => match trusted_timestamp(micros / 1_000_000, Utc::now()) {
+                Ok(ts) => Some(ts),
+                Err(e) => {
+                    ingest.reject(StatusCode::BAD_REQUEST, &e);
+                    continue;
+                }
+            },
+            None => None,
+        };
+
+        let mut raw = ga4_to_raw(event, site_id.clone(), &default_url, &client);
+        raw.screen_resolution = screen_resolution.clone();
+        if let Err(rejection) = ingest.visitor_event_at(&client, raw, timestamp, visitor_key.clone()).await {
+            debug!(client_id = %hit.client_id, reason = rejection.reason, "GA4 event rejected");
+        }
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The visitor identity of a hit: its `client_id`, plus its `user_id` if any
+fn visitor_key(hit: &Ga4Hit) -> String {
+    match hit.user_id.as_deref().filter(|id| !id.is_empty()) {
+        Some(user_id) => format!("{}:{}", hit.client_id, user_id),
+        None => hit.client_id.clone(),
+    }
+}
+
+/// `page_view` and `user_engagement` map onto pageview and engagement events;
+/// any other name becomes a custom event with its params as properties
+fn ga4_to_raw(event: Ga4Event, site_id: String, default_url: &str, client: &ClientRequest) -> RawTrackingEvent {
+    let param_str = |key: &str| event.params.get(key).and_then(Value::as_str).map(str::to_string);
+    let url = param_str("page_location").unwrap_or_else(|| default_url.to_string());
+    let mut raw = base_event(site_id, url, param_str("page_referrer"), client);
+
+    match event.name.as_str() {
+        "page_view" => {}
+        "user_engagement" => {
+            raw.event_name = "engagement".to_string();
+            raw.page_duration_seconds = event
+                .params
+                .get("engagement_time_msec")
+                .and_then(engagement_msec)
+                .map(|ms| u32::try_from(ms / 1000).unwrap_or(u32::MAX));
+        }
+        name => {
+            raw.event_name = name.to_string();
+            raw.is_custom_event = true;
+            let properties: Map<String, Value> = event
+                .params
+                .into_iter()
+                .filter(|(key, _)| !MAPPED_PARAMS.contains(&key.as_str()))
+                .collect();
+            raw.properties = Value::Object(properties).to_string();
+        }
+    }
+    raw
+}
+
+/// GA4 senders pass `engagement_time_msec` as a number or a numeric string
+fn engagement_msec(value: &Value) -> Option<u64> {
+    match value {
+        Value::Number(n) => n.as_u64().or_else(|| n.as_f64().filter(|f| *f >= 0.0).map(|f| f as u64)),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tokio::sync::mpsc;
+
+    use crate::analytics::AnalyticsEvent;
+    use crate::processing::EventProcessor;
+    use crate::processing::enrichment::EnrichmentRegistry;
+    use crate::salt;
+
+    fn client() -> ClientRequest {
+        ClientRequest {
+            ip: "203.0.113.7".to_string(),
+            user_agent: "backoffice/1.0".to_string(),
+            sec_ch_ua: String::new(),
+            prefetch: false,
+            internal_token: None,
+        }
+    }
+
+    fn events(payload: Value) -> Vec<RawTrackingEvent> {
+        let hit: Ga4Hit = serde_json::from_value(payload).unwrap();
+        hit.events
+            .into_iter()
+            .map(|event| ga4_to_raw(event, "site".to_string(), "https://example.com/", &client()))
+            .collect()
+    }
+
+    #[test]
+    fn page_view_and_engagement_use_page_params() {
+        let raw = events(json!({
+            "client_id": "123.456",
+            "events": [
+                { "name": "page_view", "params": { "page_location": "https://example.com/docs", "page_referrer": "https://search.example/" } },
+                { "name": "user_engagement", "params": { "page_location": "https://example.com/docs", "engagement_time_msec": "4200" } }
+            ]
+        }));
+        assert_eq!(raw[0].event_name, "pageview");
+        assert_eq!(raw[0].url, "https://example.com/docs");
+        assert_eq!(raw[0].referrer.as_deref(), Some("https://search.example/"));
+        assert_eq!(raw[1].event_name, "engagement");
+        assert_eq!(raw[1].page_duration_seconds, Some(4));
+    }
+
+    #[test]
+    fn other_events_become_custom_events_with_unmapped_params() {
+        let raw = events(json!({
+            "client_id": "123.456",
+            "events": [{ "name": "purchase", "params": { "value": 19.99, "currency": "EUR", "session_id": "1", "engagement_time_msec": 100 } }]
+        }));
+        assert!(raw[0].is_custom_event);
+        assert_eq!(raw[0].event_name, "purchase");
+        assert_eq!(raw[0].url, "https://example.com/");
+        let properties: Value = serde_json::from_str(&raw[0].properties).unwrap();
+        assert_eq!(properties, json!({ "value": 19.99, "currency": "EUR" }));
+    }
+
+    #[tokio::test]
+    async fn server_sender_hits_are_kept_and_keyed_by_client_id() {
+        salt::init_for_tests();
+        let (event_tx, mut event_rx) = mpsc::channel(8);
+        let (bot_tx, _bot_rx) = mpsc::channel(8);
+        let stages: [&str; 0] = [];
+        let processor = EventProcessor::new(
+            EnrichmentRegistry::default().build(&stages).unwrap(),
+            None,
+            event_tx,
+            bot_tx,
+            None,
+            false,
+            false,
+        );
+        let sender = ClientRequest {
+            user_agent: "python-requests/2.31.0".to_string(),
+            ..client()
+        };
+
+        for client_id in ["111.111", "222.222"] {
+            let hit: Ga4Hit = serde_json::from_value(json!({
+                "client_id": client_id,
+                "events": [{ "name": "page_view" }]
+            }))
+            .unwrap();
+            let key = visitor_key(&hit);
+            for event in hit.events {
+                let raw = ga4_to_raw(event, "site".to_string(), "https://example.com/", &sender);
+                let mut event = AnalyticsEvent::new(raw, sender.ip.clone(), sender.user_agent.clone(), String::new(), false);
+                event.visitor_key = Some(key.clone());
+                processor.process_event(event).await.unwrap();
+            }
+        }
+
+        let first = event_rx.try_recv().expect("python-requests hit stored");
+        let second = event_rx.try_recv().expect("python-requests hit stored");
+        assert_ne!(first.visitor_fingerprint, second.visitor_fingerprint);
+    }
+
+    #[test]
+    fn user_id_is_part_of_the_visitor_key() {
+        let hit = |payload: Value| -> Ga4Hit { serde_json::from_value(payload).unwrap() };
+        assert_eq!(visitor_key(&hit(json!({ "client_id": "1.2", "events": [] }))), "1.2");
+        assert_eq!(
+            visitor_key(&hit(json!({ "client_id": "1.2", "user_id": "u-9", "events": [] }))),
+            "1.2:u-9"
+        );
+    }
+}
@@ -0,0 +1,237 @@
+//! Shared ingest path for tracking events: sanitize, validate, apply site
+//! policies and hand the event to the processor. Every ingest route goes
+//! through `Ingest::event`, so single and batched events are treated alike.
+
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+use tracing::{debug, error, warn};
+
+use crate::analytics::{AnalyticsEvent, RawTrackingEvent};
+use crate::client_request::ClientRequest;
+use crate::db::SharedDatabase;
+use crate::metrics::MetricsCollector;
+use crate::processing::EventProcessor;
+use crate::quota;
+use crate::sanitize;
+use crate::site_config::SiteConfigCache;
+use crate::storage::s3::S3Service;
+use crate::validation::property_schema::SchemaMode;
+use crate::validation::{self, EventValidator, ValidationError};
+
+pub mod batch;
+pub mod body;
+pub mod compat;
+pub mod ga4;
+pub mod server;
+
+/// Router state shared by the ingest handlers
+pub type RouterState = (
+    SharedDatabase,
+    Arc<EventProcessor>,
+    Option<Arc<MetricsCollector>>,
+    Arc<EventValidator>,
+    Option<Arc<S3Service>>,
+    Arc<SiteConfigCache>,
+);
+
+/// Why an event was not accepted: the response status, the
+/// `events_rejected_total` reason label and a client-facing message.
+#[derive(Debug)]
+pub struct IngestRejection {
+    pub status: StatusCode,
+    pub reason: &'static str,
+    pub message: String,
+}
+
+/// Borrowed handles to everything the ingest path needs, built per request
+/// from the router state.
+pub struct Ingest<'a> {
+    pub processor: &'a EventProcessor,
+    pub metrics: Option<&'a MetricsCollector>,
+    pub validator: &'a EventValidator,
+    pub site_cfg_cache: &'a SiteConfigCache,
+}
+
+impl Ingest<'_> {
+    pub async fn event(
+        &self,
+        client: &ClientRequest,
+        raw_event: RawTrackingEvent,
+    ) -> Result<(), IngestRejection> {
+        self.event_at(client, raw_event, None).await
+    }
+
+    /// Like `event`, but with an event time from an authenticated sender that
+    /// overrides the receive time.
+    pub async fn event_at(
+        &self,
+        client: &ClientRequest,
+        raw_event: RawTrackingEvent,
+        trusted_timestamp: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<(), IngestRejection> {
+        self.accept(client, raw_event, trusted_timestamp, None).await
+    }
+
+    /// Like `event_at`, for an authenticated sender that names the visitor
+    /// itself. `visitor_key` is the visitor identity in place of the request's
+    /// IP and user agent, and the bot checks that would judge the sending
+    /// backend are skipped.
+    pub async fn visitor_event_at(
+        &self,
+        client: &ClientRequest,
+        raw_event: RawTrackingEvent,
+        trusted_timestamp: Option<chrono::DateTime<chrono::Utc>>,
+        visitor_key: String,
+    ) -> Result<(), IngestRejection> {
+        self.accept(client, raw_event, trusted_timestamp, Some(visitor_key)).await
+    }
+
+    async fn accept(
+        &self,
+        client: &ClientRequest,
+        mut raw_event: RawTrackingEvent,
+        trusted_timestamp: Option<chrono::DateTime<chrono::Utc>>,
+        visitor_key: Option<String>,
+    ) -> Result<(), IngestRejection> {
+        let start_time = std::time::Instant::now();
+
+        sanitize::sanitize_event(&mut raw_event, &sanitize::SanitizeConfig::default());
+
+        if raw_event.site_id.is_empty() && self.validator.resolves_site_from_host() {
+            match validation::resolve_site_from_host(self.site_cfg_cache, &raw_event.url) {
+                Ok(site_id) => raw_event.site_id = site_id,
+                Err(e) => {
+                    debug!(reason = %self.validator.get_rejection_reason(&e), "site resolution failed");
+                    return Err(self.reject(StatusCode::BAD_REQUEST, &e));
+                }
+            }
+        }
+
+        let validation_start = std::time::Instant::now();
+
+        let mut validated_event = match self
+            .validator
+            .validate_event(raw_event, client.ip.clone())
+            .await
+        {
+            Ok(validated) => validated,
+            Err(e) => {
+                debug!(reason = %self.validator.get_rejection_reason(&e), "validation failed");
+                warn!("Event validation failed: {}", e);
+
+                let status = match &e {
+                    ValidationError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+                    _ => StatusCode::BAD_REQUEST,
+                };
+                return Err(self.reject(status, &e));
+            }
+        };
+
+        if let Some(metrics) = self.metrics {
+            metrics.record_validation_duration(validation_start.elapsed());
+        }
+
+        let matched_domain = match validation::validate_site_policies(
+            self.site_cfg_cache,
+            &validated_event.raw.site_id,
+            &validated_event.raw.url,
+            &validated_event.ip_address,
+        )
+        .await
+        {
+            Ok(matched_domain) => matched_domain,
+            Err(e) => {
+                debug!(reason = %self.validator.get_rejection_reason(&e), "site-config validation failed");
+                return Err(self.reject(StatusCode::FORBIDDEN, &e));
+            }
+        };
+
+        // Excluded on purpose, so answered with a success status
+        if let Err(e) = validation::check_exclusion_rules(self.site_cfg_cache, &validated_event.raw) {
+            debug!(reason = %self.validator.get_rejection_reason(&e), "event excluded by site rule");
+            return Err(self.reject(StatusCode::ACCEPTED, &e));
+        }
+
+        let mut property_schema_violations = Vec::new();
+        if let Some(cfg) = self.site_cfg_cache.get(&validated_event.raw.site_id) {
+            let violations = self.validator.property_schema_violations(&cfg, &validated_event.raw);
+            if !violations.is_empty() {
+                if let Some(metrics) = self.metrics {
+                    metrics.increment_property_schema_violations(
+                        &validated_event.raw.site_id,
+                        &validated_event.raw.event_name,
+                        violations.len() as u64,
+                    );
+                }
+                if let Err(e) = self.validator.enforce_property_schema(
+                    cfg.property_schema_mode,
+                    &mut validated_event.raw,
+                    &violations,
+                ) {
+                    debug!(reason = %self.validator.get_rejection_reason(&e), "property schema validation failed");
+                    return Err(self.reject(StatusCode::BAD_REQUEST, &e));
+                }
+                if cfg.property_schema_mode == SchemaMode::Tag {
+                    property_schema_violations = violations.iter().map(ToString::to_string).collect();
+                }
+            }
+        }
+
+        if let Err(e) = validation::check_site_quota(self.site_cfg_cache, &validated_event.raw) {
+            debug!(reason = %self.validator.get_rejection_reason(&e), "site quota exceeded");
+            let status = if quota::responds_429() {
+                StatusCode::TOO_MANY_REQUESTS
+            } else {
+                StatusCode::FORBIDDEN
+            };
+            return Err(self.reject(status, &e));
+        }
+
+        debug!("validation passed");
+
+        let mut event = AnalyticsEvent::new(
+            validated_event.raw,
+            validated_event.ip_address,
+            client.user_agent.clone(),
+            client.sec_ch_ua.clone(),
+            client.prefetch,
+        );
+        event.trusted_timestamp = trusted_timestamp;
+        event.visitor_key = visitor_key;
+        event.property_schema_violations = property_schema_violations;
+        event.matched_domain = matched_domain.unwrap_or_default();
+        if let Some(cfg) = self.site_cfg_cache.get(&event.raw.site_id) {
+            event.network_policy = cfg.network_policy.clone();
+            event.campaign_aliases = cfg.campaign_aliases.clone();
+            event.path_rewriter = cfg.path_rewriter.clone();
+            event.path_options = cfg.path_options.clone();
+            event.is_internal = validation::is_internal_traffic(&cfg, &event.ip_address, client.internal_token.as_deref());
+        }
+
+        if let Err(e) = self.processor.process_event(event).await {
+            error!("Failed to process validated event: {}", e);
+            return Ok(());
+        }
+
+        if let Some(metrics) = self.metrics {
+            metrics.increment_events_processed();
+            metrics.record_processing_duration(start_time.elapsed());
+        }
+
+        Ok(())
+    }
+
+    /// Counts the rejection and converts it into the response the client sees
+    pub fn reject(&self, status: StatusCode, error: &ValidationError) -> IngestRejection {
+        let reason = self.validator.get_rejection_reason(error);
+        if let Some(metrics) = self.metrics {
+            metrics.increment_events_rejected(reason);
+        }
+        IngestRejection {
+            status,
+            reason,
+            message: error.to_string(),
+        }
+    }
+}
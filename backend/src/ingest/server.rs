@@ -0,0 +1,165 @@
+//! `/server/event`: ingest for a site's own backend services, authenticated by
+//! a per-site API key. The sender supplies the visitor's IP, user agent and
+//! event time, so the event gets the same geo, UA and session treatment as one
+//! sent by the visitor's browser.
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::analytics::RawTrackingEvent;
+use crate::client_request::{ClientRequest, bearer_token};
+use crate::validation::ValidationError;
+
+use super::{Ingest, RouterState};
+use super::body::DecodedJson;
+
+/// How far in the past a server-supplied timestamp may lie; covers queued
+/// sends and retries without letting senders rewrite history
+pub const MAX_PAST_SKEW_SECS: i64 = 60 * 60;
+/// How far ahead of our clock a server-supplied timestamp may lie
+pub const MAX_FUTURE_SKEW_SECS: i64 = 60;
+
+/// A tracking event plus the visitor attributes a browser would have supplied
+/// through the request itself. `user_agent` and `timestamp` (unix seconds) are
+/// read from the event; an absent timestamp means "now".
+#[derive(Debug, Deserialize)]
+pub struct ServerTrackingEvent {
+    #[serde(flatten)]
+    pub event: RawTrackingEvent,
+    pub visitor_ip: String,
+    #[serde(default)]
+    pub visitor_sec_ch_ua: Option<String>,
+}
+
+pub async fn track_server_event(
+    State((_db, processor, metrics, validator, _s3, site_cfg_cache)): State<RouterState>,
+    headers: HeaderMap,
+    DecodedJson(payload): DecodedJson<ServerTrackingEvent>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let ingest = Ingest {
+        processor: &processor,
+        metrics: metrics.as_deref(),
+        validator: &validator,
+        site_cfg_cache: &site_cfg_cache,
+    };
+
+    // Unknown site and wrong key are indistinguishable to the caller so keys
+    // cannot be used to probe for site IDs
+    let authorized = api_key_from_headers(&headers)
+        .zip(site_cfg_cache.get(&payload.event.site_id))
+        .is_some_and(|(key, cfg)| cfg.accepts_api_key(key));
+    if !authorized {
+        let e = ValidationError::InvalidApiKey("Missing or invalid API key for site".to_string());
+        let rejection = ingest.reject(StatusCode::UNAUTHORIZED, &e);
+        return Err((rejection.status, rejection.message));
+    }
+
+    let timestamp = match payload.event.timestamp {
+        Some(ts) => match trusted_timestamp(ts, Utc::now()) {
+            Ok(ts) => Some(ts),
+            Err(e) => {
+                let rejection = ingest.reject(StatusCode::BAD_REQUEST, &e);
+                return Err((rejection.status, rejection.message));
+            }
+        },
+        None => None,
+    };
+
+    let client = ClientRequest {
+        ip: payload.visitor_ip,
+        user_agent: payload.event.user_agent.clone(),
+        sec_ch_ua: payload.visitor_sec_ch_ua.unwrap_or_default(),
+        prefetch: false,
+        internal_token: None,
+    };
+
+    ingest
+        .event_at(&client, payload.event, timestamp)
+        .await
+        .map(|()| StatusCode::OK)
+        .map_err(|rejection| (rejection.status, rejection.message))
+}
+
+/// Reads the key from `Authorization: Bearer <key>`, falling back to `X-API-Key`
+fn api_key_from_headers(headers: &HeaderMap) -> Option<&str> {
+    bearer_token(headers).or_else(|| {
+        headers
+            .get("x-api-key")
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+    })
+}
+
+/// Accepts a unix-seconds timestamp only within the allowed skew of `now`
+pub(super) fn trusted_timestamp(ts: u64, now: DateTime<Utc>) -> Result<DateTime<Utc>, ValidationError> {
+    let ts = i64::try_from(ts)
+        .ok()
+        .and_then(|secs| DateTime::from_timestamp(secs, 0))
+        .ok_or_else(|| ValidationError::InvalidTimestamp("Timestamp out of range".to_string()))?;
+
+    let skew = (ts - now).num_seconds();
+    if skew > MAX_FUTURE_SKEW_SECS {
+        return Err(ValidationError::InvalidTimestamp(format!(
+            "Timestamp is more than {}s in the future",
+            MAX_FUTURE_SKEW_SECS
+        )));
+    }
+    if -skew > MAX_PAST_SKEW_SECS {
+        return Err(ValidationError::InvalidTimestamp(format!(
+            "Timestamp is more than {}s in the past",
+            MAX_PAST_SKEW_SECS
+        )));
+    }
+    Ok(ts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{HeaderValue, header::AUTHORIZATION};
+
+    #[test]
+    fn reads_bearer_or_x_api_key() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(api_key_from_headers(&headers), None);
+
+        headers.insert("x-api-key", HeaderValue::from_static("fallback"));
+        assert_eq!(api_key_from_headers(&headers), Some("fallback"));
+
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("bearer secret"));
+        assert_eq!(api_key_from_headers(&headers), Some("secret"));
+
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Basic dXNlcjpwYXNz"));
+        assert_eq!(api_key_from_headers(&headers), Some("fallback"));
+    }
+
+    #[test]
+    fn timestamp_skew_is_bounded() {
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let at = |offset: i64| (now.timestamp() + offset) as u64;
+
+        assert_eq!(trusted_timestamp(at(-30), now).unwrap().timestamp(), now.timestamp() - 30);
+        assert!(trusted_timestamp(at(-MAX_PAST_SKEW_SECS), now).is_ok());
+        assert!(trusted_timestamp(at(-MAX_PAST_SKEW_SECS - 1), now).is_err());
+        assert!(trusted_timestamp(at(MAX_FUTURE_SKEW_SECS), now).is_ok());
+        assert!(trusted_timestamp(at(MAX_FUTURE_SKEW_SECS + 1), now).is_err());
+        assert!(trusted_timestamp(u64::MAX, now).is_err());
+    }
+
+    #[test]
+    fn visitor_fields_sit_beside_the_event() {
+        let payload: ServerTrackingEvent = serde_json::from_str(
+            r#"{"site_id":"s","event_name":"pageview","is_custom_event":false,"properties":"","url":"https://example.com/","referrer":null,"user_agent":"Mozilla/5.0","screen_resolution":"","outbound_link_url":null,"cwv_cls":null,"cwv_lcp":null,"cwv_inp":null,"cwv_fcp":null,"cwv_ttfb":null,"scroll_depth_percentage":null,"scroll_depth_pixels":null,"error_exceptions":null,"page_duration_seconds":null,"timestamp":1700000000,"visitor_ip":"203.0.113.7"}"#,
+        )
+        .unwrap();
+        assert_eq!(payload.event.site_id, "s");
+        assert_eq!(payload.event.timestamp, Some(1_700_000_000));
+        assert_eq!(payload.visitor_ip, "203.0.113.7");
+        assert_eq!(payload.visitor_sec_ch_ua, None);
+    }
+}
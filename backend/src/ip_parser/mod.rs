@@ -1,7 +1,10 @@
 use std::net::IpAddr;
 use std::str::FromStr;
+use std::sync::OnceLock;
 
 use axum::http::HeaderMap;
+use ipnet::IpNet;
+use tracing::warn;
 
 pub fn anonymize_ip(ip: &str) -> Option<String> {
     if let Ok(ip_addr) = ip.parse::<IpAddr>() {
@@ -29,6 +32,131 @@ const HEADER_CANDIDATES: [&str; 5] = [
     "forwarded",
 ];
 
+/// Which forwarding header carries the client address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderMode {
+    /// First address found in any of `HEADER_CANDIDATES`
+    Legacy,
+    /// `cf-connecting-ip` only
+    Cloudflare,
+    /// Right-most `x-forwarded-for` entry that is not a trusted proxy
+    XForwardedFor,
+    /// Right-most RFC 7239 `forwarded` `for=` that is not a trusted proxy
+    Forwarded,
+    /// Ignore headers; always the socket peer
+    None,
+}
+
+impl FromStr for HeaderMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "" | "legacy" => Ok(Self::Legacy),
+            "cloudflare" => Ok(Self::Cloudflare),
+            "xff" | "x-forwarded-for" => Ok(Self::XForwardedFor),
+            "forwarded" | "rfc7239" => Ok(Self::Forwarded),
+            "none" | "peer" => Ok(Self::None),
+            other => Err(format!("unknown client IP header mode '{}'", other)),
+        }
+    }
+}
+
+/// Which peers may set forwarding headers, and which header to read
+#[derive(Debug, Clone)]
+pub struct ProxyConfig {
+    pub mode: HeaderMode,
+    /// Empty trusts every peer, which keeps headers spoofable by direct clients
+    pub trusted_proxies: Vec<IpNet>,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self { mode: HeaderMode::Legacy, trusted_proxies: Vec::new() }
+    }
+}
+
+impl ProxyConfig {
+    /// Parses a header mode and a comma-separated list of CIDRs or bare IPs
+    pub fn parse(mode: &str, trusted_proxies: &str) -> Result<Self, String> {
+        let mode = mode.parse()?;
+        let trusted_proxies = trusted_proxies
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                IpNet::from_str(entry)
+                    .or_else(|_| IpAddr::from_str(entry).map(IpNet::from))
+                    .map_err(|_| format!("invalid trusted proxy '{}'", entry))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { mode, trusted_proxies })
+    }
+
+    fn is_trusted(&self, ip: &IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|net| net.contains(ip))
+    }
+
+    /// Client address for a request from `peer`. Headers are only believed
+    /// when the peer is a trusted proxy; otherwise (or when the header yields
+    /// nothing) the peer itself is the client.
+    pub fn client_ip(&self, headers: &HeaderMap, peer: Option<IpAddr>) -> Option<IpAddr> {
+        let peer_trusted = self.trusted_proxies.is_empty() || peer.is_some_and(|p| self.is_trusted(&p));
+        if !peer_trusted {
+            return peer;
+        }
+
+        let from_headers = match self.mode {
+            HeaderMode::Legacy => parse_ip(headers).ok(),
+            HeaderMode::Cloudflare => header_value(headers, "cf-connecting-ip").and_then(|v| parse_ip_str(v.trim())),
+            HeaderMode::XForwardedFor => header_value(headers, "x-forwarded-for")
+                .and_then(|v| self.rightmost_untrusted(v.split(',').map(str::trim).map(parse_ip_str))),
+            HeaderMode::Forwarded => header_value(headers, "forwarded")
+                .and_then(|v| self.rightmost_untrusted(v.split(',').map(forwarded_for))),
+            HeaderMode::None => None,
+        };
+        from_headers.or(peer)
+    }
+
+    /// Walks hops right to left, skipping our own proxies. An unparsable hop
+    /// ends the walk: nothing left of it can be trusted.
+    fn rightmost_untrusted(&self, hops: impl DoubleEndedIterator<Item = Option<IpAddr>>) -> Option<IpAddr> {
+        let mut leftmost = None;
+        for hop in hops.rev() {
+            let ip = hop?;
+            if !self.is_trusted(&ip) {
+                return Some(ip);
+            }
+            leftmost = Some(ip);
+        }
+        leftmost
+    }
+}
+
+static PROXY_CONFIG: OnceLock<ProxyConfig> = OnceLock::new();
+
+pub fn initialize(config: ProxyConfig) {
+    if config.trusted_proxies.is_empty() && config.mode != HeaderMode::None {
+        warn!("TRUSTED_PROXIES is not set: client IP headers are accepted from any peer and can be spoofed");
+    }
+    if PROXY_CONFIG.set(config).is_err() {
+        warn!("Proxy config already initialized, ignoring");
+    }
+}
+
+/// Client address under the global proxy config (legacy behavior until initialized)
+pub fn client_ip(headers: &HeaderMap, peer: Option<IpAddr>) -> Option<IpAddr> {
+    static DEFAULT: OnceLock<ProxyConfig> = OnceLock::new();
+    PROXY_CONFIG
+        .get()
+        .unwrap_or_else(|| DEFAULT.get_or_init(ProxyConfig::default))
+        .client_ip(headers, peer)
+}
+
+fn header_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
 pub fn parse_ip(headers: &HeaderMap) -> Result<IpAddr, ()> {
     for header in HEADER_CANDIDATES {
         if let Some(value) = headers.get(header) {
@@ -63,6 +191,18 @@ pub fn parse_ip_str(s: &str) -> Option<IpAddr> {
     }
 }
 
+/// The `for=` address of one `forwarded` element
+fn forwarded_for(element: &str) -> Option<IpAddr> {
+    element
+        .split(';')
+        .filter_map(|directive| {
+            let (key, value) = directive.trim().split_once('=')?;
+            key.eq_ignore_ascii_case("for").then_some(value)
+        })
+        .next()
+        .and_then(|value| parse_ip_str(value.trim_matches('"')))
+}
+
 fn parse_forwarded_header(value: &str) -> Option<IpAddr> {
     for proxy_entry in value.split(',') {
         for directive in proxy_entry.split(';') {
@@ -211,4 +351,61 @@ mod tests {
             IpAddr::from_str("2001:db8::1").unwrap()
         );
     }
+
+    fn proxy_config(mode: &str, proxies: &str) -> ProxyConfig {
+        ProxyConfig::parse(mode, proxies).unwrap()
+    }
+
+    fn peer(ip: &str) -> Option<IpAddr> {
+        Some(IpAddr::from_str(ip).unwrap())
+    }
+
+    #[test]
+    fn untrusted_peer_headers_are_ignored() {
+        let config = proxy_config("legacy", "10.0.0.0/8");
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "1.2.3.4".parse().unwrap());
+        assert_eq!(config.client_ip(&headers, peer("203.0.113.9")), peer("203.0.113.9"));
+        assert_eq!(config.client_ip(&headers, peer("10.1.2.3")), peer("1.2.3.4"));
+        assert_eq!(config.client_ip(&headers, None), None);
+    }
+
+    #[test]
+    fn xff_takes_rightmost_untrusted_hop() {
+        let config = proxy_config("xff", "10.0.0.0/8, 192.168.1.1");
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "6.6.6.6, 1.2.3.4, 192.168.1.1, 10.0.0.2".parse().unwrap());
+        assert_eq!(config.client_ip(&headers, peer("10.0.0.1")), peer("1.2.3.4"));
+
+        headers.insert("x-forwarded-for", "garbage, 10.0.0.2".parse().unwrap());
+        assert_eq!(config.client_ip(&headers, peer("10.0.0.1")), peer("10.0.0.1"));
+    }
+
+    #[test]
+    fn forwarded_takes_rightmost_untrusted_hop() {
+        let config = proxy_config("forwarded", "10.0.0.0/8");
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "forwarded",
+            "for=6.6.6.6, for=\"[2001:db8::1]:443\";proto=https, For=10.0.0.2".parse().unwrap(),
+        );
+        assert_eq!(config.client_ip(&headers, peer("10.0.0.1")), peer("2001:db8::1"));
+    }
+
+    #[test]
+    fn cloudflare_mode_reads_only_cf_header() {
+        let config = proxy_config("cloudflare", "173.245.48.0/20");
+        let mut headers = HeaderMap::new();
+        headers.insert("x-real-ip", "5.6.7.8".parse().unwrap());
+        assert_eq!(config.client_ip(&headers, peer("173.245.48.1")), peer("173.245.48.1"));
+        headers.insert("cf-connecting-ip", "1.2.3.4".parse().unwrap());
+        assert_eq!(config.client_ip(&headers, peer("173.245.48.1")), peer("1.2.3.4"));
+    }
+
+    #[test]
+    fn rejects_bad_settings() {
+        assert!(ProxyConfig::parse("bogus", "").is_err());
+        assert!(ProxyConfig::parse("xff", "10.0.0.0/33").is_err());
+        assert_eq!(proxy_config("", "").mode, HeaderMode::Legacy);
+    }
 }
@@ -17,6 +17,7 @@ pub mod notifications;
 pub mod outbound_link;
 pub mod postgres;
 pub mod processing;
+pub mod quota;
 pub mod referrer;
 pub mod salt;
 pub mod sanitize;
@@ -29,7 +30,7 @@ pub mod utils;
 pub mod validation;
 
 pub use analytics::{AnalyticsEvent, generate_site_id};
-pub use campaign::{CampaignInfo, parse_campaign_params};
+pub use campaign::{CampaignInfo, parse_campaign_params, parse_campaign_params_with_aliases};
 pub use config::Config;
 pub use db::{Database, SharedDatabase};
 pub use metrics::MetricsCollector;
@@ -9,9 +9,10 @@ use std::sync::Arc;
 use std::net::SocketAddr;
 use std::time::Duration;
 use tower_http::cors::CorsLayer;
-use tracing::{debug, error, info, warn};
+use tracing::{error, info, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+mod admin;
 mod analytics;
 mod asn;
 mod bot_detection;
@@ -24,12 +25,14 @@ mod email;
 mod error_fingerprint;
 mod geoip;
 mod geoip_updater;
+mod ingest;
 mod metrics;
 mod monitor;
 mod notifications;
 mod outbound_link;
 mod postgres;
 mod processing;
+mod quota;
 mod referrer;
 mod salt;
 mod sanitize;
@@ -44,15 +47,17 @@ mod visitor;
 mod utils;
 mod validation;
 
-use analytics::{AnalyticsEvent, RawTrackingEvent, generate_site_id};
+use analytics::{RawTrackingEvent, generate_site_id};
 use clickhouse::ClickHouseClient;
 use client_request::ClientRequest;
-use db::{Database, SharedDatabase};
+use db::{Database, SharedDatabase, SpoolWriter};
 use geoip::GeoIpService;
 use geoip_updater::GeoIpUpdater;
+use ingest::Ingest;
+use ingest::body::{BodyTransport, DecodedJson};
 use metrics::MetricsCollector;
 use postgres::PostgresPool;
-use processing::EventProcessor;
+use processing::{EnrichmentRegistry, EventProcessor};
 use site_config::{RefreshConfig, SiteConfigCache, SiteConfigDataSource, SiteConfigRepository};
 use storage::s3::S3Service;
 use validation::{EventValidator, ValidationConfig};
@@ -80,10 +85,16 @@ async fn main() {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    referrer::initialize(&config.referrer_db_path);
+    referrer::initialize(&config.referrer_db_path, &config.custom_referrers_path);
+    referrer::channel::initialize(&config.ga4_source_categories_path);
 
     ua_parser::initialize(&config.ua_regexes_path);
 
+    ip_parser::initialize(
+        ip_parser::ProxyConfig::parse(&config.client_ip_header_mode, &config.trusted_proxies)
+            .expect("Invalid CLIENT_IP_HEADER_MODE / TRUSTED_PROXIES"),
+    );
+
     let ip_addr = config
         .server_host
         .parse::<std::net::IpAddr>()
@@ -117,11 +128,20 @@ async fn main() {
     };
 
     bot_detection::warm();
-    let validator = Arc::new(EventValidator::new(ValidationConfig::default()));
+    let validator = Arc::new(EventValidator::new(ValidationConfig {
+        resolve_site_from_host: config.resolve_site_from_host,
+        ..ValidationConfig::default()
+    }));
 
     let clickhouse = Arc::new(ClickHouseClient::new(&config));
     info!("ClickHouse client initialized");
 
+    quota::initialize(quota::QuotaSettings {
+        respond_429: config.quota_respond_429,
+        reconcile_interval: config.quota_reconcile_interval,
+    });
+    quota::spawn_reconciler(Arc::clone(&clickhouse));
+
     let metrics_collector = if config.enable_monitoring {
         let collector = MetricsCollector::new()
             .expect("Failed to initialize metrics collector")
@@ -133,10 +153,11 @@ async fn main() {
         None
     };
 
-    let (db, event_tx, bot_event_tx, inserter_handle, bot_inserter_handle) =
+    let (mut db, event_tx, bot_event_tx, inserter_handle, bot_inserter_handle) =
         Database::new(Arc::clone(&clickhouse), config.clone(), metrics_collector.clone())
             .await
             .expect("Failed to initialize database");
+    let export_task = db.take_export_task();
 
     if let Some(metrics) = metrics_collector.clone() {
         spawn_pressure_sampler(metrics, event_tx.downgrade());
@@ -159,15 +180,30 @@ async fn main() {
 
     let db = Arc::new(db);
 
-    let processor = Arc::new(EventProcessor::new(
-        geoip_service,
+    let enrichment = EnrichmentRegistry::with_builtins(geoip_service)
+        .build(&config.enrichment_stages)
+        .expect("Invalid ENRICHMENT_STAGES");
+    info!(stages = ?enrichment.stage_names(), "Event enrichment pipeline configured");
+    if !enrichment.stage_names().contains(&"geo") {
+        warn!("ENRICHMENT_STAGES leaves out 'geo'; country rules in site network policies will never match");
+    }
+
+    let mut processor = EventProcessor::new(
+        enrichment,
         asn_service,
         event_tx,
         bot_event_tx,
         metrics_collector.clone(),
         config.is_development,
         config.enable_bot_event_log,
-    ));
+    );
+    let mut spool_writer_handle = None;
+    if let Some(spool) = db.ingest_spool() {
+        let (writer, handle) = SpoolWriter::spawn(spool, metrics_collector.clone());
+        processor = processor.with_spool(writer);
+        spool_writer_handle = Some(handle);
+    }
+    let processor = Arc::new(processor);
 
     let site_config_pool = Arc::new(
         PostgresPool::new(
@@ -204,6 +240,12 @@ async fn main() {
         SiteConfigCache::initialize(site_config_repo, refresh_config, metrics_collector.clone())
             .await
             .expect("Failed to init SiteConfigCache");
+    quota::seed(&clickhouse, site_cfg_cache.site_ids_with_quota()).await;
+    if config.config_change_listen
+        && let Err(e) = site_cfg_cache.listen_for_changes(&config.site_config_database_url)
+    {
+        warn!(error = %e, "Site config change notifications disabled; relying on polling");
+    }
 
     let notification_engine = crate::notifications::initialize_notification_engine(
         Arc::clone(&site_config_pool),
@@ -244,6 +286,15 @@ async fn main() {
 		.route("/health", get(health_check))
 		.route("/event", post(track_event))
 		.route("/track", post(track_event)) // Deprecated: use /event instead
+		.route(
+			"/events",
+			post(ingest::batch::track_events)
+				.layer(DefaultBodyLimit::max(ingest::batch::BATCH_BODY_LIMIT_BYTES)),
+		)
+		.route("/server/event", post(ingest::server::track_server_event))
+		.route("/api/event", post(ingest::compat::track_plausible_event))
+		.route("/api/send", post(ingest::compat::track_umami_event))
+		.route("/mp/collect", post(ingest::ga4::collect_ga4_hit))
 		.route("/site-id", get(generate_site_id_handler))
 		.route("/metrics", get(metrics_handler));
 
@@ -261,9 +312,18 @@ async fn main() {
         info!("Session replay endpoints disabled by configuration");
     }
 
+    if let Some(token) = config.admin_api_token.clone() {
+        admin::initialize(token);
+        router = router
+            .route("/admin/dead-letter/replay", post(admin::replay_dead_letters))
+            .route("/admin/sites/{site_id}/path-rules/preview", post(admin::preview_path_rules));
+    } else {
+        info!("Admin endpoints disabled (set ADMIN_API_TOKEN to enable)");
+    }
+
     let app = router
         .fallback(fallback_handler)
-        .layer(DefaultBodyLimit::max(64 * 1024))
+        .layer(DefaultBodyLimit::max(ingest::body::INGEST_BODY_LIMIT_BYTES))
         .with_state((
             db,
             processor,
@@ -307,6 +367,19 @@ async fn main() {
             Ok(()) => info!("Bot event pipeline drained"),
             Err(e) => error!("Bot event inserter task failed during drain: {}", e),
         }
+        if let Some(handle) = spool_writer_handle {
+            match handle.await {
+                Ok(()) => info!("Ingest spool writer drained"),
+                Err(e) => error!("Ingest spool writer failed during drain: {}", e),
+            }
+        }
+        // After the inserters, so their last accepted batches are queued
+        if let Some(task) = export_task {
+            match task.finish().await {
+                Ok(()) => info!("Event export drained"),
+                Err(e) => error!("Event export sender failed during drain: {}", e),
+            }
+        }
         monitor::clickhouse_writer::flush_all_writers().await;
     };
     if tokio::time::timeout(SHUTDOWN_DEADLINE, drain).await.is_err() {
@@ -422,79 +495,24 @@ async fn track_event(
         Arc<SiteConfigCache>,
     )>,
     client: ClientRequest,
-    Json(mut raw_event): Json<RawTrackingEvent>,
+    transport: BodyTransport,
+    DecodedJson(raw_event): DecodedJson<RawTrackingEvent>,
 ) -> Result<StatusCode, (StatusCode, String)> {
-    let start_time = std::time::Instant::now();
-    let ip_address = client.ip.clone();
-
-    sanitize::sanitize_event(&mut raw_event, &sanitize::SanitizeConfig::default());
-
-    let validation_start = std::time::Instant::now();
-
-    let validated_event = match validator
-        .validate_event(raw_event, ip_address.clone())
-        .await
-    {
-        Ok(validated) => validated,
-        Err(e) => {
-            debug!(reason = %validator.get_rejection_reason(&e), "validation failed");
-            if let Some(metrics_collector) = &metrics {
-                metrics_collector.increment_events_rejected(&validator.get_rejection_reason(&e));
-            }
-
-            warn!("Event validation failed: {}", e);
-
-            let status = match &e {
-                validation::ValidationError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
-                _ => StatusCode::BAD_REQUEST,
-            };
-
-            return Err((status, e.to_string()));
-        }
-    };
-
-    if let Some(metrics_collector) = &metrics {
-        metrics_collector.record_validation_duration(validation_start.elapsed());
-    }
-
-    let site_policy_result = validation::validate_site_policies(
-        &site_cfg_cache,
-        &validated_event.raw.site_id,
-        &validated_event.raw.url,
-        &validated_event.ip_address,
-    )
-    .await;
-
-    if let Err(e) = site_policy_result {
-        debug!(reason = %validator.get_rejection_reason(&e), "site-config validation failed");
-        if let Some(metrics_collector) = &metrics {
-            metrics_collector.increment_events_rejected(&validator.get_rejection_reason(&e));
-        }
-        return Err((StatusCode::FORBIDDEN, e.to_string()));
-    }
-
-    debug!("validation passed");
-
-    let event = AnalyticsEvent::new(
-        validated_event.raw,
-        validated_event.ip_address,
-        client.user_agent,
-        client.sec_ch_ua,
-        client.prefetch,
-    );
-
-    if let Err(e) = processor.process_event(event).await {
-        error!("Failed to process validated event: {}", e);
-        return Ok(StatusCode::OK);
-    }
-
-    if let Some(metrics_collector) = metrics {
-        let processing_duration = start_time.elapsed();
-        metrics_collector.increment_events_processed();
-        metrics_collector.record_processing_duration(processing_duration);
+    if let Some(metrics) = &metrics {
+        metrics.increment_events_received(transport.as_str(), 1);
     }
+    let ingest = Ingest {
+        processor: &processor,
+        metrics: metrics.as_deref(),
+        validator: &validator,
+        site_cfg_cache: &site_cfg_cache,
+    };
 
-    Ok(StatusCode::OK)
+    ingest
+        .event(&client, raw_event)
+        .await
+        .map(|()| StatusCode::OK)
+        .map_err(|rejection| (rejection.status, rejection.message))
 }
 
 async fn metrics_handler(
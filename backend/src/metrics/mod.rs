@@ -8,6 +8,8 @@ use sysinfo::{Pid, ProcessesToUpdate, System, get_current_pid};
 use tokio::sync::RwLock;
 use tracing::{error, info};
 
+use crate::db::spool::SpoolStats;
+
 #[derive(Clone)]
 pub struct MetricsCollector {
     registry: Registry,
@@ -22,11 +24,21 @@ pub struct MetricsCollector {
     process_memory_usage: Gauge,
     events_processed_total: IntCounter,
     events_processing_duration: Histogram,
+    enrichment_stage_duration: HistogramVec,
 
     // Event rejection and validation metrics
     events_rejected_total: IntCounterVec,
+    events_received_total: IntCounterVec,
     bot_events_detected_total: IntCounterVec,
     events_dropped_total: IntCounterVec,
+    events_dead_lettered_total: IntCounterVec,
+    property_schema_violations_total: IntCounterVec,
+    events_spooled_total: IntCounter,
+    events_exported_total: IntCounter,
+    events_export_dropped_total: IntCounterVec,
+    ingest_spool_bytes: Gauge,
+    ingest_spool_segments: Gauge,
+    ingest_spool_oldest_age_seconds: Gauge,
     validation_duration: Histogram,
 
     // Ingest pipeline pressure
@@ -95,6 +107,15 @@ impl MetricsCollector {
             "Total number of analytics events processed",
         ))?;
 
+        let enrichment_stage_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "analytics_enrichment_stage_duration_seconds",
+                "Time spent in each event enrichment stage",
+            )
+            .buckets(prometheus::exponential_buckets(0.00001, 4.0, 8)?),
+            &["stage"],
+        )?;
+
         let events_processing_duration = Histogram::with_opts(HistogramOpts::new(
             "analytics_events_processing_duration_seconds",
             "Time spent processing analytics events",
@@ -108,6 +129,14 @@ impl MetricsCollector {
             &["reason"],
         )?;
 
+        let events_received_total = IntCounterVec::new(
+            Opts::new(
+                "analytics_events_received_total",
+                "Total tracking events received, by the body transport the client used",
+            ),
+            &["transport"],
+        )?;
+
         let bot_events_detected_total = IntCounterVec::new(
             Opts::new(
                 "analytics_bot_events_detected_total",
@@ -116,6 +145,14 @@ impl MetricsCollector {
             &["reason"],
         )?;
 
+        let property_schema_violations_total = IntCounterVec::new(
+            Opts::new(
+                "analytics_property_schema_violations_total",
+                "Total number of custom event properties that broke the site's schema",
+            ),
+            &["site_id", "event_name"],
+        )?;
+
         let events_dropped_total = IntCounterVec::new(
             Opts::new(
                 "analytics_events_dropped_total",
@@ -124,6 +161,47 @@ impl MetricsCollector {
             &["reason", "table"],
         )?;
 
+        let events_dead_lettered_total = IntCounterVec::new(
+            Opts::new(
+                "analytics_events_dead_lettered_total",
+                "Total rows of permanently rejected batches written to the dead-letter spool",
+            ),
+            &["table"],
+        )?;
+
+        let events_spooled_total = IntCounter::with_opts(Opts::new(
+            "analytics_events_spooled_total",
+            "Total events written to the on-disk ingest spool because the ingest channel was full",
+        ))?;
+
+        let events_exported_total = IntCounter::with_opts(Opts::new(
+            "analytics_events_exported_total",
+            "Total events delivered to the HTTP export endpoint",
+        ))?;
+
+        let events_export_dropped_total = IntCounterVec::new(
+            Opts::new(
+                "analytics_events_export_dropped_total",
+                "Total events left out of the HTTP export; ClickHouse inserts are unaffected",
+            ),
+            &["reason"],
+        )?;
+
+        let ingest_spool_bytes = Gauge::with_opts(Opts::new(
+            "analytics_ingest_spool_bytes",
+            "Bytes of events waiting in the on-disk ingest spool",
+        ))?;
+
+        let ingest_spool_segments = Gauge::with_opts(Opts::new(
+            "analytics_ingest_spool_segments",
+            "Segment files in the on-disk ingest spool",
+        ))?;
+
+        let ingest_spool_oldest_age_seconds = Gauge::with_opts(Opts::new(
+            "analytics_ingest_spool_oldest_age_seconds",
+            "Age of the oldest segment waiting in the on-disk ingest spool",
+        ))?;
+
         let ingest_channel_depth = Gauge::with_opts(Opts::new(
             "analytics_ingest_channel_depth",
             "Events waiting in the ingest channel",
@@ -245,9 +323,19 @@ impl MetricsCollector {
         registry.register(Box::new(process_memory_usage.clone()))?;
         registry.register(Box::new(events_processed_total.clone()))?;
         registry.register(Box::new(events_processing_duration.clone()))?;
+        registry.register(Box::new(enrichment_stage_duration.clone()))?;
         registry.register(Box::new(events_rejected_total.clone()))?;
+        registry.register(Box::new(events_received_total.clone()))?;
         registry.register(Box::new(bot_events_detected_total.clone()))?;
         registry.register(Box::new(events_dropped_total.clone()))?;
+        registry.register(Box::new(events_dead_lettered_total.clone()))?;
+        registry.register(Box::new(property_schema_violations_total.clone()))?;
+        registry.register(Box::new(events_spooled_total.clone()))?;
+        registry.register(Box::new(events_exported_total.clone()))?;
+        registry.register(Box::new(events_export_dropped_total.clone()))?;
+        registry.register(Box::new(ingest_spool_bytes.clone()))?;
+        registry.register(Box::new(ingest_spool_segments.clone()))?;
+        registry.register(Box::new(ingest_spool_oldest_age_seconds.clone()))?;
         registry.register(Box::new(ingest_channel_depth.clone()))?;
         registry.register(Box::new(inserter_batch_rows.clone()))?;
         registry.register(Box::new(inserter_retry_attempts.clone()))?;
@@ -284,9 +372,19 @@ impl MetricsCollector {
             process_memory_usage,
             events_processed_total,
             events_processing_duration,
+            enrichment_stage_duration,
             events_rejected_total,
+            events_received_total,
             bot_events_detected_total,
             events_dropped_total,
+            events_dead_lettered_total,
+            property_schema_violations_total,
+            events_spooled_total,
+            events_exported_total,
+            events_export_dropped_total,
+            ingest_spool_bytes,
+            ingest_spool_segments,
+            ingest_spool_oldest_age_seconds,
             ingest_channel_depth,
             inserter_batch_rows,
             inserter_retry_attempts,
@@ -371,6 +469,24 @@ impl MetricsCollector {
             .observe(duration.as_secs_f64());
     }
 
+    pub fn increment_property_schema_violations(&self, site_id: &str, event_name: &str, count: u64) {
+        self.property_schema_violations_total
+            .with_label_values(&[site_id, event_name])
+            .inc_by(count);
+    }
+
+    pub fn record_enrichment_stage_duration(&self, stage: &str, duration: Duration) {
+        self.enrichment_stage_duration
+            .with_label_values(&[stage])
+            .observe(duration.as_secs_f64());
+    }
+
+    pub fn increment_events_received(&self, transport: &str, count: u64) {
+        self.events_received_total
+            .with_label_values(&[transport])
+            .inc_by(count);
+    }
+
     pub fn increment_events_rejected(&self, reason: &str) {
         self.events_rejected_total
             .with_label_values(&[reason])
@@ -389,6 +505,32 @@ impl MetricsCollector {
             .inc_by(count);
     }
 
+    pub fn increment_events_exported(&self, count: u64) {
+        self.events_exported_total.inc_by(count);
+    }
+
+    pub fn increment_events_export_dropped(&self, reason: &str, count: u64) {
+        self.events_export_dropped_total
+            .with_label_values(&[reason])
+            .inc_by(count);
+    }
+
+    pub fn increment_events_dead_lettered(&self, table: &str, count: u64) {
+        self.events_dead_lettered_total
+            .with_label_values(&[table])
+            .inc_by(count);
+    }
+
+    pub fn increment_events_spooled(&self) {
+        self.events_spooled_total.inc();
+    }
+
+    pub fn set_ingest_spool_stats(&self, stats: SpoolStats) {
+        self.ingest_spool_bytes.set(stats.bytes as f64);
+        self.ingest_spool_segments.set(stats.segments as f64);
+        self.ingest_spool_oldest_age_seconds.set(stats.oldest_age_secs);
+    }
+
     pub fn record_validation_duration(&self, duration: Duration) {
         self.validation_duration.observe(duration.as_secs_f64());
     }
@@ -10,8 +10,12 @@ use tracing::{debug, info, warn};
 
 use crate::metrics::MetricsCollector;
 use crate::monitor::{MonitorCheck, MonitorCheckDataSource};
+use crate::postgres::PostgresError;
+use crate::postgres::listen;
 use crate::utils::spawn_supervised;
 
+/// NOTIFY channel fed by the `MonitorCheck` trigger
+pub const CHANGE_CHANNEL: &str = "monitor_check_changed";
 const HEALTH_CHECK_INTERVAL: StdDuration = StdDuration::from_secs(30);
 const PARTIAL_REFRESH_INTERVAL: StdDuration = StdDuration::from_secs(30);
 const FULL_REFRESH_INTERVAL: StdDuration = StdDuration::from_secs(180);
@@ -89,6 +93,26 @@ impl MonitorCache {
         Ok(cache)
     }
 
+    /// Refreshes as soon as a change is announced on `CHANGE_CHANNEL`. Always a
+    /// full refresh: partial refreshes do not see disabled or deleted checks.
+    pub fn listen_for_changes(self: &Arc<Self>, database_url: &str) -> Result<(), PostgresError> {
+        let changes =
+            listen::spawn_change_listener(database_url, "betterlytics_monitor_listen", CHANGE_CHANNEL, None)?;
+        let this = Arc::clone(self);
+        spawn_supervised("monitor_cache_notify_refresh", move || {
+            let (this, changes) = (Arc::clone(&this), Arc::clone(&changes));
+            async move {
+                let mut changes = changes.lock().await;
+                while listen::next_coalesced(&mut changes).await.is_some() {
+                    if let Err(err) = this.perform_full_refresh().await {
+                        this.handle_refresh_error("notify", err).await;
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+
     pub fn snapshot(&self) -> Vec<Arc<MonitorCheck>> {
         let map = self.checks.load();
         map.values().cloned().collect()
@@ -83,6 +83,11 @@ async fn run_monitoring_init_loop(
                 continue;
             }
         };
+        if config.config_change_listen
+            && let Err(err) = monitor_cache.listen_for_changes(&monitor_db_url)
+        {
+            warn!(error = ?err, "Monitor change notifications disabled; relying on polling");
+        }
 
         let probe =
             match MonitorProbe::new(std::time::Duration::from_millis(DEFAULT_PROBE_TIMEOUT_MS)) {
@@ -9,10 +9,13 @@ use tokio::time::{interval, MissedTickBehavior};
 use tracing::{debug, warn};
 
 use crate::postgres::PostgresError;
+use crate::postgres::listen::{self, RefreshKind};
 use crate::utils::spawn_supervised;
 
 use super::repository::{IntegrationDataSource, IntegrationRecord};
 
+/// NOTIFY channel fed by the `DashboardIntegration` trigger
+pub const CHANGE_CHANNEL: &str = "integration_changed";
 const HEALTH_CHECK_INTERVAL: StdDuration = StdDuration::from_secs(30);
 
 #[derive(Clone, Copy, Debug)]
@@ -83,6 +86,34 @@ impl IntegrationCache {
         Ok(cache)
     }
 
+    /// Refreshes as soon as a change is announced on `CHANGE_CHANNEL`; the
+    /// polling refreshes keep running as the fallback
+    pub fn listen_for_changes(self: &Arc<Self>, database_url: &str) -> Result<(), PostgresError> {
+        let changes = listen::spawn_change_listener(
+            database_url,
+            "betterlytics_integrations_listen",
+            CHANGE_CHANNEL,
+            Some("DashboardIntegration"),
+        )?;
+        let this = Arc::clone(self);
+        spawn_supervised("integration_cache_notify_refresh", move || {
+            let (this, changes) = (Arc::clone(&this), Arc::clone(&changes));
+            async move {
+                let mut changes = changes.lock().await;
+                while let Some(kind) = listen::next_coalesced(&mut changes).await {
+                    let result = match kind {
+                        RefreshKind::Partial => this.perform_partial_refresh().await,
+                        RefreshKind::Full => this.perform_full_refresh().await,
+                    };
+                    if let Err(err) = result {
+                        warn!(stage = kind.as_str(), error = ?err, "integration cache refresh failed");
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+
     pub fn get(&self, dashboard_id: &str) -> Vec<Arc<IntegrationConfig>> {
         let map = self.integrations.load();
         map.get(dashboard_id).cloned().unwrap_or_default()
@@ -10,6 +10,7 @@ use std::collections::HashMap;
 use std::sync::Arc;
 
 use anyhow::Context;
+use tracing::warn;
 
 use crate::clickhouse::ClickHouseClient;
 use crate::config::Config;
@@ -37,6 +38,11 @@ pub async fn initialize_notification_engine(
         Arc::new(IntegrationRepository::new(pool, config.integration_encryption_key));
 
     let cache = IntegrationCache::initialize(data_source, IntegrationCacheConfig::default()).await?;
+    if config.config_change_listen
+        && let Err(e) = cache.listen_for_changes(&config.site_config_database_url)
+    {
+        warn!(error = %e, "Integration change notifications disabled; relying on polling");
+    }
     let seeded_keys = history::load_recent_event_keys(&clickhouse).await?;
     let history_writer = new_notification_history_writer(
         clickhouse,
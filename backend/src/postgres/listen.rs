@@ -0,0 +1,217 @@
+//! `LISTEN`/`NOTIFY` change signals for the Postgres-backed caches. Triggers
+//! on the config tables publish `{"table": ..., "op": ...}` on a channel per
+//! cache; each cache holds one dedicated connection listening on its channel
+//! and refreshes as soon as a change is announced. The polling loops keep
+//! running as the fallback.
+//!
+//! Notifications sent while the connection is down are lost, so every
+//! (re)connect is answered with a full refresh. A half-open connection
+//! delivers nothing and never fails on its own, so the listener also pings
+//! the server and reconnects when the ping does not come back.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Deserialize;
+use tokio::sync::Mutex;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::time::{Instant, MissedTickBehavior, interval_at, sleep, timeout};
+use tokio_postgres::{AsyncMessage, Config as PgConfig};
+use tracing::{debug, info, warn};
+
+use super::tls::TlsOptions;
+use super::{PostgresError, connection_config};
+use crate::utils::spawn_supervised;
+
+/// Changes arriving this close together are applied with one refresh
+const COALESCE_WINDOW: Duration = Duration::from_millis(100);
+const RECONNECT_BASE_BACKOFF: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_BACKOFF: Duration = Duration::from_secs(30);
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Receiving end of a change listener, shared so the task applying the
+/// changes can run supervised
+pub type ChangeReceiver = Arc<Mutex<UnboundedReceiver<RefreshKind>>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefreshKind {
+    /// Rows were inserted or updated in the cache's own table
+    Partial,
+    /// Anything a partial refresh would miss: deletes, changes to joined
+    /// tables, unreadable payloads and possibly missed notifications
+    Full,
+}
+
+impl RefreshKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Partial => "partial",
+            Self::Full => "full",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ChangePayload {
+    table: String,
+    op: String,
+}
+
+/// Opens a dedicated connection that listens on `channel` and reports the
+/// refresh each notification calls for. Inserts and updates of
+/// `partial_table` ask for a partial refresh, everything else for a full one.
+/// The connection is re-established with backoff until the receiver is dropped.
+pub fn spawn_change_listener(
+    database_url: &str,
+    application_name: &str,
+    channel: &'static str,
+    partial_table: Option<&'static str>,
+) -> Result<ChangeReceiver, PostgresError> {
+    let (config, tls) = connection_config(database_url, application_name)?;
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    spawn_supervised(channel, move || {
+        let (config, tls, tx) = (config.clone(), tls.clone(), tx.clone());
+        async move {
+            let mut backoff = RECONNECT_BASE_BACKOFF;
+            loop {
+                let started = Instant::now();
+                match listen_once(&config, &tls, channel, partial_table, &tx).await {
+                    Ok(()) if tx.is_closed() => return,
+                    Ok(()) => warn!(channel, "Postgres listen connection closed"),
+                    Err(e) => warn!(channel, error = %e, "Postgres listen connection failed"),
+                }
+                if started.elapsed() > RECONNECT_MAX_BACKOFF {
+                    backoff = RECONNECT_BASE_BACKOFF;
+                }
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(RECONNECT_MAX_BACKOFF);
+            }
+        }
+    });
+
+    Ok(Arc::new(Mutex::new(rx)))
+}
+
+async fn listen_once(
+    config: &PgConfig,
+    tls: &TlsOptions,
+    channel: &'static str,
+    partial_table: Option<&'static str>,
+    tx: &UnboundedSender<RefreshKind>,
+) -> Result<(), PostgresError> {
+    let (client, mut connection) = config.connect(tls.connector()?).await?;
+
+    // The connection has to be polled for notifications to arrive
+    let (payload_tx, mut payloads) = mpsc::unbounded_channel::<String>();
+    let driver = tokio::spawn(async move {
+        loop {
+            match std::future::poll_fn(|cx| connection.poll_message(cx)).await {
+                Some(Ok(AsyncMessage::Notification(notification))) => {
+                    if payload_tx.send(notification.payload().to_string()).is_err() {
+                        return Ok(());
+                    }
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e),
+                None => return Ok(()),
+            }
+        }
+    });
+
+    client.batch_execute(&format!("LISTEN {channel}")).await?;
+    info!(channel, "Listening for Postgres change notifications");
+    // Changes committed while not listening were never delivered
+    if tx.send(RefreshKind::Full).is_err() {
+        return Ok(());
+    }
+
+    let mut health_check = interval_at(Instant::now() + HEALTH_CHECK_INTERVAL, HEALTH_CHECK_INTERVAL);
+    health_check.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            payload = payloads.recv() => {
+                let Some(payload) = payload else { break };
+                let kind = refresh_kind(&payload, partial_table);
+                debug!(channel, payload, refresh = kind.as_str(), "Postgres change notification");
+                if tx.send(kind).is_err() {
+                    driver.abort();
+                    return Ok(());
+                }
+            }
+            _ = health_check.tick() => {
+                let ping = timeout(HEALTH_CHECK_TIMEOUT, client.simple_query("SELECT 1")).await;
+                let failed = match ping {
+                    Ok(Ok(_)) => continue,
+                    Ok(Err(e)) => Err(PostgresError::from(e)),
+                    Err(_) => {
+                        warn!(channel, "Postgres listen connection stopped answering");
+                        Ok(())
+                    }
+                };
+                // The driver may be stuck on a dead socket; do not wait for it
+                driver.abort();
+                return failed;
+            }
+        }
+    }
+
+    drop(client);
+    match driver.await {
+        Ok(result) => result.map_err(PostgresError::from),
+        Err(e) => {
+            warn!(channel, error = %e, "Postgres listen driver task failed");
+            Ok(())
+        }
+    }
+}
+
+fn refresh_kind(payload: &str, partial_table: Option<&str>) -> RefreshKind {
+    match serde_json::from_str::<ChangePayload>(payload) {
+        Ok(change) if Some(change.table.as_str()) == partial_table && change.op != "DELETE" => RefreshKind::Partial,
+        _ => RefreshKind::Full,
+    }
+}
+
+/// Waits for the next change, then gathers those arriving right behind it;
+/// Full wins over Partial. None once the listener is gone.
+pub async fn next_coalesced(changes: &mut UnboundedReceiver<RefreshKind>) -> Option<RefreshKind> {
+    let mut kind = changes.recv().await?;
+    sleep(COALESCE_WINDOW).await;
+    while let Ok(next) = changes.try_recv() {
+        if next == RefreshKind::Full {
+            kind = RefreshKind::Full;
+        }
+    }
+    Some(kind)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_upserts_of_the_cache_table_refresh_partially() {
+        let partial = Some("SiteConfig");
+        assert_eq!(refresh_kind(r#"{"table":"SiteConfig","op":"UPDATE"}"#, partial), RefreshKind::Partial);
+        assert_eq!(refresh_kind(r#"{"table":"SiteConfig","op":"DELETE"}"#, partial), RefreshKind::Full);
+        assert_eq!(refresh_kind(r#"{"table":"Dashboard","op":"UPDATE"}"#, partial), RefreshKind::Full);
+        assert_eq!(refresh_kind("garbage", partial), RefreshKind::Full);
+        assert_eq!(refresh_kind(r#"{"table":"SiteConfig","op":"INSERT"}"#, None), RefreshKind::Full);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bursts_are_coalesced_into_one_refresh() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        tx.send(RefreshKind::Partial).unwrap();
+        tx.send(RefreshKind::Full).unwrap();
+        tx.send(RefreshKind::Partial).unwrap();
+        assert_eq!(next_coalesced(&mut rx).await, Some(RefreshKind::Full));
+
+        tx.send(RefreshKind::Partial).unwrap();
+        drop(tx);
+        assert_eq!(next_coalesced(&mut rx).await, Some(RefreshKind::Partial));
+        assert_eq!(next_coalesced(&mut rx).await, None);
+    }
+}
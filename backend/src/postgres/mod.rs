@@ -8,9 +8,13 @@ use std::time::Duration;
 use bb8::{Pool, PooledConnection, RunError};
 use bb8_postgres::PostgresConnectionManager;
 use thiserror::Error;
-use tokio_postgres::{Config as PgConfig, NoTls};
+use tokio_postgres::Config as PgConfig;
+use tokio_postgres_rustls::MakeRustlsConnect;
 use tracing::info;
 
+pub mod listen;
+pub mod tls;
+
 #[derive(Debug, Error)]
 pub enum PostgresError {
     #[error("Invalid Postgres URL: {0}")]
@@ -19,10 +23,12 @@ pub enum PostgresError {
     Pool(#[from] RunError<tokio_postgres::Error>),
     #[error("Postgres query failed: {0}")]
     Query(#[from] tokio_postgres::Error),
+    #[error("Postgres TLS setup failed: {0}")]
+    Tls(String),
 }
 
 pub struct PostgresPool {
-    pool: Pool<PostgresConnectionManager<NoTls>>,
+    pool: Pool<PostgresConnectionManager<MakeRustlsConnect>>,
 }
 
 impl PostgresPool {
@@ -31,12 +37,8 @@ impl PostgresPool {
         application_name: &str,
         max_connections: u32,
     ) -> Result<Self, PostgresError> {
-        let mut config = PgConfig::from_str(database_url)
-            .map_err(|e| PostgresError::InvalidDatabaseUrl(e.to_string()))?;
-        config.connect_timeout(Duration::from_secs(5));
-        config.application_name(application_name);
-
-        let manager = PostgresConnectionManager::new(config, NoTls);
+        let (config, tls) = connection_config(database_url, application_name)?;
+        let manager = PostgresConnectionManager::new(config, tls.connector()?);
         let pool = Pool::builder()
             .max_size(max_connections)
             .connection_timeout(Duration::from_secs(5))
@@ -46,6 +48,7 @@ impl PostgresPool {
         info!(
             application_name = application_name,
             max_connections = max_connections,
+            sslmode = ?tls.mode,
             "PostgreSQL pool initialized"
         );
 
@@ -54,7 +57,21 @@ impl PostgresPool {
 
     pub async fn connection(
         &self,
-    ) -> Result<PooledConnection<'_, PostgresConnectionManager<NoTls>>, PostgresError> {
+    ) -> Result<PooledConnection<'_, PostgresConnectionManager<MakeRustlsConnect>>, PostgresError> {
         Ok(self.pool.get().await?)
     }
 }
+
+/// Connection settings and TLS options for a `postgres://` URL
+pub fn connection_config(
+    database_url: &str,
+    application_name: &str,
+) -> Result<(PgConfig, tls::TlsOptions), PostgresError> {
+    let (database_url, tls) = tls::split_tls_options(database_url)?;
+    let mut config = PgConfig::from_str(&database_url)
+        .map_err(|e| PostgresError::InvalidDatabaseUrl(e.to_string()))?;
+    config.connect_timeout(Duration::from_secs(5));
+    config.application_name(application_name);
+    config.ssl_mode(tls.pg_ssl_mode());
+    Ok((config, tls))
+}
@@ -0,0 +1,303 @@
+//! TLS for the Postgres pools, configured from libpq-style URL parameters:
+//! `sslmode` (`disable`, `prefer`, `require`, `verify-ca`, `verify-full`),
+//! `sslrootcert` (PEM CA bundle; the system roots otherwise) and
+//! `sslcert`/`sslkey` (PEM client certificate and key).
+//!
+//! As in libpq, `require` only encrypts unless `sslrootcert` is given, in
+//! which case it verifies the chain like `verify-ca`. A URL without
+//! `sslmode` connects in plaintext, as the pools always have.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::{CryptoProvider, verify_tls12_signature, verify_tls13_signature};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use tokio_postgres::config::SslMode as PgSslMode;
+use tokio_postgres_rustls::MakeRustlsConnect;
+
+use super::PostgresError;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SslMode {
+    #[default]
+    Disable,
+    /// TLS without verification when the server offers it, plaintext otherwise
+    Prefer,
+    Require,
+    /// Verify the certificate chain but not the host name
+    VerifyCa,
+    VerifyFull,
+}
+
+impl std::str::FromStr for SslMode {
+    type Err = PostgresError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disable" => Ok(Self::Disable),
+            "allow" | "prefer" => Ok(Self::Prefer),
+            "require" => Ok(Self::Require),
+            "verify-ca" => Ok(Self::VerifyCa),
+            "verify-full" => Ok(Self::VerifyFull),
+            other => Err(PostgresError::InvalidDatabaseUrl(format!("unsupported sslmode '{other}'"))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TlsOptions {
+    pub mode: SslMode,
+    pub root_cert: Option<PathBuf>,
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+}
+
+impl TlsOptions {
+    /// The mode tokio-postgres negotiates with; verification is ours
+    pub fn pg_ssl_mode(&self) -> PgSslMode {
+        match self.mode {
+            SslMode::Disable => PgSslMode::Disable,
+            SslMode::Prefer => PgSslMode::Prefer,
+            SslMode::Require | SslMode::VerifyCa | SslMode::VerifyFull => PgSslMode::Require,
+        }
+    }
+
+    pub fn connector(&self) -> Result<MakeRustlsConnect, PostgresError> {
+        Ok(MakeRustlsConnect::new(self.client_config()?))
+    }
+
+    fn client_config(&self) -> Result<ClientConfig, PostgresError> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ClientConfig::builder_with_provider(Arc::clone(&provider))
+            .with_safe_default_protocol_versions()
+            .map_err(tls_error)?;
+
+        let verify_chain = match self.mode {
+            SslMode::VerifyCa | SslMode::VerifyFull => true,
+            SslMode::Require => self.root_cert.is_some(),
+            SslMode::Disable | SslMode::Prefer => false,
+        };
+
+        let builder = if !verify_chain {
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(EncryptOnly { provider }))
+        } else {
+            let roots = Arc::new(self.root_store()?);
+            if self.mode == SslMode::VerifyFull {
+                builder.with_root_certificates(roots)
+            } else {
+                let inner = WebPkiServerVerifier::builder_with_provider(roots, provider)
+                    .build()
+                    .map_err(tls_error)?;
+                builder
+                    .dangerous()
+                    .with_custom_certificate_verifier(Arc::new(IgnoreHostName { inner }))
+            }
+        };
+
+        match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => {
+                let certs = load_certs(cert)?;
+                let key = PrivateKeyDer::from_pem_file(key)
+                    .map_err(|e| PostgresError::Tls(format!("sslkey {}: {e}", key.display())))?;
+                builder.with_client_auth_cert(certs, key).map_err(tls_error)
+            }
+            (None, None) => Ok(builder.with_no_client_auth()),
+            _ => Err(PostgresError::Tls("sslcert and sslkey must be set together".to_string())),
+        }
+    }
+
+    fn root_store(&self) -> Result<RootCertStore, PostgresError> {
+        let mut roots = RootCertStore::empty();
+        match &self.root_cert {
+            Some(path) => {
+                let (added, _) = roots.add_parsable_certificates(load_certs(path)?);
+                if added == 0 {
+                    return Err(PostgresError::Tls(format!("sslrootcert {} has no usable certificates", path.display())));
+                }
+            }
+            None => {
+                let native = rustls_native_certs::load_native_certs()
+                    .map_err(|e| PostgresError::Tls(format!("load system roots: {e}")))?;
+                roots.add_parsable_certificates(native);
+            }
+        }
+        Ok(roots)
+    }
+}
+
+/// Strips the TLS parameters from a `postgres://` URL, which tokio-postgres
+/// would otherwise reject, and returns them parsed. Other parameters are kept
+/// byte-for-byte: tokio-postgres percent-decodes them but does not read `+` as
+/// a space, so re-encoding them would change values like `options=-c%20x`.
+pub fn split_tls_options(database_url: &str) -> Result<(String, TlsOptions), PostgresError> {
+    let Some((base, query)) = database_url.split_once('?') else {
+        return Ok((database_url.to_string(), TlsOptions::default()));
+    };
+
+    let mut options = TlsOptions::default();
+    let mut kept = Vec::new();
+    for segment in query.split('&').filter(|segment| !segment.is_empty()) {
+        let Some((key, value)) = url::form_urlencoded::parse(segment.as_bytes()).next() else {
+            continue;
+        };
+        match key.as_ref() {
+            "sslmode" => options.mode = value.parse()?,
+            "sslrootcert" => options.root_cert = Some(PathBuf::from(value.as_ref())),
+            "sslcert" => options.client_cert = Some(PathBuf::from(value.as_ref())),
+            "sslkey" => options.client_key = Some(PathBuf::from(value.as_ref())),
+            _ => kept.push(segment),
+        }
+    }
+    let rest = kept.join("&");
+
+    let url = if rest.is_empty() { base.to_string() } else { format!("{base}?{rest}") };
+    Ok((url, options))
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, PostgresError> {
+    CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| PostgresError::Tls(format!("{}: {e}", path.display())))
+}
+
+fn tls_error(e: impl std::fmt::Display) -> PostgresError {
+    PostgresError::Tls(e.to_string())
+}
+
+/// `sslmode=require` without a CA: encrypted, but any certificate is accepted
+#[derive(Debug)]
+struct EncryptOnly {
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for EncryptOnly {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// `sslmode=verify-ca`: full chain verification, host name not checked
+#[derive(Debug)]
+struct IgnoreHostName {
+    inner: Arc<WebPkiServerVerifier>,
+}
+
+impl ServerCertVerifier for IgnoreHostName {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        match self.inner.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now) {
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. },
+            )) => Ok(ServerCertVerified::assertion()),
+            other => other,
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tls_params_are_split_from_the_url() {
+        let (url, options) = split_tls_options(
+            "postgres://user:pw@db.example.com:5432/app?sslmode=verify-full&application_name=x&sslrootcert=%2Fetc%2Fca.pem",
+        )
+        .unwrap();
+        assert_eq!(url, "postgres://user:pw@db.example.com:5432/app?application_name=x");
+        assert_eq!(options.mode, SslMode::VerifyFull);
+        assert_eq!(options.root_cert, Some(PathBuf::from("/etc/ca.pem")));
+        assert_eq!(options.pg_ssl_mode(), PgSslMode::Require);
+    }
+
+    #[test]
+    fn other_params_are_kept_verbatim() {
+        let (url, options) =
+            split_tls_options("postgres://localhost/app?options=-c%20search_path%3Dx&sslmode=require&application_name=a+b")
+                .unwrap();
+        assert_eq!(url, "postgres://localhost/app?options=-c%20search_path%3Dx&application_name=a+b");
+        assert_eq!(options.mode, SslMode::Require);
+    }
+
+    #[test]
+    fn url_without_sslmode_stays_plaintext() {
+        let (url, options) = split_tls_options("postgres://localhost/app").unwrap();
+        assert_eq!(url, "postgres://localhost/app");
+        assert_eq!(options, TlsOptions::default());
+        assert!(options.connector().is_ok());
+    }
+
+    #[test]
+    fn client_cert_requires_key_and_unknown_mode_is_rejected() {
+        let options = TlsOptions {
+            mode: SslMode::Require,
+            client_cert: Some(PathBuf::from("/nonexistent/client.pem")),
+            ..TlsOptions::default()
+        };
+        assert!(matches!(options.connector(), Err(PostgresError::Tls(_))));
+        assert!(split_tls_options("postgres://localhost/app?sslmode=sometimes").is_err());
+    }
+}
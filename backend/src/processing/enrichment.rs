@@ -0,0 +1,420 @@
+//! Enrichment stages run by `EventProcessor` between bot detection and visitor
+//! identity. Each stage fills in part of a `ProcessedEvent`; the stage order
+//! comes from `ENRICHMENT_STAGES`, and custom stages are registered by name
+//! alongside the built-in ones. The `event_type` stage is not configurable: it
+//! always runs first, since events without a type are dropped at insert.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tracing::{debug, error, warn};
+use url::Url;
+
+use super::ProcessedEvent;
+use crate::analytics::detect_device_type_from_resolution_with_fallback;
+use crate::campaign::{parse_campaign_params, parse_campaign_params_with_aliases};
+use crate::error_fingerprint::generate_error_fingerprint;
+use crate::geoip::GeoIpService;
+use crate::metrics::MetricsCollector;
+use crate::outbound_link::process_outbound_link;
+use crate::referrer::{classify_channel, parse_referrer};
+use crate::ua_parser;
+
+/// Built-in configurable stages in their default order
+pub const DEFAULT_STAGES: [&str; 6] = ["referrer", "campaign", "channel", "geo", "device", "user_agent"];
+
+/// Name of the fixed first stage
+const EVENT_TYPE_STAGE: &str = "event_type";
+
+#[async_trait]
+pub trait EventEnricher: Send + Sync {
+    /// Stage name used in `ENRICHMENT_STAGES` and as the timing metric label
+    fn name(&self) -> &'static str;
+
+    /// Stages whose output this one reads; they must run earlier in the pipeline
+    fn requires(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// A failing stage is logged and skipped; later stages still run
+    async fn enrich(&self, event: &mut ProcessedEvent) -> Result<()>;
+}
+
+/// Named stages available to a pipeline
+#[derive(Default)]
+pub struct EnrichmentRegistry {
+    stages: HashMap<&'static str, Arc<dyn EventEnricher>>,
+}
+
+impl EnrichmentRegistry {
+    pub fn with_builtins(geoip_service: GeoIpService) -> Self {
+        let mut registry = Self::default();
+        registry.register(Arc::new(ReferrerEnricher));
+        registry.register(Arc::new(CampaignEnricher));
+        registry.register(Arc::new(ChannelEnricher));
+        registry.register(Arc::new(GeoEnricher { geoip_service }));
+        registry.register(Arc::new(DeviceEnricher));
+        registry.register(Arc::new(UserAgentEnricher));
+        registry
+    }
+
+    /// Adds a stage, replacing any registered under the same name
+    pub fn register(&mut self, stage: Arc<dyn EventEnricher>) -> &mut Self {
+        self.stages.insert(stage.name(), stage);
+        self
+    }
+
+    /// Pipeline running `event_type`, then the named stages in the given order.
+    /// Fails on unknown or repeated names and on a stage listed before (or
+    /// without) a stage it requires.
+    pub fn build<S: AsRef<str>>(&self, order: &[S]) -> Result<EnrichmentPipeline, String> {
+        let mut stages: Vec<Arc<dyn EventEnricher>> = vec![Arc::new(EventTypeEnricher)];
+        let mut seen = HashSet::from([EVENT_TYPE_STAGE]);
+        for name in order.iter().map(AsRef::as_ref) {
+            if name == EVENT_TYPE_STAGE {
+                return Err(format!("enrichment stage '{EVENT_TYPE_STAGE}' always runs first; remove it from the list"));
+            }
+            let stage = self.stages.get(name).ok_or_else(|| format!("unknown enrichment stage '{name}'"))?;
+            if !seen.insert(stage.name()) {
+                return Err(format!("enrichment stage '{name}' is listed more than once"));
+            }
+            if let Some(missing) = stage.requires().iter().find(|required| !seen.contains(*required)) {
+                return Err(format!("enrichment stage '{name}' requires '{missing}' to run before it"));
+            }
+            stages.push(Arc::clone(stage));
+        }
+        // A list written before a built-in stage existed silently leaves its columns empty
+        for builtin in DEFAULT_STAGES {
+            if self.stages.contains_key(builtin) && !seen.contains(builtin) {
+                warn!(stage = builtin, "ENRICHMENT_STAGES leaves out a built-in enrichment stage; its fields stay empty");
+            }
+        }
+        Ok(EnrichmentPipeline { stages })
+    }
+}
+
+pub struct EnrichmentPipeline {
+    stages: Vec<Arc<dyn EventEnricher>>,
+}
+
+impl EnrichmentPipeline {
+    pub fn stage_names(&self) -> Vec<&'static str> {
+        self.stages.iter().map(|s| s.name()).collect()
+    }
+
+    pub async fn run(&self, event: &mut ProcessedEvent, metrics: Option<&MetricsCollector>) {
+        for stage in &self.stages {
+            let started = Instant::now();
+            if let Err(e) = stage.enrich(event).await {
+                error!(stage = stage.name(), "Enrichment stage failed: {}", e);
+            }
+            if let Some(metrics) = metrics {
+                metrics.record_enrichment_stage_duration(stage.name(), started.elapsed());
+            }
+        }
+    }
+}
+
+/// Event type and its type-specific fields, plus global properties
+struct EventTypeEnricher;
+
+#[async_trait]
+impl EventEnricher for EventTypeEnricher {
+    fn name(&self) -> &'static str {
+        EVENT_TYPE_STAGE
+    }
+
+    async fn enrich(&self, processed: &mut ProcessedEvent) -> Result<()> {
+        let event_name = processed.event.raw.event_name.clone();
+        if processed.event.raw.is_custom_event {
+            processed.event_type = "custom".to_string();
+            processed.custom_event_name = event_name;
+            processed.custom_event_json = processed.event.raw.properties.clone();
+        } else if event_name == "outbound_link" {
+            processed.event_type = "outbound_link".to_string();
+            // Process and clean outbound link URL
+            if let Some(ref outbound_url_str) = processed.event.raw.outbound_link_url
+                && let Ok(outbound_url) = Url::parse(outbound_url_str)
+            {
+                let outbound_info = process_outbound_link(&outbound_url);
+                processed.outbound_link_url = outbound_info.url;
+            }
+        } else if event_name == "cwv" {
+            processed.event_type = "cwv".to_string();
+            processed.cwv_cls = processed.event.raw.cwv_cls;
+            processed.cwv_lcp = processed.event.raw.cwv_lcp;
+            processed.cwv_inp = processed.event.raw.cwv_inp;
+            processed.cwv_fcp = processed.event.raw.cwv_fcp;
+            processed.cwv_ttfb = processed.event.raw.cwv_ttfb;
+        } else if event_name == "scroll_depth" {
+            // Legacy event from old cached trackers. Translate to engagement at ingest
+            // so queries only need to read 'engagement' rows. page_duration_seconds = 0
+            // is the canonical sentinel for "no usable duration"; the
+            // `page_duration_seconds > 0` query gate excludes these from time-on-page
+            // averages while still letting their scroll values contribute.
+            processed.event_type = "engagement".to_string();
+            processed.page_duration_seconds = 0;
+            processed.scroll_depth_percentage = processed.event.raw.scroll_depth_percentage;
+            processed.scroll_depth_pixels = processed.event.raw.scroll_depth_pixels;
+        } else if event_name == "client_error" {
+            processed.event_type = "client_error".to_string();
+            process_client_error(processed);
+        } else if event_name == "engagement" {
+            processed.event_type = "engagement".to_string();
+            processed.page_duration_seconds = processed.event.raw.page_duration_seconds.unwrap_or(0);
+            processed.scroll_depth_percentage = processed.event.raw.scroll_depth_percentage;
+            processed.scroll_depth_pixels = processed.event.raw.scroll_depth_pixels;
+        } else {
+            processed.event_type = event_name;
+        }
+
+        if let Some(ref gp) = processed.event.raw.global_properties {
+            let (keys, values) = decompose_global_properties(gp);
+            processed.global_properties_keys = keys;
+            processed.global_properties_values = values;
+        }
+
+        Ok(())
+    }
+}
+
+fn process_client_error(processed: &mut ProcessedEvent) {
+    let list = processed.event.raw.error_exceptions.clone().unwrap_or_default();
+    if let Ok(arr) = serde_json::from_str::<serde_json::Value>(&list) {
+        processed.error_type = arr[0]["type"].as_str().unwrap_or("").to_string();
+        processed.error_message = arr[0]["value"].as_str().unwrap_or("").to_string();
+    }
+    processed.error_fingerprint = generate_error_fingerprint(
+        &processed.error_type,
+        &list,
+    );
+    processed.error_exceptions = list;
+}
+
+fn decompose_global_properties(value: &serde_json::Value) -> (Vec<String>, Vec<String>) {
+    let Some(obj) = value.as_object() else {
+        return (Vec::new(), Vec::new());
+    };
+    let mut keys = Vec::with_capacity(obj.len());
+    let mut values = Vec::with_capacity(obj.len());
+    for (key, val) in obj {
+        let value_str = match val {
+            serde_json::Value::String(s) => s.clone(),
+            serde_json::Value::Number(n) => n.to_string(),
+            serde_json::Value::Bool(b) => b.to_string(),
+            _ => continue,
+        };
+        keys.push(key.clone());
+        values.push(value_str);
+    }
+    (keys, values)
+}
+
+struct ReferrerEnricher;
+
+#[async_trait]
+impl EventEnricher for ReferrerEnricher {
+    fn name(&self) -> &'static str {
+        "referrer"
+    }
+
+    async fn enrich(&self, processed: &mut ProcessedEvent) -> Result<()> {
+        let raw = &processed.event.raw;
+        processed.referrer_info = parse_referrer(raw.referrer.as_deref(), Some(&raw.url));
+        debug!("referrer_info: {:?}", processed.referrer_info);
+        Ok(())
+    }
+}
+
+struct CampaignEnricher;
+
+#[async_trait]
+impl EventEnricher for CampaignEnricher {
+    fn name(&self) -> &'static str {
+        "campaign"
+    }
+
+    async fn enrich(&self, processed: &mut ProcessedEvent) -> Result<()> {
+        processed.campaign_info = match &processed.event.campaign_aliases {
+            Some(aliases) => parse_campaign_params_with_aliases(&processed.event.raw.url, aliases),
+            None => parse_campaign_params(&processed.event.raw.url),
+        };
+        debug!("campaign_info: {:?}", processed.campaign_info);
+        Ok(())
+    }
+}
+
+/// Channel grouping; runs after the referrer and campaign stages it reads
+struct ChannelEnricher;
+
+#[async_trait]
+impl EventEnricher for ChannelEnricher {
+    fn name(&self) -> &'static str {
+        "channel"
+    }
+
+    fn requires(&self) -> &'static [&'static str] {
+        &["referrer", "campaign"]
+    }
+
+    async fn enrich(&self, processed: &mut ProcessedEvent) -> Result<()> {
+        processed.channel = Some(classify_channel(&processed.referrer_info, &processed.campaign_info));
+        Ok(())
+    }
+}
+
+struct GeoEnricher {
+    geoip_service: GeoIpService,
+}
+
+#[async_trait]
+impl EventEnricher for GeoEnricher {
+    fn name(&self) -> &'static str {
+        "geo"
+    }
+
+    async fn enrich(&self, processed: &mut ProcessedEvent) -> Result<()> {
+        let geo = self.geoip_service.lookup(&processed.event.ip_address);
+
+        processed.country_code = geo.country_code;
+        processed.subdivision_code = geo.subdivision_code;
+        processed.city = geo.city;
+
+        if processed.country_code.is_some() {
+            debug!("Geolocation successful: country={:?}, subdivision={:?}, city={:?}",
+                processed.country_code, processed.subdivision_code, processed.city);
+        } else {
+            debug!("Geolocation lookup returned no country code.");
+        }
+        Ok(())
+    }
+}
+
+/// Device type from screen resolution
+struct DeviceEnricher;
+
+#[async_trait]
+impl EventEnricher for DeviceEnricher {
+    fn name(&self) -> &'static str {
+        "device"
+    }
+
+    async fn enrich(&self, processed: &mut ProcessedEvent) -> Result<()> {
+        let device_type = detect_device_type_from_resolution_with_fallback(&processed.event.raw.screen_resolution);
+        processed.device_type = Some(device_type);
+        Ok(())
+    }
+}
+
+struct UserAgentEnricher;
+
+#[async_trait]
+impl EventEnricher for UserAgentEnricher {
+    fn name(&self) -> &'static str {
+        "user_agent"
+    }
+
+    async fn enrich(&self, processed: &mut ProcessedEvent) -> Result<()> {
+        let parsed = ua_parser::parse_user_agent(&processed.user_agent);
+
+        processed.browser = Some(parsed.browser);
+        processed.browser_version = parsed.browser_version;
+        processed.os = Some(parsed.os);
+        processed.os_version = parsed.os_version;
+
+        debug!(
+            "User agent parsed: browser={:?}, version={:?}, os={:?}, os_version={:?}, device_type={:?}",
+            processed.browser, processed.browser_version, processed.os, processed.os_version, processed.device_type
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NamedStage(&'static str, &'static [&'static str]);
+
+    #[async_trait]
+    impl EventEnricher for NamedStage {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        fn requires(&self) -> &'static [&'static str] {
+            self.1
+        }
+
+        async fn enrich(&self, _event: &mut ProcessedEvent) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn pipeline_follows_configured_order() {
+        let mut registry = EnrichmentRegistry::default();
+        registry.register(Arc::new(NamedStage("first", &[]))).register(Arc::new(NamedStage("second", &[])));
+
+        let pipeline = registry.build(&["second", "first"]).unwrap();
+        assert_eq!(pipeline.stage_names(), vec!["event_type", "second", "first"]);
+    }
+
+    #[test]
+    fn event_type_always_runs_first_and_is_not_configurable() {
+        let registry = EnrichmentRegistry::default();
+        let empty: [&str; 0] = [];
+        assert_eq!(registry.build(&empty).unwrap().stage_names(), vec!["event_type"]);
+
+        let err = registry.build(&["event_type"]).err().unwrap();
+        assert!(err.contains("always runs first"));
+    }
+
+    #[test]
+    fn duplicate_stage_is_rejected() {
+        let mut registry = EnrichmentRegistry::default();
+        registry.register(Arc::new(NamedStage("geo", &[])));
+
+        let err = registry.build(&["geo", "geo"]).err().unwrap();
+        assert!(err.contains("more than once"));
+    }
+
+    #[test]
+    fn stage_must_follow_the_stages_it_requires() {
+        let mut registry = EnrichmentRegistry::default();
+        registry
+            .register(Arc::new(NamedStage("source", &[])))
+            .register(Arc::new(NamedStage("derived", &["source", "event_type"])));
+
+        assert!(registry.build(&["source", "derived"]).is_ok());
+        let err = registry.build(&["derived", "source"]).err().unwrap();
+        assert!(err.contains("requires 'source'"));
+        assert!(registry.build(&["derived"]).is_err());
+    }
+
+    #[test]
+    fn unknown_stage_is_rejected() {
+        let mut registry = EnrichmentRegistry::default();
+        registry.register(Arc::new(NamedStage("geo", &[])));
+
+        let err = registry.build(&["geo", "weather"]).err().unwrap();
+        assert!(err.contains("weather"));
+    }
+
+    #[test]
+    fn channel_runs_after_referrer_and_campaign() {
+        let mut registry = EnrichmentRegistry::default();
+        registry
+            .register(Arc::new(NamedStage("referrer", &[])))
+            .register(Arc::new(NamedStage("campaign", &[])))
+            .register(Arc::new(ChannelEnricher));
+
+        assert!(registry.build(&["referrer", "campaign", "channel"]).is_ok());
+        assert!(registry.build(&["channel", "referrer", "campaign"]).is_err());
+        assert!(registry.build(&["referrer", "channel"]).is_err());
+    }
+}
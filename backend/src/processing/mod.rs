@@ -8,18 +8,19 @@ use crate::asn::{AsnInfo, AsnService};
 use moka::sync::Cache;
 use once_cell::sync::Lazy;
 use std::time::Duration;
-use crate::geoip::GeoIpService;
 use crate::metrics::MetricsCollector;
 use crate::visitor;
 use crate::bot_detection;
-use crate::referrer::{ReferrerInfo, parse_referrer};
-use crate::url_utils::{extract_domain_and_path_from_url, extract_root_domain};
-use url::Url;
-use crate::campaign::{CampaignInfo, parse_campaign_params};
-use crate::ua_parser;
-use crate::outbound_link::process_outbound_link;
-use crate::analytics::detect_device_type_from_resolution_with_fallback;
-use crate::error_fingerprint::generate_error_fingerprint;
+use crate::referrer::{Channel, ReferrerInfo};
+use crate::url_utils::{extract_domain_and_path_from_url, extract_root_domain, extract_url_parts};
+use crate::campaign::CampaignInfo;
+use crate::db::EventRow;
+use crate::db::spool::{SpoolError, SpoolWriter};
+use crate::validation::network_policy::NetworkMode;
+use crate::quota;
+
+pub mod enrichment;
+pub use enrichment::{EnrichmentPipeline, EnrichmentRegistry};
 
 // Keyed on the full tuple, not a hash: the verdict gates an enforcing 403, so a
 // hash collision must not transfer one visitor's verdict to another
@@ -57,6 +58,8 @@ pub struct ProcessedEvent {
     pub domain: Option<String>,
     /// Contains only the path of the URL (e.g. "/path/to/page" or "/")
     pub url: String,
+    /// Path before the site's rewrite rules; empty unless the site keeps raw paths
+    pub url_raw: String,
     /// Geolocation data - Planning to use ip-api.com or maxmind to get this data
     pub country_code: Option<String>,
     /// Subdivision/region code in ISO 3166-2 format (e.g. "US-CA")
@@ -78,6 +81,8 @@ pub struct ProcessedEvent {
     pub referrer_info: ReferrerInfo,
     /// Parsed campaign parameters
     pub campaign_info: CampaignInfo,
+    /// GA4 default channel from the referrer and campaign; None when the stage is not configured
+    pub channel: Option<Channel>,
     pub user_agent: String,
     /// Custom event handling
     pub event_type: String,
@@ -114,19 +119,22 @@ static DROP_LOG_SAMPLE: AtomicU64 = AtomicU64::new(0);
 pub struct EventProcessor {
     event_tx: mpsc::Sender<ProcessedEvent>,
     bot_tx: mpsc::Sender<BotEvent>,
-    geoip_service: GeoIpService,
+    /// Stages between bot detection and visitor identity, in configured order
+    enrichment: EnrichmentPipeline,
     /// None when ASN lookup is disabled; detections then see asn 0 / empty org
     asn_service: Option<AsnService>,
     metrics: Option<Arc<MetricsCollector>>,
     honor_client_timestamps: bool,
     /// When false, detections update metrics but are not persisted to bot_events
     log_bot_events: bool,
+    /// Takes events the ingest channel cannot; None drops them
+    spool: Option<SpoolWriter>,
 }
 
 impl EventProcessor {
     /// `event_tx`/`bot_tx` are the ingest channels consumed by the ClickHouse inserter tasks.
     pub fn new(
-        geoip_service: GeoIpService,
+        enrichment: EnrichmentPipeline,
         asn_service: Option<AsnService>,
         event_tx: mpsc::Sender<ProcessedEvent>,
         bot_tx: mpsc::Sender<BotEvent>,
@@ -134,7 +142,13 @@ impl EventProcessor {
         honor_client_timestamps: bool,
         log_bot_events: bool,
     ) -> Self {
-        Self { event_tx, bot_tx, geoip_service, asn_service, metrics, honor_client_timestamps, log_bot_events }
+        Self { event_tx, bot_tx, enrichment, asn_service, metrics, honor_client_timestamps, log_bot_events, spool: None }
+    }
+
+    /// Spool overflow to disk instead of dropping it when the ingest channel is full
+    pub fn with_spool(mut self, spool: SpoolWriter) -> Self {
+        self.spool = Some(spool);
+        self
     }
 
     fn asn_lookup(&self, ip_address: &str) -> AsnInfo {
@@ -167,7 +181,7 @@ impl EventProcessor {
         if !self.log_bot_events {
             return;
         }
-        let bot_event = BotEvent {
+        self.send_bot_event(BotEvent {
             site_id: site_id.to_string(),
             timestamp: chrono::Utc::now(),
             domain: domain.map(str::to_string),
@@ -179,7 +193,51 @@ impl EventProcessor {
             bot_reasons,
             asn: input.asn,
             asn_org: asn_org.to_string(),
+        });
+    }
+
+    /// Applies the site's country and ASN rules once geo and ASN are known.
+    /// Blocked events are recorded to bot_events; true when the event must be dropped.
+    /// A dropped event's quota use from ingest is refunded, so a blocked flood
+    /// does not use up the site's quota.
+    fn blocked_by_network_policy(&self, processed: &ProcessedEvent) -> bool {
+        let Some(policy) = &processed.event.network_policy else {
+            return false;
+        };
+        let Some(reason) = policy.violation(processed.country_code.as_deref(), processed.asn) else {
+            return false;
         };
+        let enforce = policy.mode == NetworkMode::Enforce;
+        let tagged_reason = if enforce { reason.to_string() } else { format!("shadow:{}", reason) };
+        debug!(site_id = %processed.site_id, reason = %tagged_reason, "Network policy match");
+        if let Some(metrics) = &self.metrics {
+            metrics.increment_bot_event_detected(&tagged_reason);
+            if enforce {
+                metrics.increment_events_rejected(reason);
+            }
+        }
+        if enforce {
+            refund_quota(&processed.event);
+        }
+        if self.log_bot_events {
+            self.send_bot_event(BotEvent {
+                site_id: processed.site_id.clone(),
+                timestamp: chrono::Utc::now(),
+                domain: processed.domain.clone(),
+                url: processed.url.clone(),
+                referrer: processed.event.raw.referrer.clone().unwrap_or_default(),
+                user_agent: processed.user_agent.clone(),
+                screen_resolution: processed.event.raw.screen_resolution.clone(),
+                event_name: processed.event.raw.event_name.clone(),
+                bot_reasons: vec![tagged_reason],
+                asn: processed.asn,
+                asn_org: processed.asn_org.clone(),
+            });
+        }
+        enforce
+    }
+
+    fn send_bot_event(&self, bot_event: BotEvent) {
         // try_send: recording bot traffic must never backpressure the human event path
         if self.bot_tx.try_send(bot_event).is_err() {
             if let Some(metrics) = &self.metrics {
@@ -228,7 +286,9 @@ impl EventProcessor {
 
     pub async fn process_event(&self, event: AnalyticsEvent) -> Result<()> {
         let site_id = event.raw.site_id.clone();
-        let timestamp = if self.honor_client_timestamps {
+        let timestamp = if let Some(ts) = event.trusted_timestamp {
+            ts
+        } else if self.honor_client_timestamps {
             event.raw.timestamp
                 .and_then(|ts| chrono::DateTime::from_timestamp(ts as i64, 0))
                 .unwrap_or_else(chrono::Utc::now)
@@ -240,30 +300,45 @@ impl EventProcessor {
         let user_agent = event.raw.user_agent.clone();
 
         let asn_info = self.asn_lookup(&event.ip_address);
-        let velocity_exceeded = bot_detection::velocity::check(&site_id, &event.ip_address);
-        let (domain, path) = extract_domain_and_path_from_url(&raw_url);
-        debug!("Extracted domain '{:?}' and path '{}' from URL '{}'", domain, path, raw_url);
-
-        // Bot Detection early to avoid processing bot traffic
-        let input = bot_detection::DetectionInput {
-            user_agent: &user_agent,
-            header_user_agent: &event.header_user_agent,
-            screen_resolution: &event.raw.screen_resolution,
-            referrer: referrer.as_deref().unwrap_or_default(),
-            automation: event.raw.automation,
-            asn: asn_info.asn,
-            prefetch: event.prefetch,
-            velocity_exceeded,
-            sec_ch_ua: &event.sec_ch_ua,
+        let (domain, path, query) = match &event.path_options {
+            Some(options) => extract_url_parts(&raw_url, options),
+            None => {
+                let (domain, path) = extract_domain_and_path_from_url(&raw_url);
+                (domain, path, String::new())
+            }
         };
-        let detection = bot_detection::detect(&input);
-        self.record_detection(&detection, &input, &site_id, domain.as_deref(), &path, &event.raw.event_name, &asn_info.org);
-        if detection.should_reject() {
-            return Ok(());
+        debug!("Extracted domain '{:?}' and path '{}{}' from URL '{}'", domain, path, query, raw_url);
+        let (path, url_raw) = match &event.path_rewriter {
+            Some(rewriter) => {
+                let rewritten = rewriter.rewrite(&path);
+                (rewritten + &query, if rewriter.keep_raw_path { path + &query } else { String::new() })
+            }
+            None => (path + &query, String::new()),
+        };
+
+        // Bot Detection early to avoid processing bot traffic. Skipped for an
+        // authenticated sender that names its visitor: its user agent and request
+        // rate describe the sending backend, not the visitor.
+        if event.visitor_key.is_none() {
+            let velocity_exceeded = bot_detection::velocity::check(&site_id, &event.ip_address);
+            let input = bot_detection::DetectionInput {
+                user_agent: &user_agent,
+                header_user_agent: &event.header_user_agent,
+                screen_resolution: &event.raw.screen_resolution,
+                referrer: referrer.as_deref().unwrap_or_default(),
+                automation: event.raw.automation,
+                asn: asn_info.asn,
+                prefetch: event.prefetch,
+                velocity_exceeded,
+                sec_ch_ua: &event.sec_ch_ua,
+            };
+            let detection = bot_detection::detect(&input);
+            self.record_detection(&detection, &input, &site_id, domain.as_deref(), &path, &event.raw.event_name, &asn_info.org);
+            if detection.should_reject() {
+                refund_quota(&event);
+                return Ok(());
+            }
         }
-        // Counted only for accepted events, so a blocked bot flood cannot poison
-        // the velocity window shared with humans behind the same IP
-        bot_detection::velocity::record(&site_id, &event.ip_address);
 
         let mut processed = ProcessedEvent {
             event: event.clone(),
@@ -283,9 +358,11 @@ impl EventProcessor {
             timestamp: timestamp.clone(),
             domain,
             url: path,
+            url_raw,
             referrer_info: ReferrerInfo::default(),
             user_agent: user_agent.clone(),
             campaign_info: CampaignInfo::default(),
+            channel: None,
             custom_event_name: String::new(),
             custom_event_json: String::new(),
             outbound_link_url: String::new(),
@@ -307,29 +384,15 @@ impl EventProcessor {
             asn_org: asn_info.org,
         };
 
-        // Handle event types
-        if let Err(e) = self.handle_event_types(&mut processed).await {
-            error!("Failed to handle event type: {}", e);
-        }
-
-        // Parse referrer information
-        processed.referrer_info = parse_referrer(referrer.as_deref(), Some(&raw_url));
-        debug!("referrer_info: {:?}", processed.referrer_info);
-        
-        // Parse campaign parameters from URL
-        processed.campaign_info = parse_campaign_params(&raw_url);
-        debug!("campaign_info: {:?}", processed.campaign_info);
-
-        if let Err(e) = self.get_geolocation(&mut processed).await {
-            error!("Failed to get geolocation: {}", e);
-        }
+        self.enrichment.run(&mut processed, self.metrics.as_deref()).await;
 
-        if let Err(e) = self.detect_device_type_from_resolution(&mut processed).await {
-            error!("Failed to detect device type from resolution: {}", e);
+        if self.blocked_by_network_policy(&processed) {
+            return Ok(());
         }
-        
-        if let Err(e) = self.parse_user_agent(&mut processed).await {
-            error!("Failed to parse user agent: {}", e);
+        // Counted only for accepted events, so a blocked bot flood cannot poison
+        // the velocity window shared with humans behind the same IP
+        if processed.event.visitor_key.is_none() {
+            bot_detection::velocity::record(&site_id, &processed.event.ip_address);
         }
 
         let root_domain = processed.domain.as_ref().and_then(|d| extract_root_domain(d));
@@ -342,6 +405,7 @@ impl EventProcessor {
                 browser_version: processed.browser_version.as_deref(),
                 os: processed.os.as_deref(),
                 root_domain: root_domain.as_deref(),
+                visitor_key: processed.event.visitor_key.as_deref(),
             };
             visitor::identify(&site_id, &attrs, timestamp)
         };
@@ -354,12 +418,17 @@ impl EventProcessor {
         debug!("Session ID: {}", processed.session_id);
 
         // try_send, not send: ingestion stays responsive if the buffer fills,
-        // at the cost of dropping the newest events (counted, never silent).
+        // at the cost of spooling or dropping the newest events (counted, never silent).
         if let Err(e) = self.event_tx.try_send(processed) {
-            let reason = match e {
-                TrySendError::Full(_) => "channel_full",
-                TrySendError::Closed(_) => "channel_closed",
+            let (reason, processed) = match e {
+                TrySendError::Full(p) => ("channel_full", p),
+                TrySendError::Closed(p) => ("channel_closed", p),
+            };
+            let reason = match self.spool_overflow(processed) {
+                Ok(()) => return Ok(()),
+                Err(spool_reason) => spool_reason.unwrap_or(reason),
             };
+            refund_quota(&event);
             if let Some(metrics) = &self.metrics {
                 metrics.increment_events_dropped(reason, "analytics.events", 1);
             }
@@ -373,128 +442,32 @@ impl EventProcessor {
         Ok(())
     }
 
-    /// Extract domain and path from a URL string.
-    /// Handle different event types
-    async fn handle_event_types(&self, processed: &mut ProcessedEvent) -> Result<()> {
-        let event_name = processed.event.raw.event_name.clone();
-        if processed.event.raw.is_custom_event {
-            processed.event_type = "custom".to_string();
-            processed.custom_event_name = event_name;
-            processed.custom_event_json = processed.event.raw.properties.clone();
-        } else if event_name == "outbound_link" {
-            processed.event_type = "outbound_link".to_string();
-            // Process and clean outbound link URL
-            if let Some(ref outbound_url_str) = processed.event.raw.outbound_link_url {
-                if let Some(ref outbound_url) = Url::parse(&outbound_url_str).ok() {
-                    let outbound_info = process_outbound_link(outbound_url);
-                    processed.outbound_link_url = outbound_info.url;
-                }
+    /// Queues an event the channel could not take for the disk spool's writer.
+    /// On failure returns the drop reason to report instead of the channel's,
+    /// if any.
+    fn spool_overflow(&self, processed: ProcessedEvent) -> Result<(), Option<&'static str>> {
+        let Some(spool) = &self.spool else {
+            return Err(None);
+        };
+        // No row means no ClickHouse event type; the inserter would skip it too
+        let Some(row) = EventRow::from_processed(processed) else {
+            return Ok(());
+        };
+        match spool.append(&row) {
+            Ok(()) => Ok(()),
+            Err(SpoolError::Full) => Err(Some("spool_full")),
+            Err(SpoolError::Backlogged) => Err(Some("spool_backlogged")),
+            Err(e) => {
+                error!("Failed to queue event for ingest spool: {}", e);
+                Err(Some("spool_error"))
             }
-        } else if event_name == "cwv" {
-            processed.event_type = "cwv".to_string();
-            processed.cwv_cls = processed.event.raw.cwv_cls;
-            processed.cwv_lcp = processed.event.raw.cwv_lcp;
-            processed.cwv_inp = processed.event.raw.cwv_inp;
-            processed.cwv_fcp = processed.event.raw.cwv_fcp;
-            processed.cwv_ttfb = processed.event.raw.cwv_ttfb;
-        } else if event_name == "scroll_depth" {
-            // Legacy event from old cached trackers. Translate to engagement at ingest
-            // so queries only need to read 'engagement' rows. page_duration_seconds = 0
-            // is the canonical sentinel for "no usable duration"; the
-            // `page_duration_seconds > 0` query gate excludes these from time-on-page
-            // averages while still letting their scroll values contribute.
-            processed.event_type = "engagement".to_string();
-            processed.page_duration_seconds = 0;
-            processed.scroll_depth_percentage = processed.event.raw.scroll_depth_percentage;
-            processed.scroll_depth_pixels = processed.event.raw.scroll_depth_pixels;
-        } else if event_name == "client_error" {
-            processed.event_type = "client_error".to_string();
-            self.process_client_error(processed);
-        } else if event_name == "engagement" {
-            processed.event_type = "engagement".to_string();
-            processed.page_duration_seconds = processed.event.raw.page_duration_seconds.unwrap_or(0);
-            processed.scroll_depth_percentage = processed.event.raw.scroll_depth_percentage;
-            processed.scroll_depth_pixels = processed.event.raw.scroll_depth_pixels;
-        } else {
-            processed.event_type = event_name;
-        }
-
-        if let Some(ref gp) = processed.event.raw.global_properties {
-            let (keys, values) = decompose_global_properties(gp);
-            processed.global_properties_keys = keys;
-            processed.global_properties_values = values;
-        }
-
-        Ok(())
-    }
-
-    async fn get_geolocation(&self, processed: &mut ProcessedEvent) -> Result<()> {
-        let geo = self.geoip_service.lookup(&processed.event.ip_address);
-
-        processed.country_code = geo.country_code;
-        processed.subdivision_code = geo.subdivision_code;
-        processed.city = geo.city;
-
-        if processed.country_code.is_some() {
-            debug!("Geolocation successful: country={:?}, subdivision={:?}, city={:?}",
-                processed.country_code, processed.subdivision_code, processed.city);
-        } else {
-            debug!("Geolocation lookup returned no country code.");
         }
-        Ok(())
-    }
-
-    async fn parse_user_agent(&self, processed: &mut ProcessedEvent) -> Result<()> {
-        let parsed = ua_parser::parse_user_agent(&processed.user_agent);
-        
-        processed.browser = Some(parsed.browser);
-        processed.browser_version = parsed.browser_version;
-        processed.os = Some(parsed.os);
-        processed.os_version = parsed.os_version;
-        
-        debug!(
-            "User agent parsed: browser={:?}, version={:?}, os={:?}, os_version={:?}, device_type={:?}",
-            processed.browser, processed.browser_version, processed.os, processed.os_version, processed.device_type
-        );
-        
-        Ok(())
-    }
-    
-    fn process_client_error(&self, processed: &mut ProcessedEvent) {
-        let list = processed.event.raw.error_exceptions.clone().unwrap_or_default();
-        if let Ok(arr) = serde_json::from_str::<serde_json::Value>(&list) {
-            processed.error_type = arr[0]["type"].as_str().unwrap_or("").to_string();
-            processed.error_message = arr[0]["value"].as_str().unwrap_or("").to_string();
-        }
-        processed.error_fingerprint = generate_error_fingerprint(
-            &processed.error_type,
-            &list,
-        );
-        processed.error_exceptions = list;
-    }
-
-    async fn detect_device_type_from_resolution(&self, processed: &mut ProcessedEvent) -> Result<()> {
-        let device_type = detect_device_type_from_resolution_with_fallback(&processed.event.raw.screen_resolution);
-        processed.device_type = Some(device_type);
-        Ok(())
     }
 }
 
-fn decompose_global_properties(value: &serde_json::Value) -> (Vec<String>, Vec<String>) {
-    let Some(obj) = value.as_object() else {
-        return (Vec::new(), Vec::new());
-    };
-    let mut keys = Vec::with_capacity(obj.len());
-    let mut values = Vec::with_capacity(obj.len());
-    for (key, val) in obj {
-        let value_str = match val {
-            serde_json::Value::String(s) => s.clone(),
-            serde_json::Value::Number(n) => n.to_string(),
-            serde_json::Value::Bool(b) => b.to_string(),
-            _ => continue,
-        };
-        keys.push(key.clone());
-        values.push(value_str);
-    }
-    (keys, values)
-}
\ No newline at end of file
+/// Gives back the quota an event used at ingest when the processor drops it,
+/// so rejected and dropped events never count against the site's limits
+fn refund_quota(event: &AnalyticsEvent) {
+    let raw = &event.raw;
+    quota::refund(&raw.site_id, quota::is_billable(&raw.event_name, raw.is_custom_event), chrono::Utc::now());
+}
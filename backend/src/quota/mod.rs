@@ -0,0 +1,323 @@
+//! Per-site event quotas and burst limits, enforced with in-memory counters.
+//!
+//! The monthly count is the site's billable usage in `analytics.usage_daily`
+//! at the last reconciliation plus what this instance admitted since, so
+//! restarts and multiple ingest instances converge on ClickHouse's figure.
+//! The burst limit is a fixed one-second window per instance.
+//!
+//! Sites with a quota are seeded from ClickHouse before ingest starts. A site
+//! first tracked later (a new quota, or one idle for a day) triggers an
+//! immediate reconciliation; until it lands, only what this instance admitted
+//! counts towards the site's monthly figure.
+
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, Utc};
+use moka::sync::Cache;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use tokio::sync::Notify;
+use tracing::{debug, warn};
+
+use crate::clickhouse::ClickHouseClient;
+use crate::utils::spawn_supervised;
+
+/// Event types counted by `analytics.usage_daily_mv`; keep in sync with
+/// migration 38 and BILLABLE_EVENT_TYPES in the dashboard.
+const BILLABLE_EVENT_TYPES: [&str; 5] = ["pageview", "custom", "outbound_link", "cwv", "client_error"];
+
+const DEFAULT_RECONCILE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaExceeded {
+    /// Monthly billable event quota used up
+    Monthly,
+    /// Per-second burst limit hit
+    Burst,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QuotaLimits {
+    pub monthly: Option<u64>,
+    pub per_second: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct QuotaSettings {
+    /// Answer quota rejections with 429 instead of 403
+    pub respond_429: bool,
+    pub reconcile_interval: Duration,
+}
+
+impl Default for QuotaSettings {
+    fn default() -> Self {
+        Self { respond_429: false, reconcile_interval: DEFAULT_RECONCILE_INTERVAL }
+    }
+}
+
+static SETTINGS: OnceLock<QuotaSettings> = OnceLock::new();
+
+pub fn initialize(settings: QuotaSettings) {
+    if SETTINGS.set(settings).is_err() {
+        warn!("Quota settings already initialized, ignoring");
+    }
+}
+
+pub fn responds_429() -> bool {
+    SETTINGS.get().is_some_and(|s| s.respond_429)
+}
+
+#[derive(Debug, Default)]
+struct SiteUsage {
+    /// Year * 12 + month the monthly figures belong to
+    month: i32,
+    /// Billable events in ClickHouse at the last reconciliation
+    reconciled: u64,
+    /// `reconciled` has been loaded from ClickHouse for this month
+    seeded: bool,
+    /// Billable events admitted by this instance since then
+    local: u64,
+    /// Unix second of the current burst window
+    second: i64,
+    second_count: u32,
+}
+
+impl SiteUsage {
+    fn roll(&mut self, now: DateTime<Utc>) {
+        let month = month_key(now);
+        if self.month != month {
+            *self = SiteUsage { month, ..SiteUsage::default() };
+        }
+        if self.second != now.timestamp() {
+            self.second = now.timestamp();
+            self.second_count = 0;
+        }
+    }
+}
+
+/// Only sites with a limit are tracked; idle sites age out and are re-seeded
+/// from ClickHouse on the next reconciliation.
+static USAGE: Lazy<Cache<String, Arc<Mutex<SiteUsage>>>> = Lazy::new(|| {
+    Cache::builder()
+        .time_to_idle(Duration::from_secs(24 * 60 * 60))
+        .max_capacity(100_000)
+        .build()
+});
+
+/// Wakes the reconciler early when a site's monthly figure is not yet loaded
+static RECONCILE_NOW: Lazy<Notify> = Lazy::new(Notify::new);
+
+fn month_key(ts: DateTime<Utc>) -> i32 {
+    ts.year() * 12 + ts.month0() as i32
+}
+
+pub fn is_billable(event_name: &str, is_custom_event: bool) -> bool {
+    is_custom_event || BILLABLE_EVENT_TYPES.contains(&event_name)
+}
+
+/// Counts the event against the site's limits, or rejects it without
+/// counting. Over the monthly quota every event is rejected, but only
+/// billable ones use up quota.
+pub fn admit(
+    site_id: &str,
+    limits: QuotaLimits,
+    billable: bool,
+    now: DateTime<Utc>,
+) -> Result<(), QuotaExceeded> {
+    if limits.monthly.is_none() && limits.per_second.is_none() {
+        return Ok(());
+    }
+
+    let usage = USAGE.get_with_by_ref(site_id, || Arc::new(Mutex::new(SiteUsage::default())));
+    let mut usage = usage.lock().unwrap();
+    usage.roll(now);
+    if limits.monthly.is_some() && !usage.seeded {
+        RECONCILE_NOW.notify_one();
+    }
+
+    if limits.per_second.is_some_and(|limit| usage.second_count >= limit) {
+        return Err(QuotaExceeded::Burst);
+    }
+    if limits.monthly.is_some_and(|quota| usage.reconciled + usage.local >= quota) {
+        return Err(QuotaExceeded::Monthly);
+    }
+
+    usage.second_count += 1;
+    if billable {
+        usage.local += 1;
+    }
+    Ok(())
+}
+
+/// Gives back what `admit` counted for an event dropped after admission. The
+/// burst count is only returned within the same second and the monthly count
+/// within the same month; an untracked site has nothing to give back.
+pub fn refund(site_id: &str, billable: bool, now: DateTime<Utc>) {
+    let Some(usage) = USAGE.get(site_id) else {
+        return;
+    };
+    let mut usage = usage.lock().unwrap();
+    if usage.second == now.timestamp() {
+        usage.second_count = usage.second_count.saturating_sub(1);
+    }
+    if billable && usage.month == month_key(now) {
+        usage.local = usage.local.saturating_sub(1);
+    }
+}
+
+/// Replaces a site's monthly figure with ClickHouse's. Events this instance
+/// admitted that are not yet inserted are briefly undercounted, which errs on
+/// the side of accepting traffic.
+fn apply_reconciled(site_id: &str, billable_events: u64, now: DateTime<Utc>) {
+    if let Some(usage) = USAGE.get(site_id) {
+        let mut usage = usage.lock().unwrap();
+        usage.roll(now);
+        usage.reconciled = billable_events;
+        usage.seeded = true;
+        usage.local = 0;
+    }
+}
+
+#[derive(clickhouse::Row, Deserialize)]
+struct SiteUsageRow {
+    site_id: String,
+    billable_events: u64,
+}
+
+async fn reconcile(clickhouse: &ClickHouseClient) -> Result<(), clickhouse::error::Error> {
+    let site_ids: Vec<String> = USAGE.iter().map(|(site_id, _)| site_id.as_ref().clone()).collect();
+    if site_ids.is_empty() {
+        return Ok(());
+    }
+
+    let now = Utc::now();
+    let rows = clickhouse
+        .inner()
+        .query(
+            "SELECT site_id, sum(event_count) AS billable_events \
+             FROM analytics.usage_daily \
+             WHERE date >= toStartOfMonth(today()) AND site_id IN ? \
+             GROUP BY site_id",
+        )
+        .bind(&site_ids)
+        .fetch_all::<SiteUsageRow>()
+        .await?;
+
+    debug!(sites = site_ids.len(), with_usage = rows.len(), "Reconciled quota counters");
+    let mut seen = std::collections::HashSet::with_capacity(rows.len());
+    for row in rows {
+        apply_reconciled(&row.site_id, row.billable_events, now);
+        seen.insert(row.site_id);
+    }
+    // No usage rows yet this month means zero billable events so far
+    for site_id in site_ids.iter().filter(|id| !seen.contains(*id)) {
+        apply_reconciled(site_id, 0, now);
+    }
+    Ok(())
+}
+
+/// Loads the monthly usage of `site_ids` before ingest starts, so a restart
+/// does not admit events for sites already over their quota
+pub async fn seed(clickhouse: &ClickHouseClient, site_ids: impl IntoIterator<Item = String>) {
+    for site_id in site_ids {
+        USAGE.get_with(site_id, || Arc::new(Mutex::new(SiteUsage::default())));
+    }
+    if let Err(e) = reconcile(clickhouse).await {
+        warn!("Initial quota reconciliation failed, counting from zero until the next one: {}", e);
+    }
+}
+
+/// Periodically resyncs the monthly counters with `analytics.usage_daily`, and
+/// right away when a site with a quota is seen before its usage is loaded
+pub fn spawn_reconciler(clickhouse: Arc<ClickHouseClient>) {
+    let interval = SETTINGS.get().copied().unwrap_or_default().reconcile_interval;
+    spawn_supervised("quota_reconciler", move || {
+        let clickhouse = Arc::clone(&clickhouse);
+        async move {
+            let mut tick = tokio::time::interval(interval);
+            loop {
+                tokio::select! {
+                    _ = tick.tick() => {}
+                    _ = RECONCILE_NOW.notified() => {}
+                }
+                if let Err(e) = reconcile(&clickhouse).await {
+                    warn!("Quota reconciliation failed, keeping local counters: {}", e);
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(ts: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(ts, 0).unwrap()
+    }
+
+    #[test]
+    fn burst_limit_resets_each_second() {
+        let limits = QuotaLimits { monthly: None, per_second: Some(2) };
+        let now = at(1_700_000_000);
+        assert!(admit("quota-burst", limits, true, now).is_ok());
+        assert!(admit("quota-burst", limits, true, now).is_ok());
+        assert_eq!(admit("quota-burst", limits, true, now), Err(QuotaExceeded::Burst));
+        assert!(admit("quota-burst", limits, true, at(1_700_000_001)).is_ok());
+    }
+
+    #[test]
+    fn monthly_quota_counts_only_billable_events() {
+        let limits = QuotaLimits { monthly: Some(2), per_second: None };
+        let now = at(1_700_000_000);
+        assert!(admit("quota-monthly", limits, false, now).is_ok());
+        assert!(admit("quota-monthly", limits, true, now).is_ok());
+        assert!(admit("quota-monthly", limits, false, now).is_ok());
+        assert!(admit("quota-monthly", limits, true, now).is_ok());
+        assert_eq!(admit("quota-monthly", limits, false, now), Err(QuotaExceeded::Monthly));
+    }
+
+    #[test]
+    fn reconciliation_replaces_local_count_and_month_rolls_over() {
+        let limits = QuotaLimits { monthly: Some(10), per_second: None };
+        let now = at(1_700_000_000); // 2023-11-14
+        assert!(admit("quota-reconcile", limits, true, now).is_ok());
+
+        apply_reconciled("quota-reconcile", 10, now);
+        assert_eq!(admit("quota-reconcile", limits, true, now), Err(QuotaExceeded::Monthly));
+
+        let next_month = at(1_701_388_800); // 2023-12-01
+        assert!(admit("quota-reconcile", limits, true, next_month).is_ok());
+    }
+
+    #[test]
+    fn refunded_events_free_up_quota() {
+        let limits = QuotaLimits { monthly: Some(1), per_second: Some(1) };
+        let now = at(1_700_000_000);
+        assert!(admit("quota-refund", limits, true, now).is_ok());
+        assert_eq!(admit("quota-refund", limits, true, now), Err(QuotaExceeded::Burst));
+
+        refund("quota-refund", true, now);
+        assert!(admit("quota-refund", limits, true, now).is_ok());
+        assert_eq!(admit("quota-refund", limits, true, at(1_700_000_001)), Err(QuotaExceeded::Monthly));
+    }
+
+    #[test]
+    fn reconciliation_marks_the_site_seeded() {
+        let limits = QuotaLimits { monthly: Some(10), per_second: None };
+        let now = at(1_700_000_000);
+        assert!(admit("quota-seed", limits, true, now).is_ok());
+        let usage = USAGE.get("quota-seed").unwrap();
+        assert!(!usage.lock().unwrap().seeded);
+
+        apply_reconciled("quota-seed", 3, now);
+        assert!(usage.lock().unwrap().seeded);
+    }
+
+    #[test]
+    fn unlimited_sites_are_not_tracked() {
+        assert!(admit("quota-unlimited", QuotaLimits::default(), true, at(0)).is_ok());
+        assert!(USAGE.get("quota-unlimited").is_none());
+    }
+}
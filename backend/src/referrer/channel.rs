@@ -0,0 +1,286 @@
+//! GA4-style default channel grouping, computed once at ingest so dashboards
+//! read a stored `channel` instead of re-deriving it from referrer and UTM
+//! columns in every query. The rules follow GA4's default channel group, in
+//! GA4's order; source categories come from `ga4-source-categories.csv`, with
+//! the referrer database's search/social/email mediums filling in for
+//! referrers the CSV does not list.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use tracing::{info, warn};
+
+use super::source_categories::{normalize_referrer_key, read_ga4_source_categories};
+use super::{ReferrerInfo, ReferrerSource, ai_source_name};
+use crate::campaign::CampaignInfo;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Direct,
+    CrossNetwork,
+    PaidShopping,
+    PaidSearch,
+    PaidSocial,
+    PaidVideo,
+    Display,
+    PaidOther,
+    AiAssistants,
+    OrganicShopping,
+    OrganicSocial,
+    OrganicVideo,
+    OrganicSearch,
+    Referral,
+    Email,
+    Affiliates,
+    Audio,
+    Sms,
+    MobilePushNotifications,
+    Unassigned,
+}
+
+impl Channel {
+    /// GA4's channel name, as stored in `analytics.events.channel`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Channel::Direct => "Direct",
+            Channel::CrossNetwork => "Cross-network",
+            Channel::PaidShopping => "Paid Shopping",
+            Channel::PaidSearch => "Paid Search",
+            Channel::PaidSocial => "Paid Social",
+            Channel::PaidVideo => "Paid Video",
+            Channel::Display => "Display",
+            Channel::PaidOther => "Paid Other",
+            Channel::AiAssistants => "AI Assistants",
+            Channel::OrganicShopping => "Organic Shopping",
+            Channel::OrganicSocial => "Organic Social",
+            Channel::OrganicVideo => "Organic Video",
+            Channel::OrganicSearch => "Organic Search",
+            Channel::Referral => "Referral",
+            Channel::Email => "Email",
+            Channel::Affiliates => "Affiliates",
+            Channel::Audio => "Audio",
+            Channel::Sms => "SMS",
+            Channel::MobilePushNotifications => "Mobile Push Notifications",
+            Channel::Unassigned => "Unassigned",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SourceCategory {
+    Search,
+    Social,
+    Shopping,
+    Video,
+    Email,
+}
+
+impl SourceCategory {
+    fn from_ga4(category: &str) -> Option<Self> {
+        match category {
+            "SOURCE_CATEGORY_SEARCH" => Some(Self::Search),
+            "SOURCE_CATEGORY_SOCIAL" => Some(Self::Social),
+            "SOURCE_CATEGORY_SHOPPING" => Some(Self::Shopping),
+            "SOURCE_CATEGORY_VIDEO" => Some(Self::Video),
+            "SOURCE_CATEGORY_EMAIL" => Some(Self::Email),
+            _ => None,
+        }
+    }
+}
+
+static SOURCE_CATEGORIES: OnceLock<HashMap<String, SourceCategory>> = OnceLock::new();
+
+static SHOPPING_CAMPAIGN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(.*(([^a-df-z]|^)shop|shopping).*)$").expect("valid shopping campaign regex"));
+
+/// Loads the GA4 source categories; until called, only the referrer database's
+/// mediums categorize sources
+pub fn initialize(ga4_source_categories_path: &Path) {
+    let categories = match read_ga4_source_categories(ga4_source_categories_path) {
+        Ok(rows) => rows
+            .into_iter()
+            .filter_map(|(key, category)| SourceCategory::from_ga4(&category).map(|category| (key, category)))
+            .collect(),
+        Err(e) => {
+            warn!("Could not load GA4 source categories from {:?}: {}", ga4_source_categories_path, e);
+            HashMap::new()
+        }
+    };
+    info!("Loaded {} GA4 source categories for channel grouping", categories.len());
+    let _ = SOURCE_CATEGORIES.set(categories);
+}
+
+/// The channel of an event from its parsed referrer and campaign parameters
+pub fn classify_channel(referrer: &ReferrerInfo, campaign: &CampaignInfo) -> Channel {
+    let lower = |value: &Option<String>| value.as_deref().map(|v| v.trim().to_lowercase()).filter(|v| !v.is_empty());
+    let utm_source = lower(&campaign.utm_source);
+    let utm_medium = lower(&campaign.utm_medium);
+    let campaign_name = lower(&campaign.utm_campaign).unwrap_or_default();
+    let tagged = utm_source.is_some() || utm_medium.is_some();
+
+    // Untagged clicks carrying an ads-only click ID are paid by definition.
+    // Meta adds fbclid to organic posts and shares too, so those clicks fall
+    // through to the referrer rules like GA4's.
+    if utm_medium.is_none() {
+        match campaign.click_id_network.as_deref() {
+            Some("google_ads" | "microsoft_ads") => return Channel::PaidSearch,
+            Some("tiktok" | "linkedin" | "x") => return Channel::PaidSocial,
+            _ => {}
+        }
+    }
+
+    let sources = match &utm_source {
+        Some(source) => vec![source.clone()],
+        None => referrer_sources(referrer),
+    };
+    let medium = match utm_medium {
+        Some(medium) => medium,
+        None if tagged => String::new(),
+        None => match referrer.source_type {
+            ReferrerSource::Direct | ReferrerSource::Internal => return Channel::Direct,
+            ReferrerSource::Search => "organic".to_string(),
+            ReferrerSource::Social => "social".to_string(),
+            ReferrerSource::Email => "email".to_string(),
+            ReferrerSource::Ai | ReferrerSource::Other => "referral".to_string(),
+        },
+    };
+
+    let category = source_category(&sources).or(match (tagged, &referrer.source_type) {
+        (false, ReferrerSource::Search) => Some(SourceCategory::Search),
+        (false, ReferrerSource::Social) => Some(SourceCategory::Social),
+        (false, ReferrerSource::Email) => Some(SourceCategory::Email),
+        _ => None,
+    });
+    let is_ai = (!tagged && referrer.source_type == ReferrerSource::Ai)
+        || sources.iter().any(|source| ai_source_name(source).is_some());
+    let is_paid = medium.contains("cp") || medium == "ppc" || medium == "retargeting" || medium.starts_with("paid");
+    let is_shopping = category == Some(SourceCategory::Shopping) || SHOPPING_CAMPAIGN.is_match(&campaign_name);
+    let source_is = |name: &str| sources.iter().any(|source| source == name);
+
+    if campaign_name.contains("cross-network") {
+        Channel::CrossNetwork
+    } else if is_shopping && is_paid {
+        Channel::PaidShopping
+    } else if category == Some(SourceCategory::Search) && is_paid {
+        Channel::PaidSearch
+    } else if category == Some(SourceCategory::Social) && is_paid {
+        Channel::PaidSocial
+    } else if category == Some(SourceCategory::Video) && is_paid {
+        Channel::PaidVideo
+    } else if matches!(medium.as_str(), "display" | "banner" | "expandable" | "interstitial" | "cpm") {
+        Channel::Display
+    } else if is_paid {
+        Channel::PaidOther
+    } else if is_ai {
+        Channel::AiAssistants
+    } else if is_shopping {
+        Channel::OrganicShopping
+    } else if category == Some(SourceCategory::Social)
+        || matches!(medium.as_str(), "social" | "social-network" | "social-media" | "sm" | "social network" | "social media")
+    {
+        Channel::OrganicSocial
+    } else if category == Some(SourceCategory::Video) || medium.contains("video") {
+        Channel::OrganicVideo
+    } else if category == Some(SourceCategory::Search) || medium == "organic" {
+        Channel::OrganicSearch
+    } else if matches!(medium.as_str(), "referral" | "app" | "link") {
+        Channel::Referral
+    } else if category == Some(SourceCategory::Email)
+        || ["email", "e-mail", "e_mail", "e mail"].iter().any(|name| source_is(name) || medium == *name)
+    {
+        Channel::Email
+    } else if medium == "affiliate" {
+        Channel::Affiliates
+    } else if medium == "audio" {
+        Channel::Audio
+    } else if source_is("sms") || medium == "sms" {
+        Channel::Sms
+    } else if medium.ends_with("push") || medium.contains("mobile") || medium.contains("notification") || source_is("firebase") {
+        Channel::MobilePushNotifications
+    } else {
+        Channel::Unassigned
+    }
+}
+
+/// Names a referrer can be looked up by: its host, the referrer database's
+/// source name and its root domain
+fn referrer_sources(referrer: &ReferrerInfo) -> Vec<String> {
+    let host = referrer
+        .url
+        .as_deref()
+        .and_then(|url| url.split(['/', ':', '?']).next())
+        .map(normalize_referrer_key);
+    [host, referrer.source_canonical.as_deref().map(normalize_referrer_key), referrer.source_name.as_deref().map(normalize_referrer_key)]
+        .into_iter()
+        .flatten()
+        .filter(|source| !source.is_empty())
+        .collect()
+}
+
+fn source_category(sources: &[String]) -> Option<SourceCategory> {
+    let categories = SOURCE_CATEGORIES.get()?;
+    sources.iter().find_map(|source| categories.get(&normalize_referrer_key(source)).copied())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn referrer(source_type: ReferrerSource, url: &str) -> ReferrerInfo {
+        ReferrerInfo {
+            url: Some(url.to_string()),
+            source_type,
+            source_canonical: None,
+            source_name: Some(url.split('/').next().unwrap().to_string()),
+            search_term: None,
+        }
+    }
+
+    fn campaign(source: Option<&str>, medium: Option<&str>) -> CampaignInfo {
+        CampaignInfo {
+            utm_source: source.map(str::to_string),
+            utm_medium: medium.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn untagged_traffic_follows_the_referrer() {
+        let none = CampaignInfo::default();
+        assert_eq!(classify_channel(&ReferrerInfo::default(), &none), Channel::Direct);
+        assert_eq!(classify_channel(&referrer(ReferrerSource::Search, "google.com/search"), &none), Channel::OrganicSearch);
+        assert_eq!(classify_channel(&referrer(ReferrerSource::Social, "reddit.com"), &none), Channel::OrganicSocial);
+        assert_eq!(classify_channel(&referrer(ReferrerSource::Other, "blog.example.org/post"), &none), Channel::Referral);
+        assert_eq!(classify_channel(&referrer(ReferrerSource::Ai, "chatgpt.com"), &none), Channel::AiAssistants);
+    }
+
+    #[test]
+    fn utm_medium_decides_tagged_traffic() {
+        crate::referrer::tests::load_ai_sources();
+        let direct = ReferrerInfo::default();
+        assert_eq!(classify_channel(&direct, &campaign(Some("newsletter"), Some("email"))), Channel::Email);
+        assert_eq!(classify_channel(&direct, &campaign(Some("partner"), Some("affiliate"))), Channel::Affiliates);
+        assert_eq!(classify_channel(&direct, &campaign(Some("network"), Some("banner"))), Channel::Display);
+        assert_eq!(classify_channel(&direct, &campaign(Some("network"), Some("cpc"))), Channel::PaidOther);
+        assert_eq!(classify_channel(&direct, &campaign(Some("chatgpt.com"), None)), Channel::AiAssistants);
+        assert_eq!(classify_channel(&direct, &campaign(Some("something"), None)), Channel::Unassigned);
+    }
+
+    #[test]
+    fn ads_only_click_ids_without_a_medium_are_paid() {
+        let mut info = CampaignInfo { click_id_network: Some("google_ads".to_string()), ..Default::default() };
+        assert_eq!(classify_channel(&ReferrerInfo::default(), &info), Channel::PaidSearch);
+        info.click_id_network = Some("tiktok".to_string());
+        assert_eq!(classify_channel(&ReferrerInfo::default(), &info), Channel::PaidSocial);
+    }
+
+    #[test]
+    fn fbclid_follows_the_referrer() {
+        let info = CampaignInfo { click_id_network: Some("meta".to_string()), ..Default::default() };
+        assert_eq!(classify_channel(&referrer(ReferrerSource::Social, "facebook.com"), &info), Channel::OrganicSocial);
+        assert_eq!(classify_channel(&ReferrerInfo::default(), &info), Channel::Direct);
+    }
+}
@@ -1,13 +1,16 @@
 use refparser::RefDb;
+use std::collections::HashMap;
 use std::sync::OnceLock;
 use url::Url;
 use std::path::Path;
-use tracing::info;
+use tracing::{info, warn};
 use crate::url_utils::{normalize_url, extract_root_domain};
 
+pub mod channel;
 mod source_categories;
 mod sync;
 
+pub use channel::{Channel, classify_channel};
 pub use sync::sync_referrer_categories;
 
 /// Referrer source categories
@@ -18,6 +21,8 @@ pub enum ReferrerSource {
     Social,
     Email,
     Internal,
+    /// AI assistants citing the site (ChatGPT, Perplexity, ...)
+    Ai,
     Other,
 }
 
@@ -36,6 +41,7 @@ impl ReferrerSource {
             ReferrerSource::Social => "social",
             ReferrerSource::Email => "email",
             ReferrerSource::Internal => "internal",
+            ReferrerSource::Ai => "ai",
             ReferrerSource::Other => "other",
         }
     }
@@ -47,6 +53,7 @@ impl ReferrerSource {
             Some("social") => ReferrerSource::Social,
             Some("internal") => ReferrerSource::Internal,
             Some("email") => ReferrerSource::Email,
+            Some("ai") => ReferrerSource::Ai,
             None => ReferrerSource::Direct,
             _ => ReferrerSource::Other,
         }
@@ -70,7 +77,12 @@ pub struct ReferrerInfo {
 
 static PARSER: OnceLock<RefDb> = OnceLock::new();
 
-pub fn initialize(referrer_db_path: &Path) {
+/// Domains and lowercase names of AI assistants to their canonical name, from
+/// the `ai` section of the custom referrers file
+static AI_SOURCES: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+pub fn initialize(referrer_db_path: &Path, custom_referrers_path: &Path) {
+    initialize_ai_sources(custom_referrers_path);
     info!("Initializing referrer parser from: {:?}", referrer_db_path);
 
     PARSER.get_or_init(|| {
@@ -84,6 +96,47 @@ pub fn initialize(referrer_db_path: &Path) {
     });
 }
 
+fn initialize_ai_sources(custom_referrers_path: &Path) {
+    AI_SOURCES.get_or_init(|| {
+        let sources = source_categories::read_medium_sources(custom_referrers_path, "ai").unwrap_or_else(|e| {
+            warn!("Could not load AI referrers from {:?}: {}", custom_referrers_path, e);
+            Vec::new()
+        });
+        let mut index = HashMap::new();
+        for (name, domains) in sources {
+            for domain in domains {
+                index.insert(domain, name.clone());
+            }
+            index.insert(name.to_lowercase(), name);
+        }
+        info!("Loaded {} AI referrer keys", index.len());
+        index
+    });
+}
+
+/// Canonical name of the AI assistant behind a referrer host or a `utm_source`
+/// value such as `chatgpt.com`; subdomains of a listed domain match too
+pub fn ai_source_name(source: &str) -> Option<&'static str> {
+    let index = AI_SOURCES.get()?;
+    let mut key = source_categories::normalize_referrer_key(source);
+    loop {
+        if let Some(name) = index.get(&key) {
+            return Some(name.as_str());
+        }
+        key = key.split_once('.')?.1.to_string();
+        if !key.contains('.') {
+            return None;
+        }
+    }
+}
+
+/// AI assistant named by the `utm_source` of the landing URL
+fn ai_source_from_utm(current_url: Option<&str>) -> Option<&'static str> {
+    let url = Url::parse(current_url?).ok()?;
+    let (_, source) = url.query_pairs().find(|(key, _)| key == "utm_source")?;
+    ai_source_name(&source)
+}
+
 fn get_parser() -> &'static RefDb {
     PARSER.get().expect("Referrer parser not initialized. Call initialize() first.")
 }
@@ -137,10 +190,12 @@ pub fn parse_referrer(referrer: Option<&str>, current_url: Option<&str>) -> Refe
     let referrer_str = match referrer {
         Some(r) if !r.is_empty() => r,
         _ => {
+            // AI assistants that strip the referrer still tag their links
+            let ai_source = ai_source_from_utm(current_url);
             return ReferrerInfo {
                 url: None,
-                source_type: ReferrerSource::Direct,
-                source_canonical: None,
+                source_type: if ai_source.is_some() { ReferrerSource::Ai } else { ReferrerSource::Direct },
+                source_canonical: ai_source.map(str::to_string),
                 source_name: None,
                 search_term: None,
             }
@@ -176,14 +231,25 @@ pub fn parse_referrer(referrer: Option<&str>, current_url: Option<&str>) -> Refe
             .host_str()
             .and_then(|h| Url::parse(&format!("http://{}/", h)).ok())
     };
-    let referrer_info = lookup_url.as_ref().and_then(|u| parser.lookup(u));
-    let source_canonical = referrer_info
-        .as_ref()
-        .map(|ref_info| ref_info.source.clone())
-        .filter(|source| !source.is_empty());
+    // AI assistants take precedence over the referrer database, which lists
+    // some of them as search engines or social networks
+    let ai_source = referrer_url.host_str().and_then(ai_source_name);
+    let referrer_info = match ai_source {
+        Some(_) => None,
+        None => lookup_url.as_ref().and_then(|u| parser.lookup(u)),
+    };
+    let source_canonical = match ai_source {
+        Some(name) => Some(name.to_string()),
+        None => referrer_info
+            .as_ref()
+            .map(|ref_info| ref_info.source.clone())
+            .filter(|source| !source.is_empty()),
+    };
 
     // Determine the source based on refparser result
-    let source_type = if let Some(ref_info) = &referrer_info {
+    let source_type = if ai_source.is_some() {
+        ReferrerSource::Ai
+    } else if let Some(ref_info) = &referrer_info {
         let medium = if ref_info.medium.is_empty() {
             None 
         } else { 
@@ -268,3 +334,30 @@ fn extract_search_term(url: &Url, param_names: &[String]) -> Option<String> {
     None
 }
 
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    pub(in crate::referrer) fn load_ai_sources() {
+        initialize_ai_sources(Path::new("assets/referrers_lists/custom_referrers.json"));
+    }
+
+    #[test]
+    fn ai_sources_match_domains_subdomains_and_names() {
+        load_ai_sources();
+        assert_eq!(ai_source_name("chatgpt.com"), Some("ChatGPT"));
+        assert_eq!(ai_source_name("https://www.perplexity.ai/"), Some("Perplexity"));
+        assert_eq!(ai_source_name("labs.perplexity.ai"), Some("Perplexity"));
+        assert_eq!(ai_source_name("Claude"), Some("Claude"));
+        assert_eq!(ai_source_name("gemini.google.com"), Some("Gemini"));
+        assert_eq!(ai_source_name("google.com"), None);
+    }
+
+    #[test]
+    fn ai_utm_sources_are_read_from_the_landing_url() {
+        load_ai_sources();
+        assert_eq!(ai_source_from_utm(Some("https://example.com/?utm_source=chatgpt.com")), Some("ChatGPT"));
+        assert_eq!(ai_source_from_utm(Some("https://example.com/?utm_source=newsletter")), None);
+        assert_eq!(ai_source_from_utm(None), None);
+    }
+}
@@ -71,6 +71,32 @@ fn merge_referrer_categories(
     Ok(())
 }
 
+/// Sources listed under `medium` in a referrer JSON file, as (name, normalized
+/// domains); empty when the file is missing
+pub(super) fn read_medium_sources(path: &Path, medium: &str) -> Result<Vec<(String, Vec<String>)>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let contents = std::fs::read_to_string(path)?;
+    let mut parsed: ReferrerJson = serde_json::from_str(&contents)?;
+
+    Ok(parsed
+        .remove(medium)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(name, entry)| {
+            let domains = entry
+                .domains
+                .iter()
+                .map(|domain| normalize_referrer_key(domain))
+                .filter(|domain| !domain.is_empty())
+                .collect();
+            (name, domains)
+        })
+        .collect())
+}
+
 fn ambiguous_source_names(parsed: &ReferrerJson) -> HashSet<String> {
     let mut source_mediums: HashMap<&str, String> = HashMap::new();
     let mut ambiguous = HashSet::new();
@@ -120,12 +146,24 @@ fn merge_ga4_source_categories(
     categories: &mut HashMap<String, String>,
     path: &Path,
 ) -> Result<()> {
+    for (key, category) in read_ga4_source_categories(path)? {
+        let medium = normalize_ga4_source_category(&category);
+        insert_referrer_category_key(categories, key, &medium, InsertMode::KeepExisting);
+    }
+
+    Ok(())
+}
+
+/// Rows of the GA4 source category CSV as (normalized source, `SOURCE_CATEGORY_*`);
+/// empty when the file is missing
+pub(super) fn read_ga4_source_categories(path: &Path) -> Result<Vec<(String, String)>> {
     if !path.exists() {
         tracing::warn!("GA4 source categories file does not exist: {:?}; skipping", path);
-        return Ok(());
+        return Ok(Vec::new());
     }
 
     let contents = std::fs::read_to_string(path)?;
+    let mut rows = Vec::new();
 
     for (index, line) in contents.lines().enumerate() {
         let line = line.trim();
@@ -138,15 +176,13 @@ fn merge_ga4_source_categories(
             continue;
         };
 
-        let medium = normalize_ga4_source_category(category.trim());
-        let key = normalize_referrer_key(source);
-        insert_referrer_category_key(categories, key, &medium, InsertMode::KeepExisting);
+        rows.push((normalize_referrer_key(source), category.trim().to_string()));
     }
 
-    Ok(())
+    Ok(rows)
 }
 
-fn normalize_referrer_key(key: &str) -> String {
+pub(super) fn normalize_referrer_key(key: &str) -> String {
     let lowercase = key.to_lowercase();
     let without_scheme = lowercase
         .trim()
@@ -161,7 +197,7 @@ fn normalize_referrer_key(key: &str) -> String {
 
 fn normalize_referrer_medium(medium: &str) -> String {
     match medium {
-        "search" | "social" | "email" | "internal" => medium.to_string(),
+        "search" | "social" | "email" | "internal" | "ai" => medium.to_string(),
         _ => "other".to_string(),
     }
 }
@@ -59,6 +59,16 @@ pub async fn init(pool: Arc<PostgresPool>) -> Result<()> {
     Ok(())
 }
 
+/// Fixed salts for tests that run events through visitor identification
+#[cfg(test)]
+pub fn init_for_tests() {
+    let _ = STATE.set(ArcSwap::from_pointee(SaltState {
+        date: Utc::now().date_naive(),
+        current: [1u8; 16],
+        previous: None,
+    }));
+}
+
 /// Return today's `current` salt and the `previous` salt.
 ///
 /// Lock-free read on the hot path. When the UTC day has rolled over, the cached salts (still
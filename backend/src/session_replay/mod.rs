@@ -7,6 +7,7 @@ use moka::sync::Cache;
 use once_cell::sync::Lazy;
 
 use crate::client_request::ClientRequest;
+use crate::ingest::body::DecodedJson;
 use crate::storage::s3::S3Service;
 use crate::site_config::SiteConfigCache;
 use crate::ua_parser;
@@ -81,7 +82,7 @@ mod u64_as_string {
 pub async fn presign_put_segment(
     State((_, processor, _, _, s3, _)): State<(SharedDatabase, Arc<EventProcessor>, Option<Arc<MetricsCollector>>, Arc<EventValidator>, Option<Arc<S3Service>>, Arc<SiteConfigCache>)>,
     client: ClientRequest,
-    Json(req): Json<PresignPutRequest>,
+    DecodedJson(req): DecodedJson<PresignPutRequest>,
 ) -> Result<Json<PresignPutResponse>, (StatusCode, String)> {
     let s3 = s3.ok_or((StatusCode::SERVICE_UNAVAILABLE, "S3 not configured".to_string()))?;
 
@@ -114,6 +115,7 @@ pub async fn presign_put_segment(
             browser_version: parsed.browser_version.as_deref(),
             os: Some(parsed.os.as_str()),
             root_domain: root_domain.as_deref(),
+            visitor_key: None,
         };
         visitor::identify(&req.site_id, &attrs, Utc::now())
     };
@@ -162,7 +164,7 @@ pub struct FinalizeRequest {
 pub async fn finalize_session_replay(
     State((db, processor, _, _, _, _)): State<(SharedDatabase, Arc<EventProcessor>, Option<Arc<MetricsCollector>>, Arc<EventValidator>, Option<Arc<S3Service>>, Arc<SiteConfigCache>)>,
     client: ClientRequest,
-    Json(req): Json<FinalizeRequest>,
+    DecodedJson(req): DecodedJson<FinalizeRequest>,
 ) -> Result<StatusCode, (StatusCode, String)> {
     if processor.check_replay_request(
         &req.site_id,
@@ -4,16 +4,27 @@ use std::time::Duration as StdDuration;
 
 use arc_swap::ArcSwap;
 use chrono::{DateTime, TimeZone, Utc};
+use sha2::{Digest, Sha256};
 use tokio::sync::RwLock;
 use tokio::time::{interval, MissedTickBehavior};
 use tracing::{debug, warn};
 
+use crate::campaign::{self, CampaignAliases};
 use crate::metrics::MetricsCollector;
 use crate::postgres::PostgresError;
-use crate::utils::spawn_supervised;
+use crate::url_utils::PathOptions;
+use crate::url_utils::path_rules::PathRewriter;
+use crate::postgres::listen::{self, RefreshKind};
+use crate::utils::{constant_time_eq, spawn_supervised};
+use crate::validation::domain_policy::DomainMode;
+use crate::validation::exclusion::{self, ExclusionRule};
+use crate::validation::network_policy::{NetworkMode, NetworkPolicy};
+use crate::validation::property_schema::{self, PropertySchemas, SchemaMode};
 use super::repository::{SiteConfigDataSource, SiteConfigRecord};
 
 const CACHE_NAME: &str = "site_config";
+/// NOTIFY channel fed by the `SiteConfig` and `Dashboard` triggers
+pub const CHANGE_CHANNEL: &str = "site_config_changed";
 const HEALTH_CHECK_INTERVAL: StdDuration = StdDuration::from_secs(30);
 
 #[derive(Clone, Copy, Debug)]
@@ -37,15 +48,125 @@ impl Default for RefreshConfig {
 pub struct SiteConfig {
     pub domain: String,
     pub blacklisted_ips: Vec<String>,
+    /// IPs and CIDR ranges whose events are flagged as internal
+    pub internal_networks: Vec<String>,
+    /// Cookie or header value that flags an event as internal
+    pub internal_traffic_token: Option<String>,
     pub enforce_domain: bool,
+    /// Hostnames besides `domain` that serve the site
+    pub domain_aliases: Vec<String>,
+    /// Hostnames and wildcard patterns allowed besides `domain` and the aliases
+    pub allowed_domains: Vec<String>,
+    pub allow_subdomains: bool,
+    pub domain_mode: DomainMode,
+    /// Lowercase hex SHA-256 digests of the server-side ingest API keys
+    pub ingest_api_key_hashes: Vec<String>,
+    /// Billable events per calendar month; None is unlimited
+    pub monthly_event_quota: Option<u64>,
+    /// Events per second per instance; None is unlimited
+    pub events_per_second_limit: Option<u32>,
+    /// Custom event property schemas by event name
+    pub property_schemas: PropertySchemas,
+    pub property_schema_mode: SchemaMode,
+    /// Events matching any of these are dropped before storage
+    pub exclusion_rules: Vec<ExclusionRule>,
+    /// Query parameters read as campaign fields; None when the site has none
+    pub campaign_aliases: Option<Arc<CampaignAliases>>,
+    /// Rewrites stored paths; None when the site has no rules or detectors
+    pub path_rewriter: Option<Arc<PathRewriter>>,
+    /// Hash routing and retained query parameters; None when the site uses neither
+    pub path_options: Option<Arc<PathOptions>>,
+    /// GA4 measurement ID routed to this site by `/mp/collect`
+    pub ga4_measurement_id: Option<String>,
+    /// Country and ASN rules applied by the processor; None when the site has none
+    pub network_policy: Option<Arc<NetworkPolicy>>,
+}
+
+impl SiteConfig {
+    /// True when `api_key` hashes to one of the site's registered ingest keys
+    pub fn accepts_api_key(&self, api_key: &str) -> bool {
+        if api_key.is_empty() {
+            return false;
+        }
+        let digest = hex::encode(Sha256::digest(api_key.as_bytes()));
+        self.ingest_api_key_hashes
+            .iter()
+            .any(|stored| constant_time_eq(stored.trim().to_ascii_lowercase().as_bytes(), digest.as_bytes()))
+    }
 }
 
 impl From<SiteConfigRecord> for SiteConfig {
     fn from(record: SiteConfigRecord) -> Self {
+        let property_schemas = property_schema::parse_schemas(&record.custom_event_schemas)
+            .unwrap_or_else(|e| {
+                warn!(site_id = %record.site_id, error = %e, "Ignoring malformed custom event schemas");
+                PropertySchemas::new()
+            });
+        let property_schema_mode = record.custom_event_schema_mode.parse().unwrap_or_else(|e| {
+            warn!(site_id = %record.site_id, error = %e, "Falling back to reject mode for custom event schemas");
+            SchemaMode::default()
+        });
+        let (exclusion_rules, exclusion_errors) = exclusion::parse_exclusion_rules(&record.exclusion_rules);
+        for e in exclusion_errors {
+            warn!(site_id = %record.site_id, error = %e, "Skipping malformed exclusion rule");
+        }
+        let campaign_aliases = campaign::parse_campaign_aliases(&record.campaign_param_aliases)
+            .unwrap_or_else(|e| {
+                warn!(site_id = %record.site_id, error = %e, "Ignoring malformed campaign parameter aliases");
+                CampaignAliases::new()
+            });
+        let (path_rewriter, path_rule_errors) =
+            PathRewriter::parse(&record.path_rules, &record.path_id_detectors, record.keep_raw_path);
+        for e in path_rule_errors {
+            warn!(site_id = %record.site_id, error = %e, "Skipping malformed path rule");
+        }
+        let domain_mode = record.domain_enforcement_mode.parse().unwrap_or_else(|e| {
+            warn!(site_id = %record.site_id, error = %e, "Falling back to reject mode for domain enforcement");
+            DomainMode::default()
+        });
+        let network_mode = record.network_policy_mode.parse().unwrap_or_else(|e| {
+            warn!(site_id = %record.site_id, error = %e, "Falling back to enforce mode for the network policy");
+            NetworkMode::default()
+        });
+        let network_policy = NetworkPolicy::new(
+            record.blocked_countries,
+            record.allowed_countries,
+            record.blocked_asns,
+            record.allowed_asns,
+            network_mode,
+        )
+        .map(Arc::new);
         Self {
             domain: record.domain,
             blacklisted_ips: record.blacklisted_ips,
+            internal_networks: record.internal_networks,
+            internal_traffic_token: record
+                .internal_traffic_token
+                .map(|token| token.trim().to_string())
+                .filter(|token| !token.is_empty()),
             enforce_domain: record.enforce_domain,
+            domain_aliases: record.domain_aliases,
+            allowed_domains: record.allowed_domains,
+            allow_subdomains: record.allow_subdomains,
+            domain_mode,
+            ingest_api_key_hashes: record.ingest_api_key_hashes,
+            monthly_event_quota: record
+                .monthly_event_quota
+                .and_then(|quota| u64::try_from(quota).ok()),
+            events_per_second_limit: record
+                .events_per_second_limit
+                .and_then(|limit| u32::try_from(limit).ok()),
+            property_schemas,
+            property_schema_mode,
+            exclusion_rules,
+            campaign_aliases: (!campaign_aliases.is_empty()).then(|| Arc::new(campaign_aliases)),
+            path_rewriter: (!path_rewriter.is_empty()).then(|| Arc::new(path_rewriter)),
+            path_options: PathOptions::new(record.hash_routing, record.retained_query_params).map(Arc::new),
+            ga4_measurement_id: record
+                .ga4_measurement_id
+                .map(|id| id.trim().to_string())
+                .filter(|id| !id.is_empty()),
+            network_policy,
         }
     }
 }
@@ -58,6 +179,10 @@ pub enum SiteConfigError {
 
 pub struct SiteConfigCache {
     configs: ArcSwap<HashMap<String, Arc<SiteConfig>>>,
+    /// Normalized site domain to site_id, rebuilt whenever `configs` changes
+    domain_index: ArcSwap<HashMap<String, String>>,
+    /// GA4 measurement ID to site_id, rebuilt alongside `domain_index`
+    measurement_id_index: ArcSwap<HashMap<String, String>>,
     data_source: Arc<dyn SiteConfigDataSource>,
     metrics: Option<Arc<MetricsCollector>>,
     refresh_config: RefreshConfig,
@@ -83,6 +208,8 @@ impl SiteConfigCache {
     ) -> Result<Arc<Self>, SiteConfigError> {
         let cache = Arc::new(Self {
             configs: ArcSwap::from_pointee(HashMap::new()),
+            domain_index: ArcSwap::from_pointee(HashMap::new()),
+            measurement_id_index: ArcSwap::from_pointee(HashMap::new()),
             data_source,
             metrics,
             refresh_config,
@@ -98,6 +225,34 @@ impl SiteConfigCache {
         Ok(cache)
     }
 
+    /// Refreshes as soon as a change is announced on `CHANGE_CHANNEL`; the
+    /// polling refreshes keep running as the fallback
+    pub fn listen_for_changes(self: &Arc<Self>, database_url: &str) -> Result<(), PostgresError> {
+        let changes = listen::spawn_change_listener(
+            database_url,
+            "betterlytics_site_config_listen",
+            CHANGE_CHANNEL,
+            Some("SiteConfig"),
+        )?;
+        let this = Arc::clone(self);
+        spawn_supervised("site_config_cache_notify_refresh", move || {
+            let (this, changes) = (Arc::clone(&this), Arc::clone(&changes));
+            async move {
+                let mut changes = changes.lock().await;
+                while let Some(kind) = listen::next_coalesced(&mut changes).await {
+                    let result = match kind {
+                        RefreshKind::Partial => this.perform_partial_refresh().await,
+                        RefreshKind::Full => this.perform_full_refresh().await,
+                    };
+                    if let Err(err) = result {
+                        this.handle_refresh_error(kind.as_str(), err).await;
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+
     pub fn get(&self, site_id: &str) -> Option<Arc<SiteConfig>> {
         let map = self.configs.load();
         let cfg = map.get(site_id).cloned();
@@ -110,6 +265,62 @@ impl SiteConfigCache {
         cfg
     }
 
+    /// Sites with a monthly event quota
+    pub fn site_ids_with_quota(&self) -> Vec<String> {
+        self.configs
+            .load()
+            .iter()
+            .filter(|(_, cfg)| cfg.monthly_event_quota.is_some())
+            .map(|(site_id, _)| site_id.clone())
+            .collect()
+    }
+
+    /// Site whose configured domain or alias matches `host`, ignoring case,
+    /// port and a leading `www.`
+    pub fn site_id_for_domain(&self, host: &str) -> Option<String> {
+        self.domain_index.load().get(&normalize_domain(host)).cloned()
+    }
+
+    /// Site configured for a GA4 measurement ID (case-insensitive)
+    pub fn site_id_for_measurement_id(&self, measurement_id: &str) -> Option<String> {
+        self.measurement_id_index
+            .load()
+            .get(&measurement_id.trim().to_ascii_uppercase())
+            .cloned()
+    }
+
+    fn rebuild_indexes(&self) {
+        let configs = self.configs.load();
+        // Primary domains win over aliases; otherwise several sites on one
+        // host resolve to the smallest site_id, the same one on every rebuild
+        let mut index: HashMap<String, (bool, String)> = HashMap::with_capacity(configs.len());
+        let mut measurement_ids = HashMap::new();
+        for (site_id, cfg) in configs.iter() {
+            if let Some(id) = &cfg.ga4_measurement_id {
+                measurement_ids.insert(id.to_ascii_uppercase(), site_id.clone());
+            }
+            let hosts = std::iter::once((false, &cfg.domain)).chain(cfg.domain_aliases.iter().map(|a| (true, a)));
+            for (is_alias, host) in hosts {
+                let host = normalize_domain(host);
+                if host.is_empty() {
+                    continue;
+                }
+                let candidate = (is_alias, site_id.clone());
+                index
+                    .entry(host)
+                    .and_modify(|existing| {
+                        if candidate < *existing {
+                            *existing = candidate.clone();
+                        }
+                    })
+                    .or_insert(candidate);
+            }
+        }
+        let index = index.into_iter().map(|(host, (_, site_id))| (host, site_id)).collect();
+        self.domain_index.store(Arc::new(index));
+        self.measurement_id_index.store(Arc::new(measurement_ids));
+    }
+
     async fn perform_full_refresh(&self) -> Result<(), SiteConfigError> {
         let records = self.data_source.fetch_all_configs().await?;
         let count = records.len();
@@ -123,6 +334,7 @@ impl SiteConfigCache {
             );
         }
         self.configs.store(Arc::new(new_map));
+        self.rebuild_indexes();
         *self.last_full_refresh_at.write().await = Some(Utc::now());
         self.update_last_seen(max_updated).await;
         self.mark_refresh_success().await;
@@ -161,6 +373,7 @@ impl SiteConfigCache {
     
             Arc::new(new_map)
         });
+        self.rebuild_indexes();
     
         let updated = updates.len();
         self.update_last_seen(max_updated).await;
@@ -277,8 +490,143 @@ impl SiteConfigCache {
     }
 }
 
+pub fn normalize_domain(host: &str) -> String {
+    let host = host.trim().trim_end_matches('.').to_ascii_lowercase();
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if !name.contains(':') && port.chars().all(|c| c.is_ascii_digit()) => name.to_string(),
+        _ => host,
+    };
+    host.strip_prefix("www.").map(str::to_string).unwrap_or(host)
+}
+
 fn epoch_timestamp() -> DateTime<Utc> {
     Utc.timestamp_opt(0, 0).unwrap()
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn api_key_matches_stored_digest() {
+        let cfg = SiteConfig {
+            domain: String::new(),
+            blacklisted_ips: Vec::new(),
+            internal_networks: Vec::new(),
+            internal_traffic_token: None,
+            enforce_domain: false,
+            domain_aliases: Vec::new(),
+            allowed_domains: Vec::new(),
+            allow_subdomains: true,
+            domain_mode: DomainMode::default(),
+            ingest_api_key_hashes: vec![hex::encode(Sha256::digest(b"key-one")).to_uppercase()],
+            monthly_event_quota: None,
+            events_per_second_limit: None,
+            property_schemas: PropertySchemas::new(),
+            property_schema_mode: SchemaMode::default(),
+            exclusion_rules: Vec::new(),
+            campaign_aliases: None,
+            path_rewriter: None,
+            path_options: None,
+            ga4_measurement_id: None,
+            network_policy: None,
+        };
+        assert!(cfg.accepts_api_key("key-one"));
+        assert!(!cfg.accepts_api_key("key-two"));
+        assert!(!cfg.accepts_api_key(""));
+    }
+
+    struct StaticSource(Vec<SiteConfigRecord>);
+
+    #[async_trait::async_trait]
+    impl SiteConfigDataSource for StaticSource {
+        async fn fetch_all_configs(&self) -> Result<Vec<SiteConfigRecord>, PostgresError> {
+            Ok(self.0.clone())
+        }
+
+        async fn fetch_configs_updated_since(
+            &self,
+            _since: DateTime<Utc>,
+        ) -> Result<Vec<SiteConfigRecord>, PostgresError> {
+            Ok(Vec::new())
+        }
+    }
+
+    fn record(site_id: &str, domain: &str) -> SiteConfigRecord {
+        SiteConfigRecord {
+            site_id: site_id.to_string(),
+            domain: domain.to_string(),
+            blacklisted_ips: Vec::new(),
+            internal_networks: Vec::new(),
+            internal_traffic_token: None,
+            enforce_domain: false,
+            domain_aliases: Vec::new(),
+            allowed_domains: Vec::new(),
+            allow_subdomains: true,
+            domain_enforcement_mode: "reject".to_string(),
+            ingest_api_key_hashes: Vec::new(),
+            monthly_event_quota: None,
+            events_per_second_limit: None,
+            custom_event_schemas: serde_json::Value::Null,
+            custom_event_schema_mode: "reject".to_string(),
+            exclusion_rules: serde_json::Value::Null,
+            campaign_param_aliases: serde_json::Value::Null,
+            path_rules: serde_json::Value::Null,
+            path_id_detectors: Vec::new(),
+            keep_raw_path: false,
+            hash_routing: false,
+            retained_query_params: Vec::new(),
+            ga4_measurement_id: None,
+            blocked_countries: Vec::new(),
+            allowed_countries: Vec::new(),
+            blocked_asns: Vec::new(),
+            allowed_asns: Vec::new(),
+            network_policy_mode: "enforce".to_string(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn sites_are_found_by_domain() {
+        let source = StaticSource(vec![
+            record("site-b", "example.com"),
+            record("site-a", "www.example.com"),
+            record("site-c", "Shop.Example.org"),
+        ]);
+        let cache = SiteConfigCache::new_internal(Arc::new(source), RefreshConfig::default(), None, false)
+            .await
+            .unwrap();
+
+        assert_eq!(cache.site_id_for_domain("EXAMPLE.com:443").as_deref(), Some("site-a"));
+        assert_eq!(cache.site_id_for_domain("shop.example.org.").as_deref(), Some("site-c"));
+        assert_eq!(cache.site_id_for_domain("example.org"), None);
+    }
+
+    #[tokio::test]
+    async fn aliases_resolve_but_never_shadow_a_primary_domain() {
+        let mut aliased = record("site-0", "example.net");
+        aliased.domain_aliases = vec!["blog.example.net".to_string(), "example.com".to_string()];
+        let source = StaticSource(vec![aliased, record("site-a", "example.com")]);
+        let cache = SiteConfigCache::new_internal(Arc::new(source), RefreshConfig::default(), None, false)
+            .await
+            .unwrap();
+
+        assert_eq!(cache.site_id_for_domain("blog.example.net").as_deref(), Some("site-0"));
+        assert_eq!(cache.site_id_for_domain("example.com").as_deref(), Some("site-a"));
+    }
+
+    #[tokio::test]
+    async fn sites_are_found_by_measurement_id() {
+        let mut tagged = record("site-a", "example.com");
+        tagged.ga4_measurement_id = Some("G-ABC123".to_string());
+        let source = StaticSource(vec![tagged, record("site-b", "example.org")]);
+        let cache = SiteConfigCache::new_internal(Arc::new(source), RefreshConfig::default(), None, false)
+            .await
+            .unwrap();
+
+        assert_eq!(cache.site_id_for_measurement_id("g-abc123").as_deref(), Some("site-a"));
+        assert_eq!(cache.site_id_for_measurement_id("G-OTHER"), None);
+    }
+}
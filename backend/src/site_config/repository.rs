@@ -11,7 +11,31 @@ SELECT
     d."siteId" AS site_id,
     d."domain" AS domain,
     sc."blacklistedIps" AS blacklisted_ips,
+    sc."internalNetworks" AS internal_networks,
+    sc."internalTrafficToken" AS internal_traffic_token,
     sc."enforceDomain" AS enforce_domain,
+    sc."domainAliases" AS domain_aliases,
+    sc."allowedDomains" AS allowed_domains,
+    sc."allowSubdomains" AS allow_subdomains,
+    sc."domainEnforcementMode"::text AS domain_enforcement_mode,
+    sc."ingestApiKeyHashes" AS ingest_api_key_hashes,
+    sc."monthlyEventQuota" AS monthly_event_quota,
+    sc."eventsPerSecondLimit" AS events_per_second_limit,
+    sc."customEventSchemas" AS custom_event_schemas,
+    sc."customEventSchemaMode"::text AS custom_event_schema_mode,
+    sc."exclusionRules" AS exclusion_rules,
+    sc."campaignParamAliases" AS campaign_param_aliases,
+    sc."pathRules" AS path_rules,
+    sc."pathIdDetectors" AS path_id_detectors,
+    sc."keepRawPath" AS keep_raw_path,
+    sc."hashRouting" AS hash_routing,
+    sc."retainedQueryParams" AS retained_query_params,
+    sc."ga4MeasurementId" AS ga4_measurement_id,
+    sc."blockedCountries" AS blocked_countries,
+    sc."allowedCountries" AS allowed_countries,
+    sc."blockedAsns" AS blocked_asns,
+    sc."allowedAsns" AS allowed_asns,
+    sc."networkPolicyMode"::text AS network_policy_mode,
     sc."updatedAt" AS updated_at
 FROM "SiteConfig" sc
 INNER JOIN "Dashboard" d ON d."id" = sc."dashboardId"
@@ -24,7 +48,31 @@ pub struct SiteConfigRecord {
     pub site_id: String,
     pub domain: String,
     pub blacklisted_ips: Vec<String>,
+    pub internal_networks: Vec<String>,
+    pub internal_traffic_token: Option<String>,
     pub enforce_domain: bool,
+    pub domain_aliases: Vec<String>,
+    pub allowed_domains: Vec<String>,
+    pub allow_subdomains: bool,
+    pub domain_enforcement_mode: String,
+    pub ingest_api_key_hashes: Vec<String>,
+    pub monthly_event_quota: Option<i32>,
+    pub events_per_second_limit: Option<i32>,
+    pub custom_event_schemas: serde_json::Value,
+    pub custom_event_schema_mode: String,
+    pub exclusion_rules: serde_json::Value,
+    pub campaign_param_aliases: serde_json::Value,
+    pub path_rules: serde_json::Value,
+    pub path_id_detectors: Vec<String>,
+    pub keep_raw_path: bool,
+    pub hash_routing: bool,
+    pub retained_query_params: Vec<String>,
+    pub ga4_measurement_id: Option<String>,
+    pub blocked_countries: Vec<String>,
+    pub allowed_countries: Vec<String>,
+    pub blocked_asns: Vec<i64>,
+    pub allowed_asns: Vec<i64>,
+    pub network_policy_mode: String,
     pub updated_at: DateTime<Utc>,
 }
 
@@ -37,7 +85,31 @@ impl TryFrom<Row> for SiteConfigRecord {
             site_id: row.try_get("site_id")?,
             domain: row.try_get("domain")?,
             blacklisted_ips: row.try_get("blacklisted_ips")?,
+            internal_networks: row.try_get("internal_networks")?,
+            internal_traffic_token: row.try_get("internal_traffic_token")?,
             enforce_domain: row.try_get("enforce_domain")?,
+            domain_aliases: row.try_get("domain_aliases")?,
+            allowed_domains: row.try_get("allowed_domains")?,
+            allow_subdomains: row.try_get("allow_subdomains")?,
+            domain_enforcement_mode: row.try_get("domain_enforcement_mode")?,
+            ingest_api_key_hashes: row.try_get("ingest_api_key_hashes")?,
+            monthly_event_quota: row.try_get("monthly_event_quota")?,
+            events_per_second_limit: row.try_get("events_per_second_limit")?,
+            custom_event_schemas: row.try_get("custom_event_schemas")?,
+            custom_event_schema_mode: row.try_get("custom_event_schema_mode")?,
+            exclusion_rules: row.try_get("exclusion_rules")?,
+            campaign_param_aliases: row.try_get("campaign_param_aliases")?,
+            path_rules: row.try_get("path_rules")?,
+            path_id_detectors: row.try_get("path_id_detectors")?,
+            keep_raw_path: row.try_get("keep_raw_path")?,
+            hash_routing: row.try_get("hash_routing")?,
+            retained_query_params: row.try_get("retained_query_params")?,
+            ga4_measurement_id: row.try_get("ga4_measurement_id")?,
+            blocked_countries: row.try_get("blocked_countries")?,
+            allowed_countries: row.try_get("allowed_countries")?,
+            blocked_asns: row.try_get("blocked_asns")?,
+            allowed_asns: row.try_get("allowed_asns")?,
+            network_policy_mode: row.try_get("network_policy_mode")?,
             updated_at: DateTime::<Utc>::from_naive_utc_and_offset(updated_at, Utc),
         })
     }
@@ -2,6 +2,8 @@ use psl;
 use tracing::debug;
 use url::Url;
 
+pub mod path_rules;
+
 /// Extracts the root/registrable domain from a full domain string
 pub fn extract_root_domain(domain: &str) -> Option<String> {
     let domain_bytes = domain.as_bytes();
@@ -49,4 +51,108 @@ pub fn extract_domain_and_path_from_url(url_str: &str) -> (Option<String>, Strin
             }
         }
     }
-}
\ No newline at end of file
+}
+/// Per-site options for the parts of a URL, beyond its path, that are stored
+#[derive(Debug, Clone, Default)]
+pub struct PathOptions {
+    /// Treat route fragments (`#/settings`, `#!/settings`) as part of the path, for hash-routed SPAs
+    pub hash_routing: bool,
+    /// Query parameters kept in the stored URL, in this order
+    pub retained_query_params: Vec<String>,
+}
+
+impl PathOptions {
+    /// None when neither option is set, so sites without them skip the extra parsing
+    pub fn new(hash_routing: bool, retained_query_params: Vec<String>) -> Option<Self> {
+        let retained_query_params: Vec<String> = retained_query_params
+            .into_iter()
+            .map(|param| param.trim().to_string())
+            .filter(|param| !param.is_empty())
+            .collect();
+        (hash_routing || !retained_query_params.is_empty()).then_some(Self { hash_routing, retained_query_params })
+    }
+}
+
+/// Like `extract_domain_and_path_from_url`, with the route fragment folded into
+/// the path when hash routing is on. The third value is the retained query
+/// string (`?plan=pro`), or empty when no allowlisted parameter is present; it
+/// is kept apart so path rewrite rules only ever see the path.
+pub fn extract_url_parts(url_str: &str, options: &PathOptions) -> (Option<String>, String, String) {
+    let Ok(url) = Url::parse(url_str) else {
+        let (domain, path) = extract_domain_and_path_from_url(url_str);
+        return (domain, path, String::new());
+    };
+    let domain = url.domain().map(|d| d.to_string());
+    let mut path = if url.path().is_empty() { "/".to_string() } else { url.path().to_string() };
+
+    // Hash routers keep their own query string inside the fragment: `#/search?q=shoes`
+    let (route, route_query) = match url.fragment() {
+        Some(fragment) if options.hash_routing && (fragment.starts_with('/') || fragment.starts_with("!/")) => {
+            fragment.split_once('?').map_or((Some(fragment), None), |(route, query)| (Some(route), Some(query)))
+        }
+        _ => (None, None),
+    };
+    if let Some(route) = route {
+        path.push('#');
+        path.push_str(route);
+    }
+
+    let mut pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+    if let Some(query) = route_query {
+        pairs.extend(url::form_urlencoded::parse(query.as_bytes()).into_owned());
+    }
+    let mut serializer = url::form_urlencoded::Serializer::new(String::new());
+    let mut retained = false;
+    for param in &options.retained_query_params {
+        if let Some((key, value)) = pairs.iter().find(|(key, _)| key == param) {
+            serializer.append_pair(key, value);
+            retained = true;
+        }
+    }
+    let query = if retained { format!("?{}", serializer.finish()) } else { String::new() };
+
+    (domain, path, query)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(hash_routing: bool, params: &[&str]) -> PathOptions {
+        PathOptions::new(hash_routing, params.iter().map(|param| param.to_string()).collect()).unwrap()
+    }
+
+    #[test]
+    fn hash_routing_keeps_route_fragments_only() {
+        let options = options(true, &[]);
+        let (domain, path, query) = extract_url_parts("https://example.com/app/#/settings/profile", &options);
+        assert_eq!(domain.as_deref(), Some("example.com"));
+        assert_eq!(path, "/app/#/settings/profile");
+        assert_eq!(query, "");
+        assert_eq!(extract_url_parts("https://example.com/#!/inbox", &options).1, "/#!/inbox");
+        assert_eq!(extract_url_parts("https://example.com/docs#install", &options).1, "/docs");
+    }
+
+    #[test]
+    fn only_allowlisted_query_params_are_retained_in_order() {
+        let options = options(false, &["tab", "plan"]);
+        let (_, path, query) =
+            extract_url_parts("https://example.com/pricing?plan=pro&email=a%40b.c&tab=yearly#/x", &options);
+        assert_eq!(path, "/pricing");
+        assert_eq!(query, "?tab=yearly&plan=pro");
+        assert_eq!(extract_url_parts("https://example.com/pricing?email=a%40b.c", &options).2, "");
+    }
+
+    #[test]
+    fn fragment_query_counts_with_hash_routing() {
+        let options = options(true, &["q"]);
+        let (_, path, query) = extract_url_parts("https://example.com/#/search?q=red+shoes&page=2", &options);
+        assert_eq!(path, "/#/search");
+        assert_eq!(query, "?q=red+shoes");
+    }
+
+    #[test]
+    fn no_options_means_none() {
+        assert!(PathOptions::new(false, vec![" ".to_string()]).is_none());
+    }
+}
@@ -0,0 +1,198 @@
+//! Per-site rewriting of stored paths, so `/users/123/orders/456` is counted as
+//! one page. `SiteConfig.pathRules` holds regex rules, e.g.
+//! `[{"pattern": "^/users/[^/]+/orders/[^/]+$", "template": "/users/:id/orders/:id"}]`,
+//! and `pathIdDetectors` turns on built-in segment detectors. The first rule
+//! whose pattern matches rewrites the path (`$1` in the template refers to a
+//! capture group); the detectors then replace whole segments that look like IDs.
+
+use std::str::FromStr;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+
+static NUMERIC: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\d+$").expect("valid numeric regex"));
+static UUID: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(?i)[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}$").expect("valid uuid regex")
+});
+/// Hex digests and object IDs: 16+ hex characters with at least one digit
+static HASH: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(?i)[0-9a-f]{16,}$").expect("valid hash regex"));
+
+/// Built-in detector for ID-like path segments
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdDetector {
+    Numeric,
+    Uuid,
+    Hash,
+}
+
+impl IdDetector {
+    fn placeholder(self) -> &'static str {
+        match self {
+            Self::Numeric => ":id",
+            Self::Uuid => ":uuid",
+            Self::Hash => ":hash",
+        }
+    }
+
+    fn matches(self, segment: &str) -> bool {
+        match self {
+            Self::Numeric => NUMERIC.is_match(segment),
+            Self::Uuid => UUID.is_match(segment),
+            Self::Hash => HASH.is_match(segment) && segment.bytes().any(|b| b.is_ascii_digit()),
+        }
+    }
+}
+
+impl FromStr for IdDetector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "numeric" => Ok(Self::Numeric),
+            "uuid" => Ok(Self::Uuid),
+            "hash" => Ok(Self::Hash),
+            other => Err(format!("unknown path ID detector '{other}'")),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleSpec {
+    pattern: String,
+    template: String,
+}
+
+#[derive(Debug, Clone)]
+struct PathRule {
+    pattern: Regex,
+    template: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PathRewriter {
+    rules: Vec<PathRule>,
+    detectors: Vec<IdDetector>,
+    /// Store the path as received next to the rewritten one
+    pub keep_raw_path: bool,
+}
+
+impl PathRewriter {
+    /// Parses the stored rules and detector names. A malformed rule or unknown
+    /// detector is skipped, and reported in the returned errors, so the site
+    /// keeps the rest of its rewriting.
+    pub fn parse(rules: &Value, detectors: &[String], keep_raw_path: bool) -> (Self, Vec<String>) {
+        let mut errors = Vec::new();
+        let specs = match rules {
+            Value::Null => &[][..],
+            Value::Array(specs) => specs.as_slice(),
+            _ => {
+                errors.push("path rules must be an array".to_string());
+                &[][..]
+            }
+        };
+        let mut parsed = Vec::with_capacity(specs.len());
+        for (index, spec) in specs.iter().enumerate() {
+            let rule = RuleSpec::deserialize(spec).map_err(|e| e.to_string()).and_then(|spec| {
+                let pattern = Regex::new(&spec.pattern).map_err(|e| format!("invalid path pattern: {e}"))?;
+                Ok(PathRule { pattern, template: spec.template })
+            });
+            match rule {
+                Ok(rule) => parsed.push(rule),
+                Err(e) => errors.push(format!("rule {index}: {e}")),
+            }
+        }
+        let detectors = detectors
+            .iter()
+            .filter_map(|name| name.parse().map_err(|e| errors.push(e)).ok())
+            .collect();
+        (Self { rules: parsed, detectors, keep_raw_path }, errors)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty() && self.detectors.is_empty()
+    }
+
+    pub fn rewrite(&self, path: &str) -> String {
+        let path = match self.rules.iter().find(|rule| rule.pattern.is_match(path)) {
+            Some(rule) => rule.pattern.replace(path, rule.template.as_str()).into_owned(),
+            None => path.to_string(),
+        };
+        if self.detectors.is_empty() {
+            return path;
+        }
+        path.split('/')
+            .map(|segment| {
+                self.detectors
+                    .iter()
+                    .find(|detector| detector.matches(segment))
+                    .map_or(segment, |detector| detector.placeholder())
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn detectors(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn first_matching_rule_rewrites_the_path() {
+        let rewriter = PathRewriter::parse(
+            &json!([
+                { "pattern": "^/users/[^/]+/orders/[^/]+$", "template": "/users/:id/orders/:id" },
+                { "pattern": "^/blog/(\\d{4})/[^/]+$", "template": "/blog/$1/:slug" },
+                { "pattern": "^/users/", "template": "/never/" }
+            ]),
+            &[],
+            false,
+        )
+        .0;
+        assert_eq!(rewriter.rewrite("/users/alice/orders/456"), "/users/:id/orders/:id");
+        assert_eq!(rewriter.rewrite("/blog/2024/hello-world"), "/blog/2024/:slug");
+        assert_eq!(rewriter.rewrite("/pricing"), "/pricing");
+    }
+
+    #[test]
+    fn detectors_replace_id_segments() {
+        let rewriter = PathRewriter::parse(&Value::Null, &detectors(&["numeric", "uuid", "hash"]), false).0;
+        assert_eq!(rewriter.rewrite("/users/123/orders/456/"), "/users/:id/orders/:id/");
+        assert_eq!(rewriter.rewrite("/docs/550e8400-e29b-41d4-a716-446655440000"), "/docs/:uuid");
+        assert_eq!(rewriter.rewrite("/commit/9f86d081884c7d65"), "/commit/:hash");
+        assert_eq!(rewriter.rewrite("/deadbeefdeadbeef/v2"), "/deadbeefdeadbeef/v2");
+    }
+
+    #[test]
+    fn invalid_rules_and_detectors_are_rejected() {
+        let rejected = |rules: Value, names: &[&str]| {
+            let (rewriter, errors) = PathRewriter::parse(&rules, &detectors(names), false);
+            rewriter.is_empty() && errors.len() == 1
+        };
+        assert!(rejected(json!([{ "pattern": "(", "template": "/" }]), &[]));
+        assert!(rejected(json!([{ "pattern": "^/" }]), &[]));
+        assert!(rejected(Value::Null, &["slug"]));
+    }
+
+    #[test]
+    fn invalid_entries_do_not_drop_the_others() {
+        let (rewriter, errors) = PathRewriter::parse(
+            &json!([
+                { "pattern": "(", "template": "/" },
+                { "pattern": "^/blog/[^/]+$", "template": "/blog/:slug" }
+            ]),
+            &detectors(&["slug", "numeric"]),
+            false,
+        );
+        assert_eq!(errors.len(), 2);
+        assert_eq!(rewriter.rewrite("/blog/hello"), "/blog/:slug");
+        assert_eq!(rewriter.rewrite("/users/42"), "/users/:id");
+    }
+}
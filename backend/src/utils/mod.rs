@@ -1,3 +1,5 @@
+pub mod secrets;
 pub mod tasks;
 
+pub use secrets::constant_time_eq;
 pub use tasks::spawn_supervised;
@@ -0,0 +1,5 @@
+/// Compares two secrets without short-circuiting on the first differing byte,
+/// so response timing does not reveal how much of a guess was right.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
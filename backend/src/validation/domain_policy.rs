@@ -0,0 +1,115 @@
+//! Which hostnames may send events for a site. A site allows its `domain`, its
+//! `domainAliases` and the entries of `allowedDomains`, which may be plain
+//! hostnames or patterns with `*`: a leading `*.` matches any subdomain depth,
+//! any other `*` matches within one label (`staging-*.example.com`).
+//! `allowSubdomains` extends plain hostnames to their subdomains.
+
+use std::str::FromStr;
+
+use url::Url;
+
+use crate::site_config::cache::SiteConfig;
+
+/// What `enforceDomain` does with an event from a host the site does not allow
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DomainMode {
+    #[default]
+    Reject,
+    /// Keep the event; it is stored without a matched domain
+    Tag,
+}
+
+impl FromStr for DomainMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(Self::Reject),
+            "tag" => Ok(Self::Tag),
+            other => Err(format!("unknown domain enforcement mode '{other}'")),
+        }
+    }
+}
+
+/// Host of an event URL, lowercased; None when the URL has none
+pub fn event_host(event_url: &str) -> Option<String> {
+    Url::parse(event_url)
+        .ok()?
+        .host_str()
+        .map(|host| host.trim_end_matches('.').to_ascii_lowercase())
+        .filter(|host| !host.is_empty())
+}
+
+/// The first configured entry, as written in the site config, that allows `host`
+pub fn matched_domain(cfg: &SiteConfig, host: &str) -> Option<String> {
+    std::iter::once(&cfg.domain)
+        .chain(&cfg.domain_aliases)
+        .chain(&cfg.allowed_domains)
+        .map(|entry| entry.trim())
+        .filter(|entry| !entry.is_empty())
+        .find(|entry| host_matches(entry, host, cfg.allow_subdomains))
+        .map(str::to_string)
+}
+
+/// True when the site has nothing to match hosts against
+pub fn has_no_domains(cfg: &SiteConfig) -> bool {
+    std::iter::once(&cfg.domain)
+        .chain(&cfg.domain_aliases)
+        .chain(&cfg.allowed_domains)
+        .all(|entry| entry.trim().is_empty())
+}
+
+pub fn host_matches(pattern: &str, host: &str, allow_subdomains: bool) -> bool {
+    let pattern = pattern.to_ascii_lowercase();
+    if let Some(suffix) = pattern.strip_prefix("*.") {
+        return host
+            .strip_suffix(suffix)
+            .and_then(|rest| rest.strip_suffix('.'))
+            .is_some_and(|sub| !sub.is_empty());
+    }
+    if pattern.contains('*') {
+        return glob_matches(pattern.as_bytes(), host.as_bytes());
+    }
+    host == pattern || (allow_subdomains && host.ends_with(&format!(".{pattern}")))
+}
+
+/// `*` matches any run of characters other than `.`
+fn glob_matches(pattern: &[u8], host: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => host.is_empty(),
+        Some((b'*', rest)) => {
+            let label_end = host.iter().position(|&c| c == b'.').unwrap_or(host.len());
+            (0..=label_end).any(|skip| glob_matches(rest, &host[skip..]))
+        }
+        Some((&c, rest)) => host.first() == Some(&c) && glob_matches(rest, &host[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards_match_subdomains_and_single_labels() {
+        assert!(host_matches("*.example.com", "a.b.example.com", false));
+        assert!(!host_matches("*.example.com", "example.com", false));
+        assert!(!host_matches("*.example.com", "badexample.com", false));
+        assert!(host_matches("staging-*.example.com", "staging-eu.example.com", false));
+        assert!(!host_matches("staging-*.example.com", "staging-eu.x.example.com", false));
+        assert!(host_matches("example.*", "example.de", false));
+    }
+
+    #[test]
+    fn subdomains_of_plain_hosts_follow_the_toggle() {
+        assert!(host_matches("Example.com", "example.com", false));
+        assert!(host_matches("example.com", "shop.example.com", true));
+        assert!(!host_matches("example.com", "shop.example.com", false));
+        assert!(!host_matches("example.com", "notexample.com", true));
+    }
+
+    #[test]
+    fn hosts_are_read_from_event_urls() {
+        assert_eq!(event_host("https://WWW.Example.com:8443/a").as_deref(), Some("www.example.com"));
+        assert_eq!(event_host("not a url"), None);
+    }
+}
@@ -0,0 +1,187 @@
+//! Per-site rules for events that should never be stored, from
+//! `SiteConfig.exclusionRules`, e.g.
+//! `[{"path": "/admin/**"}, {"pathRegex": "^/preview/"}, {"event": "debug"},
+//! {"query": "preview", "value": "1"}]`. All conditions of a rule must match;
+//! an event is excluded when any rule matches it.
+//!
+//! In `path`, `*` matches within one path segment, `**` across segments and
+//! `?` a single character. `query` without `value` matches any value.
+
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+use url::Url;
+
+use crate::analytics::RawTrackingEvent;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct RuleSpec {
+    path: Option<String>,
+    path_regex: Option<String>,
+    event: Option<String>,
+    query: Option<String>,
+    value: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExclusionRule {
+    path: Option<Regex>,
+    event: Option<String>,
+    query: Option<(String, Option<String>)>,
+}
+
+impl ExclusionRule {
+    pub fn matches(&self, event: &RawTrackingEvent) -> bool {
+        if self.event.as_ref().is_some_and(|name| *name != event.event_name) {
+            return false;
+        }
+        if self.path.is_none() && self.query.is_none() {
+            return true;
+        }
+        let Ok(url) = Url::parse(&event.url) else {
+            return false;
+        };
+        if self.path.as_ref().is_some_and(|path| !path.is_match(url.path())) {
+            return false;
+        }
+        match &self.query {
+            None => true,
+            Some((key, value)) => url
+                .query_pairs()
+                .any(|(k, v)| k == key.as_str() && value.as_ref().is_none_or(|value| v == value.as_str())),
+        }
+    }
+}
+
+/// Parses the stored rules; null means the site has none. Each rule is parsed
+/// on its own, so a malformed one is skipped, and reported in the returned
+/// errors, without dropping the site's other rules. A rule without any
+/// condition would exclude everything and is rejected.
+pub fn parse_exclusion_rules(value: &Value) -> (Vec<ExclusionRule>, Vec<String>) {
+    let specs = match value {
+        Value::Null => return (Vec::new(), Vec::new()),
+        Value::Array(specs) => specs,
+        _ => return (Vec::new(), vec!["exclusion rules must be an array".to_string()]),
+    };
+    let mut rules = Vec::with_capacity(specs.len());
+    let mut errors = Vec::new();
+    for (index, spec) in specs.iter().enumerate() {
+        let rule = RuleSpec::deserialize(spec)
+            .map_err(|e| e.to_string())
+            .and_then(compile_rule);
+        match rule {
+            Ok(rule) => rules.push(rule),
+            Err(e) => errors.push(format!("rule {index}: {e}")),
+        }
+    }
+    (rules, errors)
+}
+
+fn compile_rule(spec: RuleSpec) -> Result<ExclusionRule, String> {
+    let path = match (spec.path, spec.path_regex) {
+        (Some(_), Some(_)) => return Err("a rule may set path or pathRegex, not both".to_string()),
+        (Some(glob), None) => Some(glob_to_regex(&glob)),
+        (None, Some(pattern)) => Some(pattern),
+        (None, None) => None,
+    };
+    let path = path
+        .map(|pattern| Regex::new(&pattern).map_err(|e| format!("invalid path pattern: {e}")))
+        .transpose()?;
+    if spec.value.is_some() && spec.query.is_none() {
+        return Err("value requires query".to_string());
+    }
+    if path.is_none() && spec.event.is_none() && spec.query.is_none() {
+        return Err("a rule needs at least one of path, pathRegex, event or query".to_string());
+    }
+    Ok(ExclusionRule {
+        path,
+        event: spec.event,
+        query: spec.query.map(|key| (key, spec.value)),
+    })
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut pattern = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                pattern.push_str(".*");
+            }
+            '*' => pattern.push_str("[^/]*"),
+            '?' => pattern.push_str("[^/]"),
+            other => pattern.push_str(&regex::escape(&other.to_string())),
+        }
+    }
+    pattern.push('$');
+    pattern
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn event(name: &str, url: &str) -> RawTrackingEvent {
+        serde_json::from_value(json!({
+            "site_id": "site", "event_name": name, "is_custom_event": false, "properties": "{}",
+            "url": url, "referrer": null, "user_agent": "", "screen_resolution": "",
+            "outbound_link_url": null, "cwv_cls": null, "cwv_lcp": null, "cwv_inp": null, "cwv_fcp": null,
+            "cwv_ttfb": null, "scroll_depth_percentage": null, "scroll_depth_pixels": null,
+            "error_exceptions": null, "page_duration_seconds": null
+        }))
+        .unwrap()
+    }
+
+    fn excluded(rules: Value, name: &str, url: &str) -> bool {
+        let (rules, errors) = parse_exclusion_rules(&rules);
+        assert!(errors.is_empty(), "{errors:?}");
+        let event = event(name, url);
+        rules.iter().any(|rule| rule.matches(&event))
+    }
+
+    #[test]
+    fn path_globs_and_regexes_match_the_url_path() {
+        let rules = json!([{ "path": "/admin/**" }, { "path": "/checkout/*/callback" }, { "pathRegex": "^/preview/\\d+$" }]);
+        assert!(excluded(rules.clone(), "pageview", "https://example.com/admin/users/1"));
+        assert!(excluded(rules.clone(), "pageview", "https://example.com/checkout/stripe/callback?x=1"));
+        assert!(!excluded(rules.clone(), "pageview", "https://example.com/checkout/a/b/callback"));
+        assert!(excluded(rules.clone(), "pageview", "https://example.com/preview/42"));
+        assert!(!excluded(rules, "pageview", "https://example.com/pricing"));
+    }
+
+    #[test]
+    fn conditions_in_one_rule_must_all_match() {
+        let rules = json!([{ "event": "pageview", "query": "preview", "value": "1" }, { "query": "draft" }]);
+        assert!(excluded(rules.clone(), "pageview", "https://example.com/?preview=1"));
+        assert!(!excluded(rules.clone(), "signup", "https://example.com/?preview=1"));
+        assert!(!excluded(rules.clone(), "pageview", "https://example.com/?preview=0"));
+        assert!(excluded(rules, "signup", "https://example.com/?draft"));
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        let rejected = |rules: Value| {
+            let (rules, errors) = parse_exclusion_rules(&rules);
+            rules.is_empty() && errors.len() == 1
+        };
+        assert!(rejected(json!([{}])));
+        assert!(rejected(json!([{ "pathRegex": "(" }])));
+        assert!(rejected(json!([{ "value": "1" }])));
+        assert!(rejected(json!([{ "paths": "/admin" }])));
+        assert!(rejected(json!({ "path": "/admin" })));
+    }
+
+    #[test]
+    fn invalid_rule_does_not_drop_the_others() {
+        let (rules, errors) = parse_exclusion_rules(&json!([{ "pathRegex": "(" }, { "path": "/admin/**" }]));
+        assert_eq!(rules.len(), 1);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("rule 0:"));
+
+        let event = event("pageview", "https://example.com/admin/users");
+        assert!(rules.iter().any(|rule| rule.matches(&event)));
+    }
+}
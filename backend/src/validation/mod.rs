@@ -4,10 +4,21 @@ use ipnet::IpNet;
 use std::str::FromStr;
 use url::Url;
 use crate::analytics::RawTrackingEvent;
+use crate::quota::{self, QuotaExceeded, QuotaLimits};
 use crate::site_config::SiteConfigCache;
+use crate::site_config::cache::SiteConfig;
+use crate::utils::constant_time_eq;
 use sha2::{Digest, Sha256};
 use tracing::warn;
 
+pub mod domain_policy;
+pub mod exclusion;
+pub mod network_policy;
+pub mod property_schema;
+
+use domain_policy::DomainMode;
+use property_schema::{SchemaMode, Violation, ViolationKind};
+
 #[derive(Debug, Clone)]
 pub struct ValidationConfig {
     pub max_custom_properties_size: usize,
@@ -16,6 +27,8 @@ pub struct ValidationConfig {
     pub max_site_id_length: usize,
     pub max_user_agent_length: usize,
     pub max_error_exceptions_size: usize,
+    /// Find the site from the event URL's host when the event has no site ID
+    pub resolve_site_from_host: bool,
 }
 
 impl Default for ValidationConfig {
@@ -27,6 +40,7 @@ impl Default for ValidationConfig {
             max_site_id_length: 100,                  // Site ID is usually short, but we should keep leeway for extra long domain names
             max_user_agent_length: 8 * 1024,          // 8192 bytes - same limit that apache uses (https://httpd.apache.org/docs/2.2/mod/core.html#limitrequestfieldsize)
             max_error_exceptions_size: 16 * 1024,     // 16KB - client caps stack at 10KB + type/value/mechanism overhead
+            resolve_site_from_host: false,
         }
     }
 }
@@ -57,6 +71,50 @@ pub enum ValidationError {
     InvalidScrollDepth(String),
     #[error("Invalid page duration: {0}")]
     InvalidPageDuration(String),
+    #[error("Invalid API key: {0}")]
+    InvalidApiKey(String),
+    #[error("Invalid timestamp: {0}")]
+    InvalidTimestamp(String),
+    #[error("Over quota: {0}")]
+    OverQuota(String),
+    #[error("Rate limited: {0}")]
+    RateLimited(String),
+    #[error("Schema violation: {0}")]
+    SchemaViolation(String),
+    #[error("Decompressed body too large: {0}")]
+    DecompressedBodyTooLarge(String),
+    #[error("Unknown site host: {0}")]
+    UnknownSiteHost(String),
+    #[error("Excluded by rule: {0}")]
+    ExcludedByRule(String),
+}
+
+impl ValidationError {
+    /// Label for the `events_rejected` metric
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::InvalidSiteId(_) => "invalid_site_id",
+            Self::InvalidUrl(_) => "invalid_url",
+            Self::InvalidEventName(_) => "invalid_event_name",
+            Self::InvalidIpAddress(_) => "invalid_ip_address",
+            Self::InvalidUserAgent(_) => "invalid_user_agent",
+            Self::PayloadTooLarge(_) => "payload_too_large",
+            Self::InvalidJson(_) => "invalid_json",
+            Self::InvalidOutboundLinkUrl(_) => "invalid_outbound_link_url",
+            Self::BlacklistedIp(_) => "blacklisted_ip",
+            Self::DomainNotAllowed(_) => "domain_not_allowed",
+            Self::InvalidScrollDepth(_) => "invalid_scroll_depth",
+            Self::InvalidPageDuration(_) => "invalid_page_duration",
+            Self::InvalidApiKey(_) => "invalid_api_key",
+            Self::InvalidTimestamp(_) => "invalid_timestamp",
+            Self::OverQuota(_) => "over_quota",
+            Self::RateLimited(_) => "rate_limited",
+            Self::SchemaViolation(_) => "schema_violation",
+            Self::DecompressedBodyTooLarge(_) => "decompressed_body_too_large",
+            Self::UnknownSiteHost(_) => "unknown_site_host",
+            Self::ExcludedByRule(_) => "excluded_by_rule",
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -74,6 +132,10 @@ impl EventValidator {
         Self { config }
     }
 
+    pub fn resolves_site_from_host(&self) -> bool {
+        self.config.resolve_site_from_host
+    }
+
     pub async fn validate_event(
         &self,
         raw_event: RawTrackingEvent,
@@ -132,6 +194,47 @@ impl EventValidator {
         })
     }
 
+    /// Checks a custom event's properties against the site's schema for its
+    /// event name. Events without a schema have no violations.
+    pub fn property_schema_violations(&self, cfg: &SiteConfig, raw_event: &RawTrackingEvent) -> Vec<Violation> {
+        if !raw_event.is_custom_event {
+            return Vec::new();
+        }
+        let Some(schema) = cfg.property_schemas.get(&raw_event.event_name) else {
+            return Vec::new();
+        };
+        schema.check(&properties_object(&raw_event.properties))
+    }
+
+    /// Applies the site's schema mode to the violations: reject the event,
+    /// strip the offending properties, or leave the event as it is for tagging.
+    pub fn enforce_property_schema(
+        &self,
+        mode: SchemaMode,
+        raw_event: &mut RawTrackingEvent,
+        violations: &[Violation],
+    ) -> Result<(), ValidationError> {
+        if violations.is_empty() {
+            return Ok(());
+        }
+        match mode {
+            SchemaMode::Reject => Err(ValidationError::SchemaViolation(format!(
+                "Properties do not match the schema for this event: {}",
+                violations.iter().map(Violation::to_string).collect::<Vec<_>>().join(", ")
+            ))),
+            SchemaMode::Strip => {
+                // A missing required key cannot be stripped; the event is kept without it
+                let mut properties = properties_object(&raw_event.properties);
+                for violation in violations.iter().filter(|v| v.kind != ViolationKind::Missing) {
+                    properties.remove(&violation.key);
+                }
+                raw_event.properties = serde_json::Value::Object(properties).to_string();
+                Ok(())
+            }
+            SchemaMode::Tag => Ok(()),
+        }
+    }
+
     /// Validate required fields are not empty and don't contain control characters
     fn validate_required_fields(&self, raw_event: &RawTrackingEvent) -> Result<(), ValidationError> {
         if raw_event.site_id.is_empty() {
@@ -349,20 +452,7 @@ impl EventValidator {
     }
 
     pub fn get_rejection_reason(&self, error: &ValidationError) -> &'static str {
-        match error {
-            ValidationError::InvalidSiteId(_) => "invalid_site_id",
-            ValidationError::InvalidUrl(_) => "invalid_url",
-            ValidationError::InvalidEventName(_) => "invalid_event_name",
-            ValidationError::InvalidIpAddress(_) => "invalid_ip_address",
-            ValidationError::InvalidUserAgent(_) => "invalid_user_agent",
-            ValidationError::PayloadTooLarge(_) => "payload_too_large",
-            ValidationError::InvalidJson(_) => "invalid_json",
-            ValidationError::InvalidOutboundLinkUrl(_) => "invalid_outbound_link_url",
-            ValidationError::BlacklistedIp(_) => "blacklisted_ip",
-            ValidationError::DomainNotAllowed(_) => "domain_not_allowed",
-            ValidationError::InvalidScrollDepth(_) => "invalid_scroll_depth",
-            ValidationError::InvalidPageDuration(_) => "invalid_page_duration",
-        }
+        error.reason()
     }
 
     fn hash_ip_address(&self, ip: &str) -> String {
@@ -416,81 +506,127 @@ pub fn check_blacklist(
     let event_ip: IpAddr = IpAddr::from_str(ip_address)
         .map_err(|_| ValidationError::InvalidIpAddress("Invalid IP address format".to_string()))?;
 
-    let is_blocked = blacklisted_ips.iter().any(|entry| {
-        if let Ok(ip) = IpAddr::from_str(entry) {
-            ip == event_ip
+    if ip_listed(event_ip, blacklisted_ips) {
+        Err(ValidationError::BlacklistedIp("IP is blacklisted".to_string()))
+    } else {
+        Ok(())
+    }
+}
+
+/// True when `ip` equals one of the entries or falls in one of their CIDR ranges
+fn ip_listed(ip: IpAddr, entries: &[String]) -> bool {
+    entries.iter().any(|entry| {
+        if let Ok(entry_ip) = IpAddr::from_str(entry) {
+            entry_ip == ip
         } else if let Ok(net) = entry.parse::<IpNet>() {
-            net.contains(&event_ip)
+            net.contains(&ip)
         } else {
             false
         }
-    });
+    })
+}
 
-    if is_blocked {
-        Err(ValidationError::BlacklistedIp("IP is blacklisted".to_string()))
-    } else {
-        Ok(())
-    }
+/// Internal traffic comes from one of the site's internal networks or carries
+/// its internal traffic token; it is stored and flagged rather than rejected
+pub fn is_internal_traffic(cfg: &SiteConfig, ip_address: &str, token: Option<&str>) -> bool {
+    let token_matches = match (&cfg.internal_traffic_token, token) {
+        (Some(expected), Some(token)) => constant_time_eq(expected.as_bytes(), token.as_bytes()),
+        _ => false,
+    };
+    token_matches
+        || IpAddr::from_str(ip_address).is_ok_and(|ip| ip_listed(ip, &cfg.internal_networks))
 }
 
-/// Validate site-specific policies using the in-memory site-config cache
+/// Validate site-specific policies using the in-memory site-config cache.
+/// Returns the configured domain entry the event's host matched, if any.
 pub async fn validate_site_policies(
     cfg_cache: &SiteConfigCache,
     site_id: &str,
     event_url: &str,
     ip_address: &str,
-) -> Result<(), ValidationError> {
-    match cfg_cache.get(site_id) {
-        Some(cfg) => {
-            if let Err(e) = check_blacklist(ip_address, &cfg.blacklisted_ips) {
-                return Err(e);
-            }
-            if cfg.enforce_domain {
-                let expected = cfg.domain.trim();
-                if expected.is_empty() {
-                    warn!(
-                        site_id = %site_id,
-                        "site-config has enforce_domain=true but empty domain; rejecting event as misconfigured"
-                    );
-                    return Err(ValidationError::DomainNotAllowed(
-                        "Site config domain is misconfigured".to_string(),
-                    ));
-                } else if let Err(e) = check_domain_allowed(expected, event_url, true) {
-                    return Err(e);
-                }
-            }
-            Ok(())
+) -> Result<Option<String>, ValidationError> {
+    let Some(cfg) = cfg_cache.get(site_id) else {
+        return Err(ValidationError::InvalidSiteId(
+            "SiteID not recognized or missing".to_string(),
+        ));
+    };
+    check_blacklist(ip_address, &cfg.blacklisted_ips)?;
+
+    // Unparsable URLs are left to URL validation
+    let Some(host) = domain_policy::event_host(event_url) else {
+        return Ok(None);
+    };
+    let matched = domain_policy::matched_domain(&cfg, &host);
+    if cfg.enforce_domain && matched.is_none() && cfg.domain_mode == DomainMode::Reject {
+        if domain_policy::has_no_domains(&cfg) {
+            warn!(
+                site_id = %site_id,
+                "site-config has enforce_domain=true but no domains; rejecting event as misconfigured"
+            );
+            return Err(ValidationError::DomainNotAllowed(
+                "Site config domain is misconfigured".to_string(),
+            ));
         }
-        None => Err(ValidationError::InvalidSiteId(
-                "SiteID not recognized or missing".to_string(),
-            )),
+        return Err(ValidationError::DomainNotAllowed(
+            "Domain does not match site config".to_string(),
+        ));
     }
+    Ok(matched)
 }
 
-/// Check if the event URL's domain satisfies the expected domain policy.
-/// When `allow_subdomains` is true, subdomains of `expected_domain` are also allowed.
-pub fn check_domain_allowed(
-    expected_domain: &str,
-    event_url: &str,
-    allow_subdomains: bool,
-) -> Result<(), ValidationError> {
-    if let Ok(url) = Url::parse(event_url) {
-        let event_domain_lc = url.host_str().unwrap_or("").to_ascii_lowercase();
-        let expected_lc = expected_domain.to_ascii_lowercase();
+/// Rejects events matching one of the site's exclusion rules
+pub fn check_exclusion_rules(cfg_cache: &SiteConfigCache, raw_event: &RawTrackingEvent) -> Result<(), ValidationError> {
+    let Some(cfg) = cfg_cache.get(&raw_event.site_id) else {
+        return Ok(());
+    };
+    match cfg.exclusion_rules.iter().position(|rule| rule.matches(raw_event)) {
+        Some(index) => Err(ValidationError::ExcludedByRule(format!("Event matches exclusion rule {index}"))),
+        None => Ok(()),
+    }
+}
 
-        let allowed = if allow_subdomains {
-            event_domain_lc == expected_lc || event_domain_lc.ends_with(&format!(".{}", expected_lc))
-        } else {
-            event_domain_lc == expected_lc
-        };
+/// Site ID for an event sent without one, from its URL's host and the
+/// configured site domains and aliases
+pub fn resolve_site_from_host(cfg_cache: &SiteConfigCache, event_url: &str) -> Result<String, ValidationError> {
+    let host = Url::parse(event_url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .filter(|host| !host.is_empty())
+        .ok_or_else(|| ValidationError::UnknownSiteHost("Event has no site ID and its URL has no host".to_string()))?;
+
+    cfg_cache
+        .site_id_for_domain(&host)
+        .ok_or_else(|| ValidationError::UnknownSiteHost(format!("No site is configured for host '{host}'")))
+}
 
-        if !allowed {
-            return Err(ValidationError::DomainNotAllowed(
-                "Domain does not match site config".to_string(),
-            ));
-        }
+/// Counts the event against the site's quota and burst limit. Run after every
+/// other ingest check, so events rejected here never use up quota; the
+/// processor refunds events it drops later (bot detection, network policy, a
+/// full channel or spool).
+pub fn check_site_quota(
+    cfg_cache: &SiteConfigCache,
+    raw_event: &RawTrackingEvent,
+) -> Result<(), ValidationError> {
+    let Some(cfg) = cfg_cache.get(&raw_event.site_id) else {
+        return Ok(());
+    };
+    let limits = QuotaLimits {
+        monthly: cfg.monthly_event_quota,
+        per_second: cfg.events_per_second_limit,
+    };
+    let billable = quota::is_billable(&raw_event.event_name, raw_event.is_custom_event);
+    quota::admit(&raw_event.site_id, limits, billable, chrono::Utc::now()).map_err(|exceeded| match exceeded {
+        QuotaExceeded::Monthly => ValidationError::OverQuota("Monthly event quota exceeded".to_string()),
+        QuotaExceeded::Burst => ValidationError::RateLimited("Events per second limit exceeded".to_string()),
+    })
+}
+
+/// Custom event properties as a JSON object; anything else counts as no properties
+fn properties_object(properties: &str) -> serde_json::Map<String, serde_json::Value> {
+    match serde_json::from_str(properties) {
+        Ok(serde_json::Value::Object(map)) => map,
+        _ => serde_json::Map::new(),
     }
-    Ok(())
 }
 
 /// Check if a string contains any control characters
@@ -555,6 +691,22 @@ mod tests {
         }
     }
 
+    #[test]
+    fn strip_mode_drops_only_offending_properties() {
+        let mut event = raw_event("signup", true);
+        event.properties = r#"{"plan":"gold","seats":3}"#.to_string();
+        let violations = vec![
+            Violation { key: "plan".to_string(), kind: ViolationKind::NotAllowed },
+            Violation { key: "email".to_string(), kind: ViolationKind::Missing },
+        ];
+
+        validator().enforce_property_schema(SchemaMode::Strip, &mut event, &violations).unwrap();
+        assert_eq!(event.properties, r#"{"seats":3}"#);
+
+        let result = validator().enforce_property_schema(SchemaMode::Reject, &mut event, &violations);
+        assert!(matches!(result, Err(ValidationError::SchemaViolation(_))));
+    }
+
     #[test]
     fn custom_events_may_use_any_name() {
         let result = validator().validate_event_internal(&raw_event("my_signup_funnel", true), "127.0.0.1");
@@ -0,0 +1,142 @@
+//! Per-site country and ASN rules from `SiteConfig.blockedCountries`,
+//! `allowedCountries`, `blockedAsns` and `allowedAsns`. They need the GeoIP and
+//! ASN lookups, so the processor applies them after enrichment rather than
+//! request validation.
+//!
+//! An allow list only applies once the lookup produced a value: events whose
+//! country or ASN is unknown pass it, so a missing GeoIP or ASN database does
+//! not take a site offline.
+
+use std::str::FromStr;
+
+pub const REASON_COUNTRY_BLOCKED: &str = "country_blocked";
+pub const REASON_COUNTRY_NOT_ALLOWED: &str = "country_not_allowed";
+pub const REASON_ASN_BLOCKED: &str = "asn_blocked";
+pub const REASON_ASN_NOT_ALLOWED: &str = "asn_not_allowed";
+
+/// What happens to an event the network policy blocks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NetworkMode {
+    /// Drop the event and record it to bot_events
+    #[default]
+    Enforce,
+    /// Keep the event; only record it to bot_events
+    Shadow,
+}
+
+impl FromStr for NetworkMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "enforce" => Ok(Self::Enforce),
+            "shadow" => Ok(Self::Shadow),
+            other => Err(format!("unknown network policy mode '{other}'")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct NetworkPolicy {
+    /// Uppercase ISO 3166-1 alpha-2 codes
+    pub blocked_countries: Vec<String>,
+    pub allowed_countries: Vec<String>,
+    pub blocked_asns: Vec<u32>,
+    pub allowed_asns: Vec<u32>,
+    pub mode: NetworkMode,
+}
+
+impl NetworkPolicy {
+    /// None when no list is set, so sites without rules skip the check
+    pub fn new(
+        blocked_countries: Vec<String>,
+        allowed_countries: Vec<String>,
+        blocked_asns: Vec<i64>,
+        allowed_asns: Vec<i64>,
+        mode: NetworkMode,
+    ) -> Option<Self> {
+        let countries = |codes: Vec<String>| -> Vec<String> {
+            codes
+                .iter()
+                .map(|code| code.trim().to_ascii_uppercase())
+                .filter(|code| !code.is_empty())
+                .collect()
+        };
+        let asns = |numbers: Vec<i64>| -> Vec<u32> {
+            numbers.into_iter().filter_map(|asn| u32::try_from(asn).ok()).filter(|asn| *asn != 0).collect()
+        };
+        let policy = Self {
+            blocked_countries: countries(blocked_countries),
+            allowed_countries: countries(allowed_countries),
+            blocked_asns: asns(blocked_asns),
+            allowed_asns: asns(allowed_asns),
+            mode,
+        };
+        let is_empty = policy.blocked_countries.is_empty()
+            && policy.allowed_countries.is_empty()
+            && policy.blocked_asns.is_empty()
+            && policy.allowed_asns.is_empty();
+        (!is_empty).then_some(policy)
+    }
+
+    /// The reason an event from `country_code` and `asn` is blocked, if it is.
+    /// `asn` 0 means unknown.
+    pub fn violation(&self, country_code: Option<&str>, asn: u32) -> Option<&'static str> {
+        if let Some(country) = country_code.filter(|code| !code.is_empty()) {
+            let listed = |codes: &[String]| codes.iter().any(|code| code.eq_ignore_ascii_case(country));
+            if listed(&self.blocked_countries) {
+                return Some(REASON_COUNTRY_BLOCKED);
+            }
+            if !self.allowed_countries.is_empty() && !listed(&self.allowed_countries) {
+                return Some(REASON_COUNTRY_NOT_ALLOWED);
+            }
+        }
+        if asn != 0 {
+            if self.blocked_asns.contains(&asn) {
+                return Some(REASON_ASN_BLOCKED);
+            }
+            if !self.allowed_asns.is_empty() && !self.allowed_asns.contains(&asn) {
+                return Some(REASON_ASN_NOT_ALLOWED);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(blocked_countries: &[&str], allowed_countries: &[&str], blocked_asns: &[i64], allowed_asns: &[i64]) -> NetworkPolicy {
+        let codes = |codes: &[&str]| codes.iter().map(|code| code.to_string()).collect();
+        NetworkPolicy::new(
+            codes(blocked_countries),
+            codes(allowed_countries),
+            blocked_asns.to_vec(),
+            allowed_asns.to_vec(),
+            NetworkMode::Enforce,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn block_lists_win_over_allow_lists() {
+        let policy = policy(&["ru"], &["RU", "DE"], &[64496], &[64496, 64497]);
+        assert_eq!(policy.violation(Some("RU"), 64497), Some(REASON_COUNTRY_BLOCKED));
+        assert_eq!(policy.violation(Some("DE"), 64496), Some(REASON_ASN_BLOCKED));
+        assert_eq!(policy.violation(Some("DE"), 64497), None);
+    }
+
+    #[test]
+    fn allow_lists_reject_other_known_values_only() {
+        let policy = policy(&[], &["DE"], &[], &[64497]);
+        assert_eq!(policy.violation(Some("US"), 64497), Some(REASON_COUNTRY_NOT_ALLOWED));
+        assert_eq!(policy.violation(Some("de"), 64500), Some(REASON_ASN_NOT_ALLOWED));
+        assert_eq!(policy.violation(None, 0), None);
+    }
+
+    #[test]
+    fn empty_lists_mean_no_policy() {
+        assert!(NetworkPolicy::new(vec![" ".to_string()], Vec::new(), vec![0, -1], Vec::new(), NetworkMode::Shadow).is_none());
+    }
+}
@@ -0,0 +1,198 @@
+//! Per-site schemas for custom event properties, keyed on the custom event
+//! name. Stored as JSON in `SiteConfig.customEventSchemas`, e.g.
+//! `{"signup": {"properties": {"plan": {"type": "string", "required": true,
+//! "enum": ["free", "pro"]}}}}`. Keys without a rule are left alone.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+/// What to do with an event whose properties break its schema
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SchemaMode {
+    /// Reject the event
+    #[default]
+    Reject,
+    /// Drop the offending properties and keep the event
+    Strip,
+    /// Keep the event unchanged and record the violations alongside it
+    Tag,
+}
+
+impl FromStr for SchemaMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(Self::Reject),
+            "strip" => Ok(Self::Strip),
+            "tag" => Ok(Self::Tag),
+            other => Err(format!("unknown property schema mode '{other}'")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PropertyType {
+    String,
+    Number,
+    Integer,
+    Boolean,
+}
+
+impl PropertyType {
+    fn matches(self, value: &Value) -> bool {
+        match self {
+            Self::String => value.is_string(),
+            Self::Number => value.is_number(),
+            Self::Integer => value.is_i64() || value.is_u64(),
+            Self::Boolean => value.is_boolean(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PropertyRule {
+    #[serde(rename = "type")]
+    pub kind: Option<PropertyType>,
+    #[serde(default)]
+    pub required: bool,
+    #[serde(rename = "enum")]
+    pub allowed: Option<Vec<Value>>,
+    /// Maximum length in characters, for string values
+    pub max_length: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EventSchema {
+    #[serde(default)]
+    pub properties: HashMap<String, PropertyRule>,
+}
+
+/// Schemas by custom event name
+pub type PropertySchemas = HashMap<String, EventSchema>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViolationKind {
+    Missing,
+    WrongType,
+    NotAllowed,
+    TooLong,
+}
+
+impl ViolationKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Missing => "missing",
+            Self::WrongType => "wrong_type",
+            Self::NotAllowed => "not_allowed",
+            Self::TooLong => "too_long",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub key: String,
+    pub kind: ViolationKind,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.key, self.kind.as_str())
+    }
+}
+
+impl EventSchema {
+    /// Violations in key order, so tags and messages are stable
+    pub fn check(&self, properties: &Map<String, Value>) -> Vec<Violation> {
+        let mut keys: Vec<&String> = self.properties.keys().collect();
+        keys.sort();
+
+        keys.into_iter()
+            .filter_map(|key| {
+                let rule = &self.properties[key];
+                let kind = match properties.get(key) {
+                    None | Some(Value::Null) => rule.required.then_some(ViolationKind::Missing)?,
+                    Some(value) => rule_violation(rule, value)?,
+                };
+                Some(Violation { key: key.clone(), kind })
+            })
+            .collect()
+    }
+}
+
+fn rule_violation(rule: &PropertyRule, value: &Value) -> Option<ViolationKind> {
+    if rule.kind.is_some_and(|kind| !kind.matches(value)) {
+        return Some(ViolationKind::WrongType);
+    }
+    if rule.allowed.as_ref().is_some_and(|allowed| !allowed.contains(value)) {
+        return Some(ViolationKind::NotAllowed);
+    }
+    if let (Some(max), Some(s)) = (rule.max_length, value.as_str())
+        && s.chars().count() > max
+    {
+        return Some(ViolationKind::TooLong);
+    }
+    None
+}
+
+/// Parses the stored schema JSON; null means the site has no schemas
+pub fn parse_schemas(value: &Value) -> Result<PropertySchemas, serde_json::Error> {
+    if value.is_null() {
+        return Ok(PropertySchemas::new());
+    }
+    serde_json::from_value(value.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn schema() -> EventSchema {
+        let schemas = parse_schemas(&json!({
+            "signup": {
+                "properties": {
+                    "plan": { "type": "string", "required": true, "enum": ["free", "pro"] },
+                    "seats": { "type": "integer" },
+                    "note": { "maxLength": 5 }
+                }
+            }
+        }))
+        .unwrap();
+        schemas["signup"].clone()
+    }
+
+    fn check(properties: Value) -> Vec<String> {
+        schema()
+            .check(properties.as_object().unwrap())
+            .iter()
+            .map(Violation::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn conforming_properties_pass() {
+        assert!(check(json!({ "plan": "pro", "seats": 3, "note": "hi", "extra": true })).is_empty());
+    }
+
+    #[test]
+    fn each_rule_reports_its_violation() {
+        assert_eq!(check(json!({})), vec!["plan:missing"]);
+        assert_eq!(
+            check(json!({ "plan": "gold", "seats": 1.5, "note": "too long" })),
+            vec!["note:too_long", "plan:not_allowed", "seats:wrong_type"]
+        );
+    }
+
+    #[test]
+    fn unknown_mode_is_rejected() {
+        assert_eq!("strip".parse::<SchemaMode>(), Ok(SchemaMode::Strip));
+        assert!("drop".parse::<SchemaMode>().is_err());
+    }
+}
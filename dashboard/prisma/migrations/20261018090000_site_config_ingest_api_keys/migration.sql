@@ -0,0 +1,2 @@
+-- AlterTable
+ALTER TABLE "SiteConfig" ADD COLUMN     "ingestApiKeyHashes" TEXT[] DEFAULT ARRAY[]::TEXT[];
@@ -0,0 +1,3 @@
+-- AlterTable
+ALTER TABLE "SiteConfig" ADD COLUMN     "eventsPerSecondLimit" INTEGER,
+ADD COLUMN     "monthlyEventQuota" INTEGER;
@@ -0,0 +1,6 @@
+-- CreateEnum
+CREATE TYPE "CustomEventSchemaMode" AS ENUM ('reject', 'strip', 'tag');
+
+-- AlterTable
+ALTER TABLE "SiteConfig" ADD COLUMN     "customEventSchemaMode" "CustomEventSchemaMode" NOT NULL DEFAULT 'reject',
+ADD COLUMN     "customEventSchemas" JSONB NOT NULL DEFAULT '{}';
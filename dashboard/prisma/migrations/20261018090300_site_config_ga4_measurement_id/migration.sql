@@ -0,0 +1,5 @@
+-- AlterTable
+ALTER TABLE "SiteConfig" ADD COLUMN     "ga4MeasurementId" TEXT;
+
+-- CreateIndex
+CREATE UNIQUE INDEX "SiteConfig_ga4MeasurementId_key" ON "SiteConfig"("ga4MeasurementId");
@@ -0,0 +1,2 @@
+-- AlterTable
+ALTER TABLE "SiteConfig" ADD COLUMN     "domainAliases" TEXT[] DEFAULT ARRAY[]::TEXT[];
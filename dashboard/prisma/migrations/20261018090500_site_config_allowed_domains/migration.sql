@@ -0,0 +1,7 @@
+-- CreateEnum
+CREATE TYPE "DomainEnforcementMode" AS ENUM ('reject', 'tag');
+
+-- AlterTable
+ALTER TABLE "SiteConfig" ADD COLUMN     "allowSubdomains" BOOLEAN NOT NULL DEFAULT true,
+ADD COLUMN     "allowedDomains" TEXT[] DEFAULT ARRAY[]::TEXT[],
+ADD COLUMN     "domainEnforcementMode" "DomainEnforcementMode" NOT NULL DEFAULT 'reject';
@@ -0,0 +1,2 @@
+-- AlterTable
+ALTER TABLE "SiteConfig" ADD COLUMN     "exclusionRules" JSONB NOT NULL DEFAULT '[]';
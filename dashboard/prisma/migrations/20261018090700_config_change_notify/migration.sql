@@ -0,0 +1,26 @@
+-- Announce config changes to the analytics backend caches, which LISTEN on
+-- these channels and refresh immediately instead of waiting for their next poll.
+-- Payload: {"table": "<table>", "op": "INSERT" | "UPDATE" | "DELETE"}
+CREATE OR REPLACE FUNCTION "notify_config_change"() RETURNS trigger AS $$
+BEGIN
+  PERFORM pg_notify(TG_ARGV[0], json_build_object('table', TG_TABLE_NAME, 'op', TG_OP)::text);
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER "SiteConfig_notify_change"
+AFTER INSERT OR UPDATE OR DELETE ON "SiteConfig"
+FOR EACH STATEMENT EXECUTE FUNCTION "notify_config_change"('site_config_changed');
+
+-- Site ID and domain live on the dashboard
+CREATE TRIGGER "Dashboard_notify_site_config_change"
+AFTER INSERT OR UPDATE OF "siteId", "domain" OR DELETE ON "Dashboard"
+FOR EACH STATEMENT EXECUTE FUNCTION "notify_config_change"('site_config_changed');
+
+CREATE TRIGGER "MonitorCheck_notify_change"
+AFTER INSERT OR UPDATE OR DELETE ON "MonitorCheck"
+FOR EACH STATEMENT EXECUTE FUNCTION "notify_config_change"('monitor_check_changed');
+
+CREATE TRIGGER "DashboardIntegration_notify_change"
+AFTER INSERT OR UPDATE OR DELETE ON "DashboardIntegration"
+FOR EACH STATEMENT EXECUTE FUNCTION "notify_config_change"('integration_changed');
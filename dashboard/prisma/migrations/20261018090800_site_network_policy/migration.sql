@@ -0,0 +1,9 @@
+-- CreateEnum
+CREATE TYPE "NetworkPolicyMode" AS ENUM ('enforce', 'shadow');
+
+-- AlterTable
+ALTER TABLE "SiteConfig" ADD COLUMN     "allowedAsns" INTEGER[] DEFAULT ARRAY[]::INTEGER[],
+ADD COLUMN     "allowedCountries" TEXT[] DEFAULT ARRAY[]::TEXT[],
+ADD COLUMN     "blockedAsns" INTEGER[] DEFAULT ARRAY[]::INTEGER[],
+ADD COLUMN     "blockedCountries" TEXT[] DEFAULT ARRAY[]::TEXT[],
+ADD COLUMN     "networkPolicyMode" "NetworkPolicyMode" NOT NULL DEFAULT 'enforce';
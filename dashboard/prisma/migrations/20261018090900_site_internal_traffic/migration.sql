@@ -0,0 +1,3 @@
+-- AlterTable
+ALTER TABLE "SiteConfig" ADD COLUMN     "internalNetworks" TEXT[] DEFAULT ARRAY[]::TEXT[],
+ADD COLUMN     "internalTrafficToken" TEXT;
@@ -0,0 +1,2 @@
+-- AlterTable
+ALTER TABLE "SiteConfig" ADD COLUMN     "campaignParamAliases" JSONB NOT NULL DEFAULT '{}';
@@ -0,0 +1,4 @@
+-- AlterTable
+ALTER TABLE "SiteConfig" ADD COLUMN     "keepRawPath" BOOLEAN NOT NULL DEFAULT false,
+ADD COLUMN     "pathIdDetectors" TEXT[] DEFAULT ARRAY[]::TEXT[],
+ADD COLUMN     "pathRules" JSONB NOT NULL DEFAULT '[]';
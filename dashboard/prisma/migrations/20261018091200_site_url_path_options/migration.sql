@@ -0,0 +1,3 @@
+-- AlterTable
+ALTER TABLE "SiteConfig" ADD COLUMN     "hashRouting" BOOLEAN NOT NULL DEFAULT false,
+ADD COLUMN     "retainedQueryParams" TEXT[] DEFAULT ARRAY[]::TEXT[];
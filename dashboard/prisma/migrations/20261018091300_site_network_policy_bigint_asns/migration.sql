@@ -0,0 +1,5 @@
+-- AlterTable
+ALTER TABLE "SiteConfig" ALTER COLUMN "allowedAsns" SET DATA TYPE BIGINT[],
+ALTER COLUMN "allowedAsns" SET DEFAULT ARRAY[]::BIGINT[],
+ALTER COLUMN "blockedAsns" SET DATA TYPE BIGINT[],
+ALTER COLUMN "blockedAsns" SET DEFAULT ARRAY[]::BIGINT[];
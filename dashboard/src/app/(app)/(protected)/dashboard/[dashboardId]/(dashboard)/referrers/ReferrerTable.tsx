@@ -20,6 +20,7 @@ export const ReferrerTab = {
   Social: 'social',
   Direct: 'direct',
   Email: 'email',
+  Ai: 'ai',
   Other: 'other',
 } as const;
 
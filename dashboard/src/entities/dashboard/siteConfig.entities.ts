@@ -2,15 +2,132 @@ import { z } from 'zod';
 
 export const DEFAULT_SITE_CONFIG_VALUES: Omit<SiteConfig, 'id' | 'dashboardId' | 'createdAt' | 'updatedAt'> = {
   blacklistedIps: [],
+  internalNetworks: [],
+  internalTrafficToken: null,
   enforceDomain: false,
+  domainAliases: [],
+  allowedDomains: [],
+  allowSubdomains: true,
+  domainEnforcementMode: 'reject',
+  ingestApiKeyHashes: [],
+  monthlyEventQuota: null,
+  eventsPerSecondLimit: null,
+  customEventSchemas: {},
+  customEventSchemaMode: 'reject',
+  exclusionRules: [],
+  campaignParamAliases: {},
+  pathRules: [],
+  pathIdDetectors: [],
+  keepRawPath: false,
+  hashRouting: false,
+  retainedQueryParams: [],
+  ga4MeasurementId: null,
+  blockedCountries: [],
+  allowedCountries: [],
+  blockedAsns: [],
+  allowedAsns: [],
+  networkPolicyMode: 'enforce',
 };
 
+const PropertyRuleSchema = z
+  .object({
+    type: z.enum(['string', 'number', 'integer', 'boolean']).optional(),
+    required: z.boolean().optional(),
+    enum: z.array(z.union([z.string(), z.number(), z.boolean()])).optional(),
+    maxLength: z.number().int().positive().optional(),
+  })
+  .strict();
+
+export const CustomEventSchemasSchema = z.record(
+  z.string(),
+  z.object({ properties: z.record(z.string(), PropertyRuleSchema) }).strict(),
+);
+
+/**
+ * Best-effort check that a pattern compiles under the Rust `regex` crate the backend uses.
+ * Rust has no lookaround or backreferences; its inline flags and `(?P<name>` groups are
+ * rewritten before compiling the rest as a JavaScript RegExp.
+ */
+function isBackendRegex(pattern: string): boolean {
+  if (/\(\?<?[=!]|\\[1-9]|\\k</.test(pattern)) {
+    return false;
+  }
+  const jsPattern = pattern
+    .replace(/\(\?P</g, '(?<')
+    .replace(/\(\?[imsURux-]+\)/g, '')
+    .replace(/\(\?[imsURux-]+:/g, '(?:');
+  try {
+    new RegExp(jsPattern);
+    return true;
+  } catch {
+    return false;
+  }
+}
+
+const BackendRegexSchema = z.string().min(1).refine(isBackendRegex, { message: 'Invalid regular expression' });
+
+export const ExclusionRuleSchema = z
+  .object({
+    path: z.string().min(1).optional(),
+    pathRegex: BackendRegexSchema.optional(),
+    event: z.string().min(1).optional(),
+    query: z.string().min(1).optional(),
+    value: z.string().optional(),
+  })
+  .strict()
+  .refine((rule) => rule.path || rule.pathRegex || rule.event || rule.query, {
+    message: 'A rule needs at least one of path, pathRegex, event or query',
+  })
+  .refine((rule) => !(rule.path && rule.pathRegex), { message: 'Use either path or pathRegex' })
+  .refine((rule) => rule.value === undefined || rule.query, { message: 'value requires query' });
+
+const CountryCodeSchema = z.string().regex(/^[A-Z]{2}$/, 'Use ISO 3166-1 alpha-2 country codes');
+
+// Prisma reads the BigInt[] columns as bigint; ASNs are 32-bit
+const AsnSchema = z.coerce.number().int().positive().max(4294967295);
+
+export const CampaignParamAliasesSchema = z.record(
+  z.string().min(1),
+  z.enum(['utm_source', 'utm_medium', 'utm_campaign', 'utm_term', 'utm_content', 'utm_id', 'utm_source_platform']),
+);
+
+export const PathRuleSchema = z
+  .object({
+    pattern: BackendRegexSchema,
+    template: z.string().startsWith('/'),
+  })
+  .strict();
+
 export const SiteConfigSchema = z
   .object({
     id: z.string(),
     dashboardId: z.string(),
     blacklistedIps: z.array(z.string()),
+    internalNetworks: z.array(z.string()),
+    internalTrafficToken: z.string().min(16).nullable(),
     enforceDomain: z.boolean(),
+    domainAliases: z.array(z.string()),
+    allowedDomains: z.array(z.string()),
+    allowSubdomains: z.boolean(),
+    domainEnforcementMode: z.enum(['reject', 'tag']),
+    ingestApiKeyHashes: z.array(z.string()),
+    monthlyEventQuota: z.number().int().positive().nullable(),
+    eventsPerSecondLimit: z.number().int().positive().nullable(),
+    customEventSchemas: CustomEventSchemasSchema,
+    customEventSchemaMode: z.enum(['reject', 'strip', 'tag']),
+    exclusionRules: z.array(ExclusionRuleSchema),
+    campaignParamAliases: CampaignParamAliasesSchema,
+    pathRules: z.array(PathRuleSchema),
+    pathIdDetectors: z.array(z.enum(['numeric', 'uuid', 'hash'])),
+    keepRawPath: z.boolean(),
+    hashRouting: z.boolean(),
+    retainedQueryParams: z.array(z.string().min(1)),
+    ga4MeasurementId: z.string().nullable(),
+    blockedCountries: z.array(CountryCodeSchema),
+    allowedCountries: z.array(CountryCodeSchema),
+    blockedAsns: z.array(AsnSchema),
+    allowedAsns: z.array(AsnSchema),
+    networkPolicyMode: z.enum(['enforce', 'shadow']),
     createdAt: z.date(),
     updatedAt: z.date(),
   })
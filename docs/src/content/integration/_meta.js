@@ -3,6 +3,7 @@ const meta = {
   "custom-events": "Custom Events",
   "global-properties": "Global Properties",
   "dynamic-urls": "Dynamic URLs",
+  "single-page-apps": "Single-Page Apps",
   "outbound-links": "Outbound Links",
   "web-vitals": "Web Vitals",
   "session-replay": "Session Replay",
@@ -0,0 +1,3 @@
+-- "key:kind" entries for custom event properties that broke the site's schema
+-- while it is in tag mode; empty for every other event.
+ALTER TABLE analytics.events ADD COLUMN IF NOT EXISTS property_schema_violations Array(LowCardinality(String)) DEFAULT [];
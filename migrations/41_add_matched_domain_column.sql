@@ -0,0 +1,3 @@
+-- Site domain, alias or allowed-domain pattern the event's host matched; empty
+-- when none did (kept by sites whose domain enforcement is in tag mode).
+ALTER TABLE analytics.events ADD COLUMN IF NOT EXISTS matched_domain LowCardinality(String) DEFAULT '';
@@ -0,0 +1,4 @@
+-- Event came from one of the site's internal networks or carried its internal
+-- traffic token. Such events are stored, so tracking can be verified end to
+-- end, and dashboards filter them out.
+ALTER TABLE analytics.events ADD COLUMN IF NOT EXISTS is_internal Bool DEFAULT false;
@@ -0,0 +1,6 @@
+-- Ad network whose click ID (gclid, fbclid, msclkid, ...) the landing URL
+-- carried; the ID itself is not stored. utm_id and utm_source_platform are the
+-- GA4 campaign parameters beyond the classic five.
+ALTER TABLE analytics.events ADD COLUMN IF NOT EXISTS utm_id String DEFAULT '';
+ALTER TABLE analytics.events ADD COLUMN IF NOT EXISTS utm_source_platform LowCardinality(String) DEFAULT '';
+ALTER TABLE analytics.events ADD COLUMN IF NOT EXISTS click_id_network LowCardinality(String) DEFAULT '';
@@ -0,0 +1,4 @@
+-- GA4 default channel group ("Organic Search", "Paid Social", ...) assigned at
+-- ingest from the referrer, UTM parameters and click IDs; empty for events
+-- stored before the column existed.
+ALTER TABLE analytics.events ADD COLUMN IF NOT EXISTS channel LowCardinality(String) DEFAULT '';
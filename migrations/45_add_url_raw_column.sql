@@ -0,0 +1,3 @@
+-- Path as received, for sites whose path rewrite rules keep it next to the
+-- rewritten `url`; empty otherwise.
+ALTER TABLE analytics.events ADD COLUMN IF NOT EXISTS url_raw String DEFAULT '';
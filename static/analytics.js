@@ -39,6 +39,21 @@
 
   var coreWebVitals = script.getAttribute("data-web-vitals") === "true";
 
+  // Hash-routed SPAs (#/settings): fragment changes count as page views.
+  // Set together with the site's "hash routing" setting.
+  var hashRouting = script.getAttribute("data-hash-routing") === "true";
+
+  // Query-routed pages (/product?id=42): a change in one of these parameters
+  // counts as a page view. Set to the site's "retained query parameters".
+  var retainedQueryParams = (
+    script.getAttribute("data-retained-query-params") || ""
+  )
+    .split(",")
+    .map(function (name) {
+      return name.trim();
+    })
+    .filter(Boolean);
+
   var enableReplay = script.getAttribute("data-replay") === "true";
   var consentReplay = script.getAttribute("data-consent-replay") === "true";
 
@@ -73,8 +88,23 @@
     return console.error("Betterlytics: data-site-id attribute missing");
   }
 
+  function routePath() {
+    var path = window.location.pathname;
+    if (retainedQueryParams.length) {
+      var params = new URLSearchParams(window.location.search);
+      path +=
+        "?" +
+        retainedQueryParams
+          .map(function (name) {
+            return name + "=" + (params.get(name) ?? "");
+          })
+          .join("&");
+    }
+    return hashRouting ? path + window.location.hash : path;
+  }
+
   // Store current URL for SPA navigation
-  var currentPath = window.location.pathname;
+  var currentPath = routePath();
 
   var globalProperties = {};
 
@@ -381,23 +411,28 @@
     history.pushState = function () {
       flushEngagement(); // Flush before URL changes (uses current URL)
       originalPushState.apply(this, arguments); // URL changes here
-      if (currentPath !== window.location.pathname) {
-        currentPath = window.location.pathname;
+      if (currentPath !== routePath()) {
+        currentPath = routePath();
         resetEngagement();
         monitorContentHeight();
         sendEvent("pageview");
       }
     };
 
-    window.addEventListener("popstate", function () {
-      if (currentPath !== window.location.pathname) {
-        currentPath = window.location.pathname;
+    function onHistoryNavigation() {
+      if (currentPath !== routePath()) {
+        currentPath = routePath();
         flushEngagement(currentUrl); // Pass old URL as override
         resetEngagement();
         monitorContentHeight();
         sendEvent("pageview");
       }
-    });
+    }
+
+    window.addEventListener("popstate", onHistoryNavigation);
+    if (hashRouting) {
+      window.addEventListener("hashchange", onHistoryNavigation);
+    }
   }
 
   function parseOutboundLink(link) {